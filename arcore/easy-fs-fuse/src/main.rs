@@ -1,9 +1,17 @@
 use clap::{App, Arg};
-use easy_fs::{BlockDevice, EasyFileSystem};
+#[cfg(test)]
+use easy_fs::Inode;
+use easy_fs::{
+    block_size, max_file_size, set_clock, BlockDevice, EasyFileSystem, RenameMode,
+    SYMLINK_INLINE_LIMIT,
+};
+#[cfg(test)]
+use std::collections::BTreeMap;
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const BLOCK_SZ: usize = 512;
 
@@ -25,7 +33,43 @@ impl BlockDevice for BlockFile {
     }
 }
 
+/// A block file losing power at a block write, after which writes are lost
+#[cfg(test)]
+struct CutBlockFile(BlockFile, std::sync::atomic::AtomicUsize);
+
+#[cfg(test)]
+impl BlockDevice for CutBlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.0.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        use std::sync::atomic::Ordering;
+        // the writes left before the cut, plus one
+        match self.1.load(Ordering::Relaxed) {
+            0 => {}
+            1 => {
+                self.1.store(0, Ordering::Relaxed);
+                panic!("power cut");
+            }
+            left => {
+                self.1.store(left - 1, Ordering::Relaxed);
+                self.0.write_block(block_id, buf);
+            }
+        }
+    }
+}
+
+/// Nanoseconds since the epoch, the clock of easy-fs
+fn host_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as u64
+}
+
 fn main() {
+    set_clock(host_clock);
     easy_fs_pack().expect("Error when packing easy-fs!");
 }
 
@@ -45,9 +89,27 @@ fn easy_fs_pack() -> std::io::Result<()> {
                 .takes_value(true)
                 .help("Executable target dir(with backslash)"),
         )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .help("Size of the image in MiB, 16 by default"),
+        )
+        .arg(
+            Arg::with_name("block-size")
+                .long("block-size")
+                .takes_value(true)
+                .help("Block size of the file system: 512, 1024, 2048 or 4096, 512 by default"),
+        )
         .get_matches();
     let src_path = matches.value_of("source").unwrap();
     let target_path = matches.value_of("target").unwrap();
+    let size: u32 = matches
+        .value_of("size")
+        .map_or(16, |size| size.parse().expect("Invalid image size!"));
+    let block_size: u32 = matches.value_of("block-size").map_or(512, |block_size| {
+        block_size.parse().expect("Invalid block size!")
+    });
     println!("src_path = {}\ntarget_path = {}", src_path, target_path);
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
@@ -55,11 +117,16 @@ fn easy_fs_pack() -> std::io::Result<()> {
             .write(true)
             .create(true)
             .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(16 * 2048 * 512).unwrap();
+        f.set_len(size as u64 * 2048 * 512).unwrap();
         f
     })));
-    // 16MiB, at most 4095 files
-    let efs = EasyFileSystem::create(block_file, 16 * 2048, 1);
+    // one block of inode bitmap, at most 4095 files with 512-byte blocks
+    let efs = EasyFileSystem::create(
+        block_file,
+        size * 1024 * 1024 / block_size,
+        1,
+        block_size as usize,
+    );
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
@@ -76,7 +143,7 @@ fn easy_fs_pack() -> std::io::Result<()> {
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
         // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
+        let inode = root_inode.create_as(app.as_str(), 0o755, (0, 0)).unwrap();
         // write data to easy-fs
         inode.write_at(0, all_data.as_slice());
     }
@@ -87,8 +154,211 @@ fn easy_fs_pack() -> std::io::Result<()> {
     Ok(())
 }
 
+/// Cut the power at every few block writes of some operations, and check
+/// that the image opened again is consistent
+#[cfg(test)]
+fn efs_power_cut_test() -> std::io::Result<()> {
+    let image = |path: &str| -> std::io::Result<BlockFile> {
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        f.set_len(4096 * 512)?;
+        Ok(BlockFile(Mutex::new(f)))
+    };
+    let block_file = Arc::new(image("target/fs_template.img")?);
+    let efs = EasyFileSystem::create(block_file, 4096, 1, BLOCK_SZ);
+    let root_inode = EasyFileSystem::root_inode(&efs);
+    let data: Vec<u8> = (0..150 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    root_inode
+        .create("old")
+        .unwrap()
+        .write_at(0, &data[..100 * BLOCK_SZ]);
+    drop(root_inode);
+    let operations = |root_inode: &Inode| {
+        let dir = root_inode.create_dir("dir").unwrap();
+        dir.create("file").unwrap().write_at(0, &data);
+        dir.create_symlink("link", "file").unwrap();
+        root_inode.create_dir("sub").unwrap();
+        // replacing a file frees it, exchanging moves a directory
+        assert!(root_inode.rename("old", &dir, "file", RenameMode::Replace));
+        assert!(dir.rename("link", root_inode, "sub", RenameMode::Exchange));
+        assert!(root_inode.unlink("sub"));
+        assert!(dir.find("file").unwrap().truncate(20 * BLOCK_SZ + 1));
+    };
+    for cut in (0..).step_by(5) {
+        std::fs::copy("target/fs_template.img", "target/fs_cut.img")?;
+        let block_file = CutBlockFile(image("target/fs_cut.img")?, (cut + 1).into());
+        // quiet about the cut only, the checks below report their failures
+        let hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(|_| {}));
+        let done = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let efs = EasyFileSystem::open(Arc::new(block_file));
+            operations(&EasyFileSystem::root_inode(&efs));
+        }))
+        .is_ok();
+        std::panic::set_hook(hook);
+        let block_file: Arc<dyn BlockDevice> = Arc::new(image("target/fs_cut.img")?);
+        let efs = EasyFileSystem::open(block_file.clone());
+        // the inodes in use are those reachable, with a link per name, and
+        // the data blocks in use are those of the inodes
+        let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+        let mut inodes = BTreeMap::new();
+        inodes.insert(root_inode.inode_id(), (root_inode.clone(), 2));
+        let mut dirs = vec![root_inode];
+        while let Some(dir) = dirs.pop() {
+            for name in dir.ls() {
+                // no spare entry of an exchange is left
+                assert!(!name.contains('/'), "cut at write {}", cut);
+                let inode = dir.find(&name).unwrap();
+                let links = match inode.is_dir() {
+                    true => {
+                        inodes.get_mut(&dir.inode_id()).unwrap().1 += 1;
+                        dirs.push(inode.clone());
+                        2
+                    }
+                    false => 1,
+                };
+                inodes.entry(inode.inode_id()).or_insert((inode, 0)).1 += links;
+            }
+        }
+        for (inode, links) in inodes.values() {
+            assert_eq!(inode.nlink(), *links, "cut at write {}", cut);
+        }
+        let blocks: u32 = inodes.values().map(|(inode, _)| inode.blocks()).sum();
+        let fs = efs.lock();
+        let used_inodes: Vec<u32> = (0..fs.inode_bitmap.maximum())
+            .filter(|bit| fs.inode_bitmap.is_allocated(&block_file, *bit))
+            .map(|bit| bit as u32)
+            .collect();
+        assert!(used_inodes.iter().eq(inodes.keys()), "cut at write {}", cut);
+        let used_blocks = (0..fs.data_bitmap.maximum())
+            .filter(|bit| fs.data_bitmap.is_allocated(&block_file, *bit))
+            .count();
+        assert_eq!(used_blocks, blocks as usize, "cut at write {}", cut);
+        if done {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Write an image of layout version 1 by hand, as the packer of that time
+/// did: 128-byte inodes, a superblock without version, and a flat root
+/// without `.`, `..` or link counts. Open it and check the migration.
+#[cfg(test)]
+fn efs_migrate_test() -> std::io::Result<()> {
+    let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new({
+        let f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open("target/fs_v1.img")?;
+        f.set_len(4096 * 512).unwrap();
+        f
+    })));
+    let write_words = |block_id: usize, offset: usize, words: &[u32]| {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id, &mut block);
+        for (i, word) in words.iter().enumerate() {
+            block[offset + i * 4..offset + i * 4 + 4].copy_from_slice(&word.to_le_bytes());
+        }
+        block_file.write_block(block_id, &block);
+    };
+    let write_bytes = |block_id: usize, offset: usize, bytes: &[u8]| {
+        let mut block = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id, &mut block);
+        block[offset..offset + bytes.len()].copy_from_slice(bytes);
+        block_file.write_block(block_id, &block);
+    };
+    // one inode bitmap block, 4096 inodes of 128 bytes, one data bitmap block
+    let (inode_area_blocks, data_bitmap_block) = (1024, 2 + 1024);
+    let data_area_start = data_bitmap_block + 1;
+    let magic = 0x3b800001;
+    write_words(0, 0, &[magic, 4096, 1, inode_area_blocks, 1, 4096 - 1027]);
+    // the root and two files, in two data blocks
+    write_words(1, 0, &[0b111]);
+    write_words(data_bitmap_block, 0, &[0b11]);
+    let inode = |inode_id: usize, size: u32, block: u32, is_dir: bool| {
+        let (block_id, offset) = (2 + inode_id * 128 / BLOCK_SZ, inode_id * 128 % BLOCK_SZ);
+        write_words(block_id, offset, &[size, block]);
+        // the type follows the 28 direct and 2 indirect block ids
+        write_bytes(block_id, offset + 124, &[is_dir as u8]);
+    };
+    inode(0, 64, data_area_start as u32, true);
+    inode(1, 13, data_area_start as u32 + 1, false);
+    inode(2, 0, 0, false);
+    for (i, (name, inode_id)) in [("filea", 1), ("fileb", 2)].iter().enumerate() {
+        write_bytes(data_area_start, i * 32, name.as_bytes());
+        write_words(data_area_start, i * 32 + 28, &[*inode_id]);
+    }
+    write_bytes(data_area_start + 1, 0, b"Hello, world!");
+
+    let efs = EasyFileSystem::open(block_file);
+    let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
+    assert!(root_inode.ls() == ["filea", "fileb"]);
+    assert_eq!(root_inode.find("..").unwrap().inode_id(), 0);
+    let filea = root_inode.find("filea").unwrap();
+    let mut buffer = [0u8; 32];
+    let len = filea.read_at(0, &mut buffer);
+    assert_eq!(&buffer[..len], b"Hello, world!");
+    assert_eq!((root_inode.nlink(), filea.nlink()), (2, 1));
+    assert_eq!((root_inode.mode(), filea.mode()), (0o755, 0o755));
+    assert_eq!(filea.owner(), (0, 0));
+    assert!(filea.times().1 > 0);
+    let fileb = root_inode.find("fileb").unwrap();
+    assert_eq!((fileb.size(), fileb.nlink()), (0, 1));
+    // the inodes past the ones that fit at 256 bytes are never handed out
+    let dir = root_inode.create_dir("dir").unwrap();
+    assert_eq!(dir.inode_id(), 3);
+    assert_eq!(dir.find("..").unwrap().inode_id(), 0);
+    assert_eq!(root_inode.nlink(), 3);
+    Ok(())
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
+    set_clock(host_clock);
+    // larger blocks, on an image of 16MiB, before the rest with 512-byte blocks
+    for size in [1024, 2048, 4096] {
+        let block_file = Arc::new(BlockFile(Mutex::new({
+            let f = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open("target/fs_blocks.img")?;
+            f.set_len(16 * 2048 * 512).unwrap();
+            f
+        })));
+        EasyFileSystem::create(block_file.clone(), (16 * 2048 * 512 / size) as u32, 1, size);
+        let efs = EasyFileSystem::open(block_file);
+        assert_eq!(block_size(), size);
+        let root_inode = EasyFileSystem::root_inode(&efs);
+        let file = root_inode.create("file").unwrap();
+        let data: Vec<u8> = (0..30 * size).map(|i| (i % 251) as u8).collect();
+        assert_eq!(file.write_at(size / 2, &data), data.len());
+        // 28 direct blocks and 3 under indirect1
+        assert_eq!(file.blocks(), 31 + 1);
+        let mut read_back = vec![0u8; data.len()];
+        assert_eq!(file.read_at(size / 2, &mut read_back), data.len());
+        assert!(read_back == data);
+        // the first block under indirect2, with an indirect1 block of its own
+        let offset = (28 + size / 4) * size;
+        file.write_at(offset, b"Hello");
+        assert_eq!(file.blocks(), 32 + 3);
+        assert_eq!(file.seek_data(31 * size), Some(offset));
+        assert!(file.truncate(0));
+        assert_eq!(file.blocks(), 0);
+        assert!(root_inode.unlink("file"));
+    }
+    efs_power_cut_test()?;
+    efs_migrate_test()?;
+
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
@@ -98,7 +368,7 @@ fn efs_test() -> std::io::Result<()> {
         f.set_len(8192 * 512).unwrap();
         f
     })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    EasyFileSystem::create(block_file.clone(), 8192, 1, BLOCK_SZ);
     let efs = EasyFileSystem::open(block_file.clone());
     let root_inode = EasyFileSystem::root_inode(&efs);
     root_inode.create("filea");
@@ -146,6 +416,238 @@ fn efs_test() -> std::io::Result<()> {
     random_str_test(400 * BLOCK_SZ);
     random_str_test(1000 * BLOCK_SZ);
     random_str_test(2000 * BLOCK_SZ);
+    assert_eq!(filea.size() as usize, 2000 * BLOCK_SZ);
+    // indirect1, indirect2 and the 15 indirect1 blocks under it come on top of the data
+    assert_eq!(filea.blocks(), 2000 + 1 + 1 + 15);
+
+    // reads commit their access times, however many files they touch
+    let many = root_inode.create_dir("many").unwrap();
+    for i in 0..400 {
+        let file = many.create(&format!("file{}", i)).unwrap();
+        file.write_at(0, greet_str.as_bytes());
+    }
+    for i in 0..400 {
+        let file = many.find(&format!("file{}", i)).unwrap();
+        assert_eq!(file.read_at(0, &mut buffer), greet_str.len());
+    }
+    assert_eq!(filea.write_at(0, b"x"), 1);
+    for i in 0..400 {
+        assert!(many.unlink(&format!("file{}", i)));
+    }
+    drop(many);
+    assert!(root_inode.unlink("many"));
+
+    // holes and truncate
+    let sparse = root_inode.create("sparse").unwrap();
+    sparse.write_at(100 * BLOCK_SZ, greet_str.as_bytes());
+    sparse.write_at(1000 * BLOCK_SZ, greet_str.as_bytes());
+    assert_eq!(sparse.size() as usize, 1000 * BLOCK_SZ + greet_str.len());
+    // the two data blocks, indirect1, indirect2 and one indirect1 under it
+    assert_eq!(sparse.blocks(), 2 + 1 + 2);
+    let mut block = [1u8; BLOCK_SZ];
+    assert_eq!(sparse.read_at(500 * BLOCK_SZ, &mut block), BLOCK_SZ);
+    assert!(block.iter().all(|byte| *byte == 0));
+    assert_eq!(sparse.seek_data(0), Some(100 * BLOCK_SZ));
+    assert_eq!(
+        sparse.seek_data(100 * BLOCK_SZ + 3),
+        Some(100 * BLOCK_SZ + 3)
+    );
+    assert_eq!(sparse.seek_hole(0), Some(0));
+    assert_eq!(sparse.seek_hole(100 * BLOCK_SZ), Some(101 * BLOCK_SZ));
+    assert_eq!(
+        sparse.seek_hole(1000 * BLOCK_SZ),
+        Some(sparse.size() as usize)
+    );
+    assert_eq!(sparse.seek_data(sparse.size() as usize), None);
+    // shrinking frees the blocks past the end and zeroes the rest of the last one
+    assert!(sparse.truncate(100 * BLOCK_SZ + 5));
+    assert_eq!(sparse.blocks(), 2);
+    assert!(sparse.truncate(200 * BLOCK_SZ));
+    let len = sparse.read_at(100 * BLOCK_SZ, &mut buffer);
+    assert_eq!(len, buffer.len());
+    assert_eq!(&buffer[..5], b"Hello");
+    assert!(buffer[5..].iter().all(|byte| *byte == 0));
+    assert!(sparse.truncate(10 * BLOCK_SZ));
+    assert_eq!(
+        (sparse.size() as usize, sparse.blocks()),
+        (10 * BLOCK_SZ, 0)
+    );
+    // past indirect2, the data goes under indirect3
+    sparse.write_at(20000 * BLOCK_SZ, greet_str.as_bytes());
+    // the data block, indirect3 and an indirect2 and indirect1 under it
+    assert_eq!(sparse.blocks(), 1 + 3);
+    assert_eq!(sparse.seek_data(0), Some(20000 * BLOCK_SZ));
+    let len = sparse.read_at(20000 * BLOCK_SZ, &mut buffer);
+    assert_eq!(&buffer[..len], greet_str.as_bytes());
+    assert!(sparse.truncate(10 * BLOCK_SZ));
+    assert_eq!(sparse.blocks(), 0);
+    assert!(!sparse.truncate(max_file_size() + 1));
+    // nothing is written at or past the max file size
+    assert_eq!(sparse.write_at(max_file_size(), b"x"), 0);
+    assert_eq!(sparse.write_at(usize::MAX, b"x"), 0);
+    assert_eq!(sparse.size() as usize, 10 * BLOCK_SZ);
+    assert!(root_inode.unlink("sparse"));
+
+    // directories
+    let root_inode = Arc::new(root_inode);
+    let dira = root_inode.create_dir("dira").unwrap();
+    assert!(dira.is_dir());
+    assert!(root_inode.create_dir("dira").is_none());
+    let dirb = dira.create_dir("dirb").unwrap();
+    dirb.create("filec").unwrap();
+    assert!(dira.ls() == ["dirb"]);
+    let filec = root_inode.find_path("/dira/./dirb/filec").unwrap();
+    filec.write_at(0, greet_str.as_bytes());
+    let len = root_inode
+        .find_path("dira/dirb/../dirb/filec")
+        .unwrap()
+        .read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    assert_eq!(
+        dirb.find_path("../..").unwrap().inode_id(),
+        root_inode.inode_id()
+    );
+    assert_eq!(
+        root_inode.find_path("..").unwrap().inode_id(),
+        root_inode.inode_id()
+    );
+    assert!(root_inode.find_path("filea/x").is_none());
+    assert!(filec.create("x").is_none());
+
+    // metadata
+    assert_eq!((filec.mode(), dira.mode()), (0o644, 0o755));
+    let (atime, mtime, ctime) = filec.times();
+    assert!(mtime > 0 && ctime >= mtime && atime >= mtime);
+    // the mode and owner of a new inode are written with it
+    let owned = dira.create_dir_as("owned", 0o700, (1000, 100)).unwrap();
+    assert_eq!((owned.mode(), owned.owner()), (0o700, (1000, 100)));
+    let link = owned.create_symlink_as("link", "x", (1000, 100)).unwrap();
+    assert_eq!((link.mode(), link.owner()), (0o777, (1000, 100)));
+    assert!(owned.unlink("link"));
+    assert!(dira.unlink("owned"));
+    filec.set_mode(0o4750);
+    filec.set_owner(1000, 100);
+    assert_eq!((filec.mode(), filec.owner()), (0o4750, (1000, 100)));
+    filec.set_times(Some(1), None);
+    assert_eq!(filec.times().0, 1);
+    assert_eq!(filec.times().1, mtime);
+    assert!(filec.times().2 >= ctime);
+    // an access time in the future is newer than the last change, and left alone
+    let future = host_clock() + 1_000_000_000_000;
+    filec.set_times(Some(future), None);
+    filec.read_at(0, &mut buffer);
+    assert_eq!(filec.times().0, future);
+
+    // links
+    assert_eq!(root_inode.nlink(), 3);
+    assert!(dira.link("linkc", &filec));
+    assert!(!dira.link("dirb", &filec));
+    assert!(!root_inode.link("dirc", &dirb));
+    assert_eq!(filec.nlink(), 2);
+    let len = dira.find("linkc").unwrap().read_at(0, &mut buffer);
+    assert_eq!(greet_str, core::str::from_utf8(&buffer[..len]).unwrap());
+    assert!(!dira.unlink("dirb"));
+    assert!(dirb.unlink("filec"));
+    assert!(dirb.find("filec").is_none());
+    assert_eq!(filec.nlink(), 1);
+    // the last link goes while `filec` is open, the inode lives until it is dropped
+    let filec_id = filec.inode_id();
+    assert!(dira.unlink("linkc"));
+    assert_eq!(filec.nlink(), 0);
+    assert_eq!(filec.read_at(0, &mut buffer), greet_str.len());
+    assert_ne!(dirb.create("filed").unwrap().inode_id(), filec_id);
+    drop(filec);
+    assert_eq!(dirb.create("filee").unwrap().inode_id(), filec_id);
+    // removing an entry moves the last one into its place
+    assert!(dirb.unlink("filed"));
+    assert!(dirb.ls() == ["filee"]);
+    assert!(dirb.unlink("filee"));
+    assert!(dira.unlink("dirb"));
+    assert!(dira.ls().is_empty());
+    assert_eq!(dira.nlink(), 2);
+    assert!(root_inode.unlink("dira"));
+    assert_eq!(root_inode.nlink(), 2);
+    assert!(!root_inode.unlink("dira"));
+    assert!(!root_inode.unlink(".."));
+    // a removed directory that is still open takes no new entries, and a
+    // removed file no new names
+    let gone = root_inode.create_dir("gone").unwrap();
+    let gone_file = gone.create("file").unwrap();
+    assert!(gone.unlink("file"));
+    assert!(!gone.link("file", &gone_file));
+    assert!(root_inode.unlink("gone"));
+    assert!(gone.create("file").is_none());
+    assert!(gone.create_dir("dir").is_none());
+    assert!(!gone.link("filea", &root_inode.find("filea").unwrap()));
+    assert!(!root_inode.rename("filea", &gone, "filea", RenameMode::Replace));
+    drop((gone, gone_file));
+
+    // symbolic links, a short target is kept in the inode
+    let short = root_inode.create_symlink("short", "dira/../filea").unwrap();
+    assert!(short.is_symlink() && !short.is_dir());
+    assert_eq!(short.read_link().unwrap(), "dira/../filea");
+    assert_eq!((short.size(), short.blocks(), short.mode()), (13, 0, 0o777));
+    let inline_target = "y".repeat(SYMLINK_INLINE_LIMIT);
+    let inline = root_inode.create_symlink("inline", &inline_target).unwrap();
+    assert_eq!(
+        (inline.read_link().unwrap(), inline.blocks()),
+        (inline_target, 0)
+    );
+    let long_target = "x/".repeat(300);
+    let long = root_inode.create_symlink("long", &long_target).unwrap();
+    assert_eq!((long.read_link().unwrap(), long.blocks()), (long_target, 2));
+    assert!(root_inode.find_path("short/x").is_none());
+    assert!(root_inode.create_symlink("empty", "").is_none());
+    assert!(root_inode.create_symlink("short", "fileb").is_none());
+    assert!(filea.read_link().is_none());
+    assert!(root_inode.link("hard", &short));
+    assert!(root_inode.unlink("short"));
+    assert_eq!(
+        root_inode.find("hard").unwrap().read_link().unwrap(),
+        "dira/../filea"
+    );
+    assert!(root_inode.unlink("hard"));
+    assert!(root_inode.unlink("inline"));
+    assert!(root_inode.unlink("long"));
+    assert!(root_inode.ls() == ["filea", "fileb"]);
+
+    // rename
+    let dird = root_inode.create_dir("dird").unwrap();
+    let dire = dird.create_dir("dire").unwrap();
+    let fileb = root_inode.find("fileb").unwrap();
+    assert!(root_inode.rename("fileb", &dire, "filef", RenameMode::Replace));
+    assert!(root_inode.find("fileb").is_none());
+    assert_eq!(dire.find("filef").unwrap().inode_id(), fileb.inode_id());
+    assert!(dire.rename("filef", &dire, "fileg", RenameMode::NoReplace));
+    assert!(dire.ls() == ["fileg"]);
+    assert_eq!(fileb.nlink(), 1);
+    // replacing a file unlinks it
+    let fileh_id = dird.create("fileh").unwrap().inode_id();
+    assert!(!dird.rename("fileh", &dire, "fileg", RenameMode::NoReplace));
+    assert!(dird.rename("fileh", &dire, "fileg", RenameMode::Replace));
+    assert_eq!(dire.find("fileg").unwrap().inode_id(), fileh_id);
+    assert_eq!(fileb.nlink(), 0);
+    drop(fileb);
+    // a directory moves with its `..`, but not into itself
+    assert!(!root_inode.rename("dird", &dire, "x", RenameMode::Replace));
+    assert!(dird.rename("dire", &root_inode, "dire", RenameMode::Replace));
+    assert_eq!(dire.find("..").unwrap().inode_id(), root_inode.inode_id());
+    assert_eq!((root_inode.nlink(), dird.nlink()), (4, 2));
+    // a directory replaces only an empty directory
+    assert!(!root_inode.rename("dird", &root_inode, "filea", RenameMode::Replace));
+    assert!(!root_inode.rename("dird", &root_inode, "dire", RenameMode::Replace));
+    // exchange, here of a file and a directory
+    assert!(!root_inode.rename("dird", &root_inode, "none", RenameMode::Exchange));
+    assert!(dire.rename("fileg", &root_inode, "dird", RenameMode::Exchange));
+    assert!(dire.find("fileg").unwrap().is_dir());
+    assert_eq!(root_inode.find("dird").unwrap().inode_id(), fileh_id);
+    assert_eq!(dird.find("..").unwrap().inode_id(), dire.inode_id());
+    assert_eq!((root_inode.nlink(), dire.nlink()), (3, 3));
+    assert!(dire.ls() == ["fileg"]);
+    assert!(dire.unlink("fileg"));
+    assert!(root_inode.unlink("dird"));
+    assert!(root_inode.unlink("dire"));
+    assert!(root_inode.ls() == ["filea"]);
 
     Ok(())
 }
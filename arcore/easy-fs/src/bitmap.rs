@@ -1,12 +1,12 @@
 //! Disk layout & data structure layer: about bitmaps
 //!
-//! There are two different types of [`Bitmap`] in the easy-fs layout that manage inodes and blocks, respectively. Each bitmap consists of several blocks, each of which is 512 bytes, or 4096 bits. Each bit represents the allocation status of an inode/data block, 0 means unallocated, and 1 means allocated. What the bitmap does is allocate and de-allocate inodes/data blocks via bit-based allocation (looking for a bit of 0 and setting it to 1) and de-allocation (clearing the bit).
-use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+//! There are two different types of [`Bitmap`] in the easy-fs layout that manage inodes and blocks, respectively. Each bitmap consists of several blocks, each of which has 8 bits per byte of the block size, e.g. 4096 bits for 512-byte blocks. Each bit represents the allocation status of an inode/data block, 0 means unallocated, and 1 means allocated. What the bitmap does is allocate and de-allocate inodes/data blocks via bit-based allocation (looking for a bit of 0 and setting it to 1) and de-allocation (clearing the bit).
+use super::{block_size, get_block_cache, BlockDevice};
 use alloc::sync::Arc;
-/// A bitmap block
-type BitmapBlock = [u64; 64];
 /// Number of bits in a block
-const BLOCK_BITS: usize = BLOCK_SZ * 8;
+fn block_bits() -> usize {
+    block_size() * 8
+}
 /// bitmap struct for disk block management
 pub struct Bitmap {
     start_block_id: usize,
@@ -15,8 +15,8 @@ pub struct Bitmap {
 
 /// Decompose bits into (block_pos, bits64_pos, inner_pos)
 fn decomposition(mut bit: usize) -> (usize, usize, usize) {
-    let block_pos = bit / BLOCK_BITS;
-    bit %= BLOCK_BITS;
+    let block_pos = bit / block_bits();
+    bit %= block_bits();
     (block_pos, bit / 64, bit % 64)
 }
 
@@ -29,29 +29,27 @@ impl Bitmap {
         }
     }
     /// Allocate a block according to the bitmap info
+    ///
+    /// The free bit is looked for without modifying the blocks, so that only
+    /// the block changed joins the next transaction, not the full ones passed.
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
-            let pos = get_block_cache(
-                block_id + self.start_block_id as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
-                if let Some((bits64_pos, inner_pos)) = bitmap_block
+            let block_cache =
+                get_block_cache(block_id + self.start_block_id, Arc::clone(block_device));
+            let mut block_cache = block_cache.lock();
+            let free = block_cache.read_slice(|bitmap_block: &[u64]| {
+                bitmap_block
                     .iter()
                     .enumerate()
                     .find(|(_, bits64)| **bits64 != u64::MAX)
                     .map(|(bits64_pos, bits64)| (bits64_pos, bits64.trailing_ones() as usize))
-                {
-                    // modify cache
-                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
-                } else {
-                    None
-                }
             });
-            if pos.is_some() {
-                return pos;
+            if let Some((bits64_pos, inner_pos)) = free {
+                // modify cache
+                block_cache.modify_slice(|bitmap_block: &mut [u64]| {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                });
+                return Some(block_id * block_bits() + bits64_pos * 64 + inner_pos);
             }
         }
         None
@@ -61,13 +59,30 @@ impl Bitmap {
         let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
         get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
             .lock()
-            .modify(0, |bitmap_block: &mut BitmapBlock| {
+            .modify_slice(|bitmap_block: &mut [u64]| {
                 assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0);
                 bitmap_block[bits64_pos] -= 1u64 << inner_pos;
             });
     }
+    /// Check whether a bit is allocated
+    pub fn is_allocated(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read_slice(|bitmap_block: &[u64]| bitmap_block[bits64_pos] & (1u64 << inner_pos) > 0)
+    }
+    /// Allocate a given free bit, so that it is never handed out
+    pub fn reserve(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify_slice(|bitmap_block: &mut [u64]| {
+                assert!(bitmap_block[bits64_pos] & (1u64 << inner_pos) == 0);
+                bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+            });
+    }
     /// bitmap max size in bits(the max number of blocks according to the bitmap size)
     pub fn maximum(&self) -> usize {
-        self.blocks * BLOCK_BITS
+        self.blocks * block_bits()
     }
 }
@@ -1,10 +1,11 @@
 //! Block Cache Layer
 //! Implements about the disk block cache functionality
-use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
+use super::{BlockDevice, Journal, SECTOR_SZ};
+use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 /// BlockCache is a cache for a block in disk.
@@ -13,19 +14,22 @@ pub struct BlockCache {
     block_id: usize,
     block_device: Arc<dyn BlockDevice>,
     modified: bool,
+    /// Modified as metadata since the last commit, so written through the journal
+    journaled: bool,
 }
 
 impl BlockCache {
     /// Load a new BlockCache from disk.
     pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
         // for alignment and move effciency
-        let mut cache = vec![0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        let mut cache = vec![0u8; block_size()];
+        block_device.read_fs_block(block_id, &mut cache);
         Self {
             cache,
             block_id,
             block_device,
             modified: false,
+            journaled: false,
         }
     }
     /// Get the slice in the block cache according to the offset.
@@ -38,7 +42,7 @@ impl BlockCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SZ);
+        assert!(offset + type_size <= self.cache.len());
         let addr = self.addr_of_offset(offset);
         unsafe { &*(addr as *const T) }
     }
@@ -48,8 +52,9 @@ impl BlockCache {
         T: Sized,
     {
         let type_size = core::mem::size_of::<T>();
-        assert!(offset + type_size <= BLOCK_SZ);
+        assert!(offset + type_size <= self.cache.len());
         self.modified = true;
+        self.journaled = true;
         let addr = self.addr_of_offset(offset);
         unsafe { &mut *(addr as *mut T) }
     }
@@ -61,11 +66,32 @@ impl BlockCache {
     pub fn modify<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
         f(self.get_mut(offset))
     }
+    /// Read the whole block as a slice of `T`, as the block size is known at runtime only.
+    pub fn read_slice<T, V>(&self, f: impl FnOnce(&[T]) -> V) -> V {
+        let len = self.cache.len() / core::mem::size_of::<T>();
+        f(unsafe { core::slice::from_raw_parts(self.cache.as_ptr() as *const T, len) })
+    }
+    /// Write the whole block as a slice of `T`.
+    pub fn modify_slice<T, V>(&mut self, f: impl FnOnce(&mut [T]) -> V) -> V {
+        let len = self.cache.len() / core::mem::size_of::<T>();
+        self.modified = true;
+        self.journaled = true;
+        f(unsafe { core::slice::from_raw_parts_mut(self.cache.as_mut_ptr() as *mut T, len) })
+    }
+    /// Write file data into the whole block, which is written in place before
+    /// the next commit instead of through the journal.
+    pub fn modify_data<V>(&mut self, f: impl FnOnce(&mut [u8]) -> V) -> V {
+        let journaled = self.journaled;
+        let ret = self.modify_slice(f);
+        self.journaled = journaled;
+        ret
+    }
     /// Sync(write) the block cache to disk.
     pub fn sync(&mut self) {
         if self.modified {
             self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
+            self.journaled = false;
+            self.block_device.write_fs_block(self.block_id, &self.cache);
         }
     }
 }
@@ -82,6 +108,10 @@ const BLOCK_CACHE_SIZE: usize = 16;
 pub struct BlockCacheManager {
     /// (block_id, block_cache)
     queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// The journal of the metadata blocks, written in place without one
+    journal: Option<Journal>,
+    /// Metadata blocks modified since the last commit and evicted from the queue
+    evicted: BTreeMap<usize, Vec<u8>>,
 }
 
 impl BlockCacheManager {
@@ -89,8 +119,48 @@ impl BlockCacheManager {
     pub fn new() -> Self {
         Self {
             queue: VecDeque::new(),
+            journal: None,
+            evicted: BTreeMap::new(),
         }
     }
+    /// Sync(write) all the block caches to disk, as a transaction when there is
+    /// a journal: file data in place first, then the metadata through the journal.
+    fn sync_all(&mut self) {
+        let mut caches: Vec<_> = self.queue.iter().map(|(_, cache)| cache.lock()).collect();
+        let journal = match &self.journal {
+            Some(journal) => journal,
+            None => {
+                caches.iter_mut().for_each(|cache| cache.sync());
+                return;
+            }
+        };
+        let mut blocks: Vec<(usize, Vec<u8>)> =
+            core::mem::take(&mut self.evicted).into_iter().collect();
+        for cache in caches.iter_mut() {
+            if cache.journaled {
+                blocks.push((cache.block_id, cache.cache.clone()));
+            } else {
+                cache.sync();
+            }
+        }
+        if blocks.is_empty() {
+            return;
+        }
+        journal.commit(&blocks);
+        journal.checkpoint(&blocks);
+        for cache in caches.iter_mut() {
+            cache.modified = false;
+            cache.journaled = false;
+        }
+    }
+    /// Sync and drop all the block caches, which must not be in use
+    fn drop_all(&mut self) {
+        self.sync_all();
+        for (_, cache) in self.queue.iter() {
+            assert_eq!(Arc::strong_count(cache), 1, "Block cache in use!");
+        }
+        self.queue.clear();
+    }
     /// Get a block cache from the queue. according to the block_id.
     pub fn get_block_cache(
         &mut self,
@@ -109,22 +179,42 @@ impl BlockCacheManager {
                     .enumerate()
                     .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
                 {
-                    self.queue.drain(idx..=idx);
+                    let (block_id, block_cache) = self.queue.remove(idx).unwrap();
+                    let mut block_cache = block_cache.lock();
+                    // kept until the commit, not written in place before
+                    if block_cache.journaled && self.journal.is_some() {
+                        let cache = core::mem::take(&mut block_cache.cache);
+                        self.evicted.insert(block_id, cache);
+                        block_cache.modified = false;
+                    }
                 } else {
                     panic!("Run out of BlockCache!");
                 }
             }
             // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
+            let block_cache = match self.evicted.remove(&block_id) {
+                Some(cache) => BlockCache {
+                    cache,
+                    block_id,
+                    block_device: Arc::clone(&block_device),
+                    modified: true,
+                    journaled: true,
+                },
+                None => BlockCache::new(block_id, Arc::clone(&block_device)),
+            };
+            let block_cache = Arc::new(Mutex::new(block_cache));
             self.queue.push_back((block_id, Arc::clone(&block_cache)));
             block_cache
         }
     }
 }
 
+impl Default for BlockCacheManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 lazy_static! {
     /// BLOCK_CACHE_MANAGER: Glocal instance of BlockCacheManager.
     pub static ref BLOCK_CACHE_MANAGER: Mutex<BlockCacheManager> =
@@ -139,10 +229,44 @@ pub fn get_block_cache(
         .lock()
         .get_block_cache(block_id, block_device)
 }
-/// Sync(write) all the block cache to disk.
+/// The block size of the file system in use, [`SECTOR_SZ`] until one is created or opened
+static BLOCK_SIZE: AtomicUsize = AtomicUsize::new(SECTOR_SZ);
+/// The block size of the file system in use
+pub fn block_size() -> usize {
+    BLOCK_SIZE.load(Ordering::Relaxed)
+}
+/// Switch to blocks of `size` bytes, writing back and dropping the cached blocks
+/// of the old size, which must not be in use.
+pub fn set_block_size(size: usize) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    if block_size() != size {
+        manager.drop_all();
+        BLOCK_SIZE.store(size, Ordering::Relaxed);
+    }
+}
+/// Write the metadata blocks through `journal` from now on, or in place with
+/// `None`, committing the blocks modified so far.
+pub fn set_journal(journal: Option<Journal>) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.sync_all();
+    manager.journal = journal;
+}
+/// Sync(write) all the block cache to disk, committing a transaction of the
+/// blocks modified since the last one.
 pub fn block_cache_sync_all() {
-    let manager = BLOCK_CACHE_MANAGER.lock();
-    for (_, cache) in manager.queue.iter() {
-        cache.lock().sync();
+    BLOCK_CACHE_MANAGER.lock().sync_all();
+}
+/// Sync(write) all the block cache to disk in place when there is no journal,
+/// a step of an operation ordering its writes to survive a crash. With a
+/// journal the operation is committed as a whole by [`block_cache_sync_all`].
+pub fn block_cache_sync_step() {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    if manager.journal.is_none() {
+        manager.sync_all();
     }
 }
+/// Sync and drop all the block caches, which must not be in use, e.g. before
+/// opening a file system that may be on another device.
+pub fn block_cache_drop_all() {
+    BLOCK_CACHE_MANAGER.lock().drop_all();
+}
@@ -2,11 +2,27 @@
 //!
 //! Define the block read-write interface [BlockDevice] that the device driver needs to implement
 
+use super::SECTOR_SZ;
 use core::any::Any;
 
 pub trait BlockDevice: Send + Sync + Any {
-    /// Read a block from the block device.
+    /// Read a 512-byte sector from the block device.
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
-    /// Write a block to the block device.
+    /// Write a 512-byte sector to the block device.
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Read the file system block `block_id`, made of the `buf.len() / SECTOR_SZ`
+    /// sectors starting at `block_id * buf.len() / SECTOR_SZ`.
+    fn read_fs_block(&self, block_id: usize, buf: &mut [u8]) {
+        let sectors = buf.len() / SECTOR_SZ;
+        for (i, sector) in buf.chunks_mut(SECTOR_SZ).enumerate() {
+            self.read_block(block_id * sectors + i, sector);
+        }
+    }
+    /// Write the file system block `block_id`, sector by sector.
+    fn write_fs_block(&self, block_id: usize, buf: &[u8]) {
+        let sectors = buf.len() / SECTOR_SZ;
+        for (i, sector) in buf.chunks(SECTOR_SZ).enumerate() {
+            self.write_block(block_id * sectors + i, sector);
+        }
+    }
 }
@@ -0,0 +1,22 @@
+//! Clock for the timestamps of inodes
+//!
+//! easy-fs has no clock of its own: the kernel, or easy-fs-fuse on the host,
+//! installs one with [`set_clock`]. Until then all timestamps are 0.
+use spin::Mutex;
+
+static CLOCK: Mutex<fn() -> u64> = Mutex::new(no_clock);
+
+fn no_clock() -> u64 {
+    0
+}
+
+/// Install `clock`, which returns the time in nanoseconds since the epoch
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.lock() = clock;
+}
+
+/// The current time in nanoseconds since the epoch
+pub fn now() -> u64 {
+    let clock = *CLOCK.lock();
+    clock()
+}
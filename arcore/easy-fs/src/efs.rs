@@ -4,11 +4,15 @@
 //!
 //! NOTICE: from this level, all data structures are in memory.
 use super::{
-    block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType, Inode,
-    SuperBlock,
+    block_cache_drop_all, block_cache_sync_all, block_size, clock::now, get_block_cache,
+    set_block_size, set_journal, Bitmap, BlockDevice, DirEntry, DiskInode, DiskInodeType, Inode,
+    InodeRefs, Journal, SuperBlock, DIRENT_SZ, DISK_INODE_V1_SZ, EFS_VERSION, MAX_BLOCK_SZ,
+    SECTOR_SZ,
 };
-use crate::BLOCK_SZ;
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
 use spin::Mutex;
 
 /// EasyFileSystem struct
@@ -21,27 +25,40 @@ pub struct EasyFileSystem {
     pub data_bitmap: Bitmap,
     /// The start block id of inode area
     inode_area_start_block: u32,
+    /// The in-memory references of the inodes
+    inode_refs: Arc<Mutex<InodeRefs>>,
     /// The start block id of data area
     data_area_start_block: u32,
 }
 
-type DataBlock = [u8; BLOCK_SZ];
-
 impl EasyFileSystem {
-    /// Create a new EasyFileSystem
+    /// Create a new EasyFileSystem of `total_blocks` blocks of `block_size` bytes,
+    /// a power of two from 512 to 4096, with a journal at the end
     pub fn create(
         block_device: Arc<dyn BlockDevice>,
         total_blocks: u32,
         inode_bitmap_blocks: u32,
+        block_size: usize,
     ) -> Arc<Mutex<Self>> {
+        assert!(
+            block_size.is_power_of_two() && (SECTOR_SZ..=MAX_BLOCK_SZ).contains(&block_size),
+            "Invalid block size {}!",
+            block_size
+        );
+        set_journal(None);
+        block_cache_drop_all();
+        set_block_size(block_size);
         // calculate block size of areas & create bitmaps
         let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
         let inode_num = inode_bitmap.maximum();
         let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32;
+            ((inode_num * core::mem::size_of::<DiskInode>() + block_size - 1) / block_size) as u32;
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks;
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks;
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097;
+        let journal_blocks = Journal::region_blocks(block_size);
+        let data_total_blocks = total_blocks - 1 - inode_total_blocks - journal_blocks;
+        // a bitmap block covers its bits and takes one block itself
+        let block_bits = block_size as u32 * 8;
+        let data_bitmap_blocks = (data_total_blocks + block_bits) / (block_bits + 1);
         let data_area_blocks = data_total_blocks - data_bitmap_blocks;
         let data_bitmap = Bitmap::new(
             (1 + inode_bitmap_blocks + inode_area_blocks) as usize,
@@ -54,12 +71,13 @@ impl EasyFileSystem {
             data_bitmap,
             inode_area_start_block: 1 + inode_bitmap_blocks,
             data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks,
+            inode_refs: Arc::new(Mutex::new(InodeRefs::default())),
         };
         // clear all blocks
         for i in 0..total_blocks {
             get_block_cache(i as usize, Arc::clone(&block_device))
                 .lock()
-                .modify(0, |data_block: &mut DataBlock| {
+                .modify_slice(|data_block: &mut [u8]| {
                     for byte in data_block.iter_mut() {
                         *byte = 0;
                     }
@@ -75,6 +93,8 @@ impl EasyFileSystem {
                     inode_area_blocks,
                     data_bitmap_blocks,
                     data_area_blocks,
+                    block_size,
+                    journal_blocks,
                 );
             },
         );
@@ -86,21 +106,67 @@ impl EasyFileSystem {
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
                 disk_inode.initialize(DiskInodeType::Directory);
+                disk_inode.nlink = 2;
+                disk_inode.mode = 0o755;
+                disk_inode.set_times(now());
+                // "." and ".." of the root are the root itself
+                let blocks_needed =
+                    disk_inode.blocks_num_needed(0, 2 * DIRENT_SZ as u32, &block_device);
+                let new_blocks = (0..blocks_needed).map(|_| efs.alloc_data()).collect();
+                disk_inode.increase_size(2 * DIRENT_SZ as u32, new_blocks, &block_device);
+                disk_inode.write_at(0, DirEntry::new(".", 0).as_bytes(), &block_device);
+                disk_inode.write_at(DIRENT_SZ, DirEntry::new("..", 0).as_bytes(), &block_device);
             });
         block_cache_sync_all();
+        let journal_start_block = (total_blocks - journal_blocks) as usize;
+        set_journal(Some(Journal::new(block_device, journal_start_block)));
         Arc::new(Mutex::new(efs))
     }
-    /// Open an existing EasyFileSystem
+    /// Open an existing EasyFileSystem, replaying its journal after a crash
+    /// and migrating an image of an older layout
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+        set_journal(None);
+        block_cache_drop_all();
+        // the superblock is in the first sector, whatever the block size
+        let (block_size, journal_start_block, journal_blocks) =
+            get_block_cache(0, Arc::clone(&block_device)).lock().read(
+                0,
+                |super_block: &SuperBlock| {
+                    assert!(super_block.is_valid(), "Error loading EFS!");
+                    (
+                        super_block.block_size(),
+                        super_block.journal_start_block,
+                        super_block.journal_blocks,
+                    )
+                },
+            );
+        set_block_size(block_size);
+        let journal = match journal_blocks {
+            0 => None,
+            _ => Some(Journal::new(
+                Arc::clone(&block_device),
+                journal_start_block as usize,
+            )),
+        };
+        if let Some(journal) = journal.as_ref() {
+            // a transaction committed before a crash may not be in place yet
+            for (block_id, data) in journal.committed() {
+                get_block_cache(block_id, Arc::clone(&block_device))
+                    .lock()
+                    .modify_slice(|block: &mut [u8]| block.copy_from_slice(&data));
+            }
+            block_cache_sync_all();
+            journal.clear();
+        }
         // read SuperBlock
-        get_block_cache(0, Arc::clone(&block_device))
+        let (mut efs, version, inode_area_blocks) = get_block_cache(0, Arc::clone(&block_device))
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error loading EFS!");
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
                 let efs = Self {
-                    block_device,
+                    block_device: Arc::clone(&block_device),
                     inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
                     data_bitmap: Bitmap::new(
                         (1 + inode_total_blocks) as usize,
@@ -108,22 +174,162 @@ impl EasyFileSystem {
                     ),
                     inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
                     data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    inode_refs: Arc::new(Mutex::new(InodeRefs::default())),
                 };
-                Arc::new(Mutex::new(efs))
-            })
+                (efs, super_block.version(), super_block.inode_area_blocks)
+            });
+        assert!(
+            version <= EFS_VERSION,
+            "EFS version {} is too new!",
+            version
+        );
+        if version == 1 {
+            efs.migrate_v1(inode_area_blocks);
+        }
+        // the inodes of version 2 keep a zero indirect3 in their reserved bytes,
+        // the superblocks before version 4 a zero block size, which is 512
+        if version < EFS_VERSION {
+            get_block_cache(0, Arc::clone(&efs.block_device))
+                .lock()
+                .modify(0, |super_block: &mut SuperBlock| {
+                    super_block.set_current_version()
+                });
+            block_cache_sync_all();
+        }
+        set_journal(journal);
+        Arc::new(Mutex::new(efs))
+    }
+    /// Migrate an image of version 1 in place
+    ///
+    /// The inodes double in size within the same inode area, so only the
+    /// first half of the inode ids remain: the rest must be free and are
+    /// reserved in the inode bitmap. Existing inodes get mode 0o755, root
+    /// as owner and the current time. Link counts, zero on images made before
+    /// they were kept, are recounted from the directory tree.
+    fn migrate_v1(&mut self, inode_area_blocks: u32) {
+        let inode_count =
+            inode_area_blocks as usize * block_size() / core::mem::size_of::<DiskInode>();
+        for inode_id in inode_count..self.inode_bitmap.maximum() {
+            assert!(
+                !self.inode_bitmap.is_allocated(&self.block_device, inode_id),
+                "EFS migration: inode {} does not fit into the inode area",
+                inode_id
+            );
+            self.inode_bitmap.reserve(&self.block_device, inode_id);
+        }
+        // an inode moves from `id * 128` to `id * 256`, over the old slots
+        // of higher ids only, so go from the last inode down
+        let now = now();
+        for inode_id in (0..inode_count).rev() {
+            let old_pos = inode_id * DISK_INODE_V1_SZ;
+            let allocated = self.inode_bitmap.is_allocated(&self.block_device, inode_id);
+            let old_inode = get_block_cache(
+                self.inode_area_start_block as usize + old_pos / block_size(),
+                Arc::clone(&self.block_device),
+            )
+            .lock()
+            .read(old_pos % block_size(), |old: &[u8; DISK_INODE_V1_SZ]| *old);
+            self.modify_disk_inode(inode_id as u32, |disk_inode| {
+                disk_inode.initialize(DiskInodeType::File);
+                if allocated {
+                    let dst = disk_inode as *mut DiskInode as *mut u8;
+                    unsafe { dst.copy_from(old_inode.as_ptr(), DISK_INODE_V1_SZ) };
+                    disk_inode.mode = 0o755;
+                    disk_inode.set_times(now);
+                }
+            });
+        }
+        self.recount_links();
+    }
+    /// Set the link count of every inode reachable from the root to the
+    /// number of directory entries naming it, adding the `.` and `..`
+    /// entries a root made before directories lacks
+    fn recount_links(&mut self) {
+        let mut counts: BTreeMap<u32, u16> = BTreeMap::new();
+        // (directory, its parent)
+        let mut dirs: Vec<(u32, u32)> = vec![(0, 0)];
+        while let Some((dir_id, parent_id)) = dirs.pop() {
+            let mut dirents = self.read_disk_inode(dir_id, |dir_inode| {
+                let file_count = dir_inode.size as usize / DIRENT_SZ;
+                let mut dirents = Vec::new();
+                for i in 0..file_count {
+                    let mut dirent = DirEntry::empty();
+                    dir_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+                    dirents.push(dirent);
+                }
+                dirents
+            });
+            if dirents.iter().all(|dirent| dirent.name() != ".") {
+                let dots = [DirEntry::new(".", dir_id), DirEntry::new("..", parent_id)];
+                self.append_dirents(dir_id, &dots);
+                dirents.extend(dots);
+            }
+            for dirent in dirents.iter() {
+                *counts.entry(dirent.inode_id()).or_insert(0) += 1;
+                let name = dirent.name();
+                let inode_id = dirent.inode_id();
+                if name != "." && name != ".." && self.read_disk_inode(inode_id, |d| d.is_dir()) {
+                    dirs.push((inode_id, dir_id));
+                }
+            }
+        }
+        // an inode no entry names was leaked before the migration, leave it
+        for (inode_id, nlink) in counts {
+            self.modify_disk_inode(inode_id, |disk_inode| disk_inode.nlink = nlink);
+        }
+    }
+    /// Append `dirents` to the directory `dir_id`
+    fn append_dirents(&mut self, dir_id: u32, dirents: &[DirEntry]) {
+        let block_device = Arc::clone(&self.block_device);
+        let (old_size, blocks_needed) = self.read_disk_inode(dir_id, |dir_inode| {
+            let new_size = dir_inode.size + (dirents.len() * DIRENT_SZ) as u32;
+            let needed = dir_inode.blocks_num_needed(dir_inode.size, new_size, &block_device);
+            (dir_inode.size, needed)
+        });
+        let new_blocks = (0..blocks_needed).map(|_| self.alloc_data()).collect();
+        self.modify_disk_inode(dir_id, |dir_inode| {
+            let new_size = old_size + (dirents.len() * DIRENT_SZ) as u32;
+            dir_inode.increase_size(new_size, new_blocks, &block_device);
+            for (i, dirent) in dirents.iter().enumerate() {
+                let offset = old_size as usize + i * DIRENT_SZ;
+                dir_inode.write_at(offset, dirent.as_bytes(), &block_device);
+            }
+        });
+    }
+    /// Read the disk inode `inode_id` with 'f' function
+    fn read_disk_inode<V>(&self, inode_id: u32, f: impl FnOnce(&DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, f)
+    }
+    /// Modify the disk inode `inode_id` with 'f' function
+    fn modify_disk_inode<V>(&self, inode_id: u32, f: impl FnOnce(&mut DiskInode) -> V) -> V {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f)
     }
     /// Get the root inode
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
+        let inode_refs = Arc::clone(&efs.lock().inode_refs);
         // acquire efs lock temporarily
         let (block_id, block_offset) = efs.lock().get_disk_inode_pos(0);
         // release efs lock
-        Inode::new(block_id, block_offset, Arc::clone(efs), block_device)
+        Inode::new(
+            0,
+            block_id,
+            block_offset,
+            Arc::clone(efs),
+            block_device,
+            inode_refs,
+        )
     }
     /// Get inode block position (the block id and offset in this block) according to the inode id
     pub fn get_disk_inode_pos(&self, inode_id: u32) -> (u32, usize) {
         let inode_size = core::mem::size_of::<DiskInode>();
-        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        let inodes_per_block = (block_size() / inode_size) as u32;
         let block_id = self.inode_area_start_block + inode_id / inodes_per_block;
         (
             block_id,
@@ -139,19 +345,26 @@ impl EasyFileSystem {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
 
+    /// deallocate an inode according to its inode_id
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
+    }
+
     /// allocate a new data block, return its block position (block_id)
+    ///
+    /// The block is zeroed, in place before the allocation commits as it is
+    /// free until then.
     pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+        let block_id =
+            self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block;
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify_data(|data_block| data_block.fill(0));
+        block_id
     }
     /// deallocate a data block according to its block id
     pub fn dealloc_data(&mut self, block_id: u32) {
-        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
-                data_block.iter_mut().for_each(|p| {
-                    *p = 0;
-                })
-            });
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
@@ -0,0 +1,110 @@
+//! Write-ahead journal layer
+//!
+//! The metadata blocks a transaction modifies are first written to the
+//! journal region at the end of the disk, then a commit block records them,
+//! and only then are they written in place. A crash before the commit block
+//! is written loses the transaction, a crash after it is repaired by
+//! replaying the journal when the file system is opened. File data is not
+//! journaled but written in place before the commit, so a committed
+//! transaction never points at stale data.
+//!
+//! The region is a commit block, the descriptor blocks listing where each
+//! journaled block belongs, and the copies of the blocks.
+use super::{block_size, BlockDevice};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+const JOURNAL_MAGIC: u32 = 0x4a4e4c31;
+/// The most metadata blocks a transaction may modify
+pub const JOURNAL_CAPACITY: usize = 128;
+
+/// The journal region of a block device
+pub struct Journal {
+    block_device: Arc<dyn BlockDevice>,
+    start_block: usize,
+}
+
+impl Journal {
+    /// The journal region starting at `start_block`
+    pub fn new(block_device: Arc<dyn BlockDevice>, start_block: usize) -> Self {
+        Self {
+            block_device,
+            start_block,
+        }
+    }
+    /// Number of blocks of the region, for blocks of `block_size` bytes
+    pub fn region_blocks(block_size: usize) -> u32 {
+        (1 + Self::descriptor_blocks(block_size) + JOURNAL_CAPACITY) as u32
+    }
+    fn descriptor_blocks(block_size: usize) -> usize {
+        (JOURNAL_CAPACITY * 4 + block_size - 1) / block_size
+    }
+    /// The block of the copy of the `index`th journaled block
+    fn copy_block(&self, index: usize) -> usize {
+        self.start_block + 1 + Self::descriptor_blocks(block_size()) + index
+    }
+    /// Write the `(block id, content)` of `blocks` to the journal, then commit them
+    pub fn commit(&self, blocks: &[(usize, Vec<u8>)]) {
+        assert!(
+            blocks.len() <= JOURNAL_CAPACITY,
+            "Transaction too large for the journal!"
+        );
+        let block_size = block_size();
+        let mut descriptor = vec![0u8; Self::descriptor_blocks(block_size) * block_size];
+        for (index, (block_id, data)) in blocks.iter().enumerate() {
+            descriptor[index * 4..index * 4 + 4].copy_from_slice(&(*block_id as u32).to_le_bytes());
+            self.block_device
+                .write_fs_block(self.copy_block(index), data);
+        }
+        for (index, block) in descriptor.chunks(block_size).enumerate() {
+            self.block_device
+                .write_fs_block(self.start_block + 1 + index, block);
+        }
+        // the commit record is in the first sector, which is written at once
+        let mut commit = vec![0u8; block_size];
+        commit[..4].copy_from_slice(&JOURNAL_MAGIC.to_le_bytes());
+        commit[4..8].copy_from_slice(&(blocks.len() as u32).to_le_bytes());
+        self.block_device.write_fs_block(self.start_block, &commit);
+    }
+    /// Write the committed `blocks` in place, then forget them
+    pub fn checkpoint(&self, blocks: &[(usize, Vec<u8>)]) {
+        for (block_id, data) in blocks.iter() {
+            self.block_device.write_fs_block(*block_id, data);
+        }
+        self.clear();
+    }
+    /// The blocks of a transaction committed but maybe not yet in place
+    pub fn committed(&self) -> Vec<(usize, Vec<u8>)> {
+        let block_size = block_size();
+        let mut commit = vec![0u8; block_size];
+        self.block_device
+            .read_fs_block(self.start_block, &mut commit);
+        let word = |bytes: &[u8], index: usize| {
+            u32::from_le_bytes(bytes[index * 4..index * 4 + 4].try_into().unwrap())
+        };
+        if word(&commit, 0) != JOURNAL_MAGIC {
+            return Vec::new();
+        }
+        let count = word(&commit, 1) as usize;
+        let mut descriptor = vec![0u8; Self::descriptor_blocks(block_size) * block_size];
+        for (index, block) in descriptor.chunks_mut(block_size).enumerate() {
+            self.block_device
+                .read_fs_block(self.start_block + 1 + index, block);
+        }
+        (0..count)
+            .map(|index| {
+                let mut data = vec![0u8; block_size];
+                self.block_device
+                    .read_fs_block(self.copy_block(index), &mut data);
+                (word(&descriptor, index) as usize, data)
+            })
+            .collect()
+    }
+    /// Forget the committed transaction, once its blocks are in place
+    pub fn clear(&self) {
+        self.block_device
+            .write_fs_block(self.start_block, &vec![0u8; block_size()]);
+    }
+}
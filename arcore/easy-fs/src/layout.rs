@@ -8,20 +8,46 @@
 //!  - data bitmap
 //!  - data area with DataBlock
 
-use super::{get_block_cache, BlockDevice, BLOCK_SZ};
+use super::{block_size, get_block_cache, BlockDevice, SECTOR_SZ};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
 
 const EFS_MAGIC: u32 = 0x3b800001;
+/// The version of the disk layout this crate writes, an image of an older
+/// version is migrated when it is opened
+///
+/// - 1: 128-byte inodes with type, size and block ids (version field 0)
+/// - 2: 256-byte inodes with timestamps, permissions and ownership
+/// - 3: a triple indirect block in the inode
+/// - 4: the block size in the superblock (0 for 512 before)
+/// - 5: a journal region at the end of the disk, images migrated from older
+///   versions have none
+pub const EFS_VERSION: u32 = 5;
+/// The size of an inode of version 1
+pub const DISK_INODE_V1_SZ: usize = 128;
 const INODE_DIRECT_COUNT: usize = 28;
-const NAME_LENGTH_LIMIT: usize = 27;
-const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
-const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
+/// The max length of a file name
+pub const NAME_LENGTH_LIMIT: usize = 27;
+/// The max length of the target of a symbolic link
+pub const SYMLINK_LENGTH_LIMIT: usize = 4095;
+/// A symbolic link target up to this length is kept in the direct block ids
+/// of its inode instead of in a data block
+pub const SYMLINK_INLINE_LIMIT: usize = INODE_DIRECT_COUNT * 4;
 const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-#[allow(unused)]
-const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
+
+/// Number of block ids in an indirect block
+fn indirect_count() -> usize {
+    block_size() / 4
+}
+
+/// The max size of a file, reached by the triple indirect block or by the
+/// 32-bit file size
+pub fn max_file_size() -> usize {
+    let count = indirect_count();
+    let blocks = DIRECT_BOUND + count + count.pow(2) + count.pow(3);
+    (blocks * block_size()).min(u32::MAX as usize / block_size() * block_size())
+}
 
 /// superblock of easy-fs
 #[repr(C)]
@@ -38,6 +64,14 @@ pub struct SuperBlock {
     pub data_bitmap_blocks: u32,
     /// The number of blocks used for data area
     pub data_area_blocks: u32,
+    /// layout version, 0 on images made before it was kept
+    version: u32,
+    /// block size in bytes, 0 on images made before it was kept
+    block_size: u32,
+    /// The start block id of the journal region
+    pub journal_start_block: u32,
+    /// The number of blocks used for the journal region, 0 without a journal
+    pub journal_blocks: u32,
 }
 
 impl Debug for SuperBlock {
@@ -48,12 +82,17 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("version", &self.version())
+            .field("block_size", &self.block_size())
+            .field("journal_start_block", &self.journal_start_block)
+            .field("journal_blocks", &self.journal_blocks)
             .finish()
     }
 }
 
 impl SuperBlock {
-    /// Initialize the superblock
+    /// Initialize the superblock, with the journal region at the end
+    #[allow(clippy::too_many_arguments)]
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -61,6 +100,8 @@ impl SuperBlock {
         inode_area_blocks: u32,
         data_bitmap_blocks: u32,
         data_area_blocks: u32,
+        block_size: usize,
+        journal_blocks: u32,
     ) {
         *self = Self {
             magic: EFS_MAGIC,
@@ -69,12 +110,31 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            version: EFS_VERSION,
+            block_size: block_size as u32,
+            journal_start_block: total_blocks - journal_blocks,
+            journal_blocks,
         }
     }
     /// Check if the superblock is valid according to the magic number
     pub fn is_valid(&self) -> bool {
         self.magic == EFS_MAGIC
     }
+    /// The layout version of the image
+    pub fn version(&self) -> u32 {
+        self.version.max(1)
+    }
+    /// The block size of the image
+    pub fn block_size(&self) -> usize {
+        match self.block_size {
+            0 => SECTOR_SZ,
+            size => size as usize,
+        }
+    }
+    /// Record that the image has been migrated to the current version
+    pub fn set_current_version(&mut self) {
+        self.version = EFS_VERSION;
+    }
 }
 
 /// Inode Type of easy-fs
@@ -84,11 +144,10 @@ pub enum DiskInodeType {
     File,
     /// Directory type
     Directory,
+    /// Symbolic link type
+    Symlink,
 }
 
-type IndirectBlock = [u32; BLOCK_SZ / 4];
-type DataBlock = [u8; BLOCK_SZ];
-
 /// Inode struct in disk
 #[repr(C)]
 pub struct DiskInode {
@@ -102,16 +161,51 @@ pub struct DiskInode {
     pub indirect2: u32,
     /// inode type
     type_: DiskInodeType,
+    /// number of directory entries naming the inode, "." and ".." included
+    pub nlink: u16,
+    // the fields above are the inode of version 1
+    /// time of last access, in nanoseconds since the epoch
+    pub atime: u64,
+    /// time of last modification of the content
+    pub mtime: u64,
+    /// time of last change of the content or of the inode
+    pub ctime: u64,
+    /// user id of the owner
+    pub uid: u32,
+    /// group id of the owner
+    pub gid: u32,
+    /// permission bits, `0o7777` at most
+    pub mode: u16,
+    // the fields above are the inode of version 2
+    /// three-level indirect block id
+    pub indirect3: u32,
+    /// room for later versions
+    reserved: [u8; 88],
 }
 
 impl DiskInode {
-    /// indirect1 and indirect2 block are allocated only when they are needed.
+    /// indirect1, indirect2 and indirect3 block are allocated only when they are needed.
     pub fn initialize(&mut self, type_: DiskInodeType) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        self.indirect3 = 0;
         self.type_ = type_;
+        self.nlink = 0;
+        self.atime = 0;
+        self.mtime = 0;
+        self.ctime = 0;
+        self.uid = 0;
+        self.gid = 0;
+        self.mode = 0;
+        self.reserved.iter_mut().for_each(|v| *v = 0);
+    }
+    /// Set all three timestamps to `now`, for a new inode
+    pub fn set_times(&mut self, now: u64) {
+        self.atime = now;
+        self.mtime = now;
+        self.ctime = now;
     }
     /// inode is directory?
     pub fn is_dir(&self) -> bool {
@@ -122,59 +216,267 @@ impl DiskInode {
     pub fn is_file(&self) -> bool {
         self.type_ == DiskInodeType::File
     }
+    /// inode is symbolic link?
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::Symlink
+    }
+    /// Is the content kept in the inode itself, a short symbolic link?
+    pub fn is_inline(&self) -> bool {
+        self.is_symlink() && self.size as usize <= SYMLINK_INLINE_LIMIT
+    }
+    /// The bytes of the direct block ids, holding an inline content
+    fn inline_data(&self) -> &[u8] {
+        unsafe {
+            core::slice::from_raw_parts(self.direct.as_ptr() as *const u8, SYMLINK_INLINE_LIMIT)
+        }
+    }
+    /// Set the content of a short symbolic link, kept in the inode itself
+    pub fn write_inline(&mut self, data: &[u8]) {
+        assert!(self.is_symlink() && self.size == 0 && data.len() <= SYMLINK_INLINE_LIMIT);
+        let inline = unsafe {
+            core::slice::from_raw_parts_mut(
+                self.direct.as_mut_ptr() as *mut u8,
+                SYMLINK_INLINE_LIMIT,
+            )
+        };
+        inline[..data.len()].copy_from_slice(data);
+        self.size = data.len() as u32;
+    }
     /// Return block number correspond to size.
     pub fn data_blocks(&self) -> u32 {
         Self::_data_blocks(self.size)
     }
     fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
-    }
-    /// Return number of blocks needed include indirect1/2.
-    pub fn total_blocks(size: u32) -> u32 {
-        let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
-        // indirect1
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
+        let block_size = block_size() as u32;
+        (size + block_size - 1) / block_size
+    }
+    /// Range of the data blocks `first..last` holding the bytes `start..end`
+    fn block_range(start: u32, end: u32) -> (usize, usize) {
+        match start < end {
+            true => (
+                start as usize / block_size(),
+                Self::_data_blocks(end) as usize,
+            ),
+            false => (0, 0),
         }
-        // indirect2
-        if data_blocks > INDIRECT1_BOUND {
-            total += 1;
-            // sub indirect1
-            total +=
-                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+    }
+    /// Number of data blocks under an entry of an indirect block of `level`
+    fn entry_span(level: u32) -> usize {
+        indirect_count().pow(level - 1)
+    }
+    /// The indirect blocks of the inode, with their level and the first data
+    /// block under them
+    fn indirect_roots(&self) -> [(u32, u32, usize); 3] {
+        let [base1, base2, base3] = Self::indirect_bases();
+        [
+            (self.indirect1, 1, base1),
+            (self.indirect2, 2, base2),
+            (self.indirect3, 3, base3),
+        ]
+    }
+    fn indirect_roots_mut(&mut self) -> [(&mut u32, u32, usize); 3] {
+        let [base1, base2, base3] = Self::indirect_bases();
+        [
+            (&mut self.indirect1, 1, base1),
+            (&mut self.indirect2, 2, base2),
+            (&mut self.indirect3, 3, base3),
+        ]
+    }
+    /// The first data block under indirect1, indirect2 and indirect3
+    fn indirect_bases() -> [usize; 3] {
+        let count = indirect_count();
+        let base2 = DIRECT_BOUND + count;
+        [DIRECT_BOUND, base2, base2 + count * count]
+    }
+    /// Id of the data block at `index` under the indirect block `block_id` of
+    /// `level`, 0 if it or a block above it is missing
+    fn index_lookup(
+        mut block_id: u32,
+        level: u32,
+        mut index: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        for level in (1..=level).rev() {
+            if block_id == 0 {
+                break;
+            }
+            let span = Self::entry_span(level);
+            block_id = get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .read_slice(|indirect: &[u32]| indirect[index / span]);
+            index %= span;
         }
-        total as u32
+        block_id
     }
-    /// Number of blocks needed to extend.
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        Self::total_blocks(new_size) - Self::total_blocks(self.size)
+    /// Number of blocks missing to back the data blocks `first..last` under
+    /// the indirect block `block_id` of `level`, itself included
+    fn index_needed(
+        block_id: u32,
+        level: u32,
+        first: usize,
+        last: usize,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        let mut needed = (block_id == 0) as u32;
+        if level == 0 {
+            return needed;
+        }
+        let span = Self::entry_span(level);
+        for index in first / span..(last - 1) / span + 1 {
+            let entry = match block_id {
+                0 => 0,
+                _ => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read_slice(|indirect: &[u32]| indirect[index]),
+            };
+            let base = index * span;
+            needed += Self::index_needed(
+                entry,
+                level - 1,
+                first.max(base) - base,
+                last.min(base + span) - base,
+                block_device,
+            );
+        }
+        needed
+    }
+    /// Fill the missing blocks on the way to the data block at `index` under
+    /// the indirect block `root` of `level`
+    fn index_fill(
+        root: &mut u32,
+        level: u32,
+        mut index: usize,
+        fill: &mut impl FnMut(&mut u32) -> u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        let mut block_id = fill(root);
+        for level in (1..=level).rev() {
+            let span = Self::entry_span(level);
+            block_id = get_block_cache(block_id as usize, Arc::clone(block_device))
+                .lock()
+                .modify_slice(|indirect: &mut [u32]| fill(&mut indirect[index / span]));
+            index %= span;
+        }
+    }
+    /// Number of blocks under the indirect block `block_id` of `level`, itself
+    /// included
+    fn index_count(block_id: u32, level: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if block_id == 0 || level == 0 {
+            return (block_id != 0) as u32;
+        }
+        let indirect = get_block_cache(block_id as usize, Arc::clone(block_device))
+            .lock()
+            .read_slice(|indirect: &[u32]| indirect.to_vec());
+        1 + indirect
+            .iter()
+            .map(|entry| Self::index_count(*entry, level - 1, block_device))
+            .sum::<u32>()
+    }
+    /// Release the data blocks from `from` on under the indirect block `root`
+    /// of `level`, and `root` itself if nothing is left under it
+    fn index_truncate(
+        root: &mut u32,
+        level: u32,
+        from: usize,
+        v: &mut Vec<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        if *root == 0 || from >= Self::entry_span(level + 1) {
+            return;
+        }
+        if level > 0 {
+            let span = Self::entry_span(level);
+            let block_cache = get_block_cache(*root as usize, Arc::clone(block_device));
+            if from == 0 {
+                // released as a whole, so left as it is
+                let mut indirect: Vec<u32> =
+                    block_cache.lock().read_slice(|indirect| indirect.to_vec());
+                for entry in indirect.iter_mut() {
+                    Self::index_truncate(entry, level - 1, 0, v, block_device);
+                }
+            } else {
+                block_cache.lock().modify_slice(|indirect: &mut [u32]| {
+                    for (index, entry) in indirect.iter_mut().enumerate().skip(from / span) {
+                        let from = from.saturating_sub(index * span);
+                        Self::index_truncate(entry, level - 1, from, v, block_device);
+                    }
+                });
+            }
+        }
+        if from == 0 {
+            v.push(*root);
+            *root = 0;
+        }
+    }
+    /// Number of blocks needed to back the bytes `start..end`: the holes among
+    /// their data blocks and the indirect blocks missing above them.
+    pub fn blocks_num_needed(
+        &self,
+        start: u32,
+        end: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> u32 {
+        let (first, last) = Self::block_range(start, end);
+        let mut needed = 0;
+        for inner_id in first..last.min(DIRECT_BOUND) {
+            needed += (self.direct[inner_id] == 0) as u32;
+        }
+        for (root, level, base) in self.indirect_roots() {
+            let bound = base + Self::entry_span(level + 1);
+            if first < bound && last > base {
+                let (first, last) = (first.max(base) - base, last.min(bound) - base);
+                needed += Self::index_needed(root, level, first, last, block_device);
+            }
+        }
+        needed
     }
     /// block id of the start block corresponding to the file offset for read_at/write_at.
+    ///
+    /// 0 for a hole, which reads as zeros.
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
         let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
-            self.direct[inner_id]
-        } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
-        } else {
-            let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect1: &IndirectBlock| {
-                    indirect1[last % INODE_INDIRECT1_COUNT]
-                })
+        if inner_id < DIRECT_BOUND {
+            return self.direct[inner_id];
         }
+        for (root, level, base) in self.indirect_roots() {
+            if inner_id < base + Self::entry_span(level + 1) {
+                return Self::index_lookup(root, level, inner_id - base, block_device);
+            }
+        }
+        panic!("block {} is beyond the max file size", inner_id);
+    }
+    /// Back the bytes `start..end` with `new_blocks`, which fill the holes
+    /// among their data blocks and the indirect blocks missing above them.
+    ///
+    /// The new blocks must be zeroed, as allocated blocks are.
+    pub fn fill_holes(
+        &mut self,
+        start: u32,
+        end: u32,
+        new_blocks: Vec<u32>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) {
+        let mut new_blocks = new_blocks.into_iter();
+        let mut fill = |entry: &mut u32| {
+            if *entry == 0 {
+                *entry = new_blocks.next().unwrap();
+            }
+            *entry
+        };
+        let (first, last) = Self::block_range(start, end);
+        for inner_id in first..last {
+            if inner_id < DIRECT_BOUND {
+                fill(&mut self.direct[inner_id]);
+                continue;
+            }
+            for (root, level, base) in self.indirect_roots_mut() {
+                if inner_id < base + Self::entry_span(level + 1) {
+                    Self::index_fill(root, level, inner_id - base, &mut fill, block_device);
+                    break;
+                }
+            }
+        }
+        assert!(new_blocks.next().is_none());
     }
     /// increase file length to new_size and allocate new blocks.
     pub fn increase_size(
@@ -183,147 +485,77 @@ impl DiskInode {
         new_blocks: Vec<u32>,
         block_device: &Arc<dyn BlockDevice>,
     ) {
-        let mut current_blocks = self.data_blocks();
+        assert!(new_size >= self.size);
+        self.fill_holes(self.size, new_size, new_blocks, block_device);
         self.size = new_size;
-        let mut total_blocks = self.data_blocks();
-        let mut new_blocks = new_blocks.into_iter();
-        // fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        // alloc indirect1
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                self.indirect1 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return;
+    }
+    /// Number of blocks backing the file, indirect blocks included
+    pub fn allocated_blocks(&self, block_device: &Arc<dyn BlockDevice>) -> u32 {
+        if self.is_inline() {
+            return 0;
         }
-        // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    indirect1[current_blocks as usize] = new_blocks.next().unwrap();
-                    current_blocks += 1;
-                }
-            });
-        // alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                self.indirect2 = new_blocks.next().unwrap();
-            }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32;
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return;
+        let mut total = self.direct.iter().filter(|entry| **entry != 0).count() as u32;
+        for (root, level, _) in self.indirect_roots() {
+            total += Self::index_count(root, level, block_device);
         }
-        // fill indirect2 from (a0, b0) -> (a1, b1)
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT;
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT;
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT;
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT;
-        // alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    if b0 == 0 {
-                        indirect2[a0] = new_blocks.next().unwrap();
-                    }
-                    // fill current
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap();
-                        });
-                    // move to next
-                    b0 += 1;
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        b0 = 0;
-                        a0 += 1;
-                    }
-                }
-            });
+        total
     }
 
-    /// Clear size to zero and return blocks that should be deallocated.
+    /// Decrease file length to new_size and return blocks that should be deallocated.
     ///
-    /// We will clear the block contents to zero later.
-    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+    /// Pointers to the released blocks left in kept indirect blocks are zeroed,
+    /// as is the tail of a partly kept block, so that growing the file again
+    /// reads zeros there.
+    pub fn decrease_size(
+        &mut self,
+        new_size: u32,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size);
+        let old_blocks = self.data_blocks() as usize;
+        let new_blocks = Self::_data_blocks(new_size) as usize;
+        let tail = new_size as usize % block_size();
+        if tail != 0 {
+            let block_id = self.get_block_id(new_blocks as u32 - 1, block_device);
+            if block_id != 0 {
+                let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+                let zero = |data_block: &mut [u8]| data_block[tail..].fill(0);
+                if self.is_file() {
+                    block_cache.lock().modify_data(zero);
+                } else {
+                    block_cache.lock().modify_slice(zero);
+                }
+            }
+        }
+        self.size = new_size;
         let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0;
-        let mut current_blocks = 0usize;
         // direct
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]);
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
-        // indirect1 block
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1);
-            data_blocks -= INODE_DIRECT_COUNT;
-            current_blocks = 0;
-        } else {
-            return v;
+        for direct in self.direct.iter_mut().take(old_blocks).skip(new_blocks) {
+            if *direct != 0 {
+                v.push(*direct);
+            }
+            *direct = 0;
         }
-        // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect1[current_blocks]);
-                    //indirect1[current_blocks] = 0;
-                    current_blocks += 1;
-                }
-            });
-        self.indirect1 = 0;
-        // indirect2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2);
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v;
+        // indirect1, indirect2 and indirect3 with the blocks under them
+        for (root, level, base) in self.indirect_roots_mut() {
+            if old_blocks > base {
+                let from = new_blocks.saturating_sub(base);
+                Self::index_truncate(root, level, from, &mut v, block_device);
+            }
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry);
-                            }
-                        });
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry);
-                            }
-                        });
-                    //indirect2[a1] = 0;
-                }
-            });
-        self.indirect2 = 0;
         v
     }
+    /// Clear size to zero and return blocks that should be deallocated.
+    ///
+    /// The block contents are zeroed when they are allocated again.
+    pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
+        if self.is_inline() {
+            self.size = 0;
+            self.direct.iter_mut().for_each(|v| *v = 0);
+            return Vec::new();
+        }
+        self.decrease_size(0, block_device)
+    }
     /// Read file data at offset position from inode into buf
     pub fn read_at(
         &self,
@@ -336,24 +568,31 @@ impl DiskInode {
         if start >= end {
             return 0;
         }
-        let mut start_block = start / BLOCK_SZ;
+        if self.is_inline() {
+            buf[..end - start].copy_from_slice(&self.inline_data()[start..end]);
+            return end - start;
+        }
+        let block_size = block_size();
+        let mut start_block = start / block_size;
         let mut read_size = 0usize;
         loop {
             // calculate end of current block
-            let mut end_current_block = (start / BLOCK_SZ + 1) * BLOCK_SZ;
+            let mut end_current_block = (start / block_size + 1) * block_size;
             end_current_block = end_current_block.min(end);
             // read and update read size
             let block_read_size = end_current_block - start;
             let dst = &mut buf[read_size..read_size + block_read_size];
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .read(0, |data_block: &DataBlock| {
-                let src = &data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_read_size];
-                dst.copy_from_slice(src);
-            });
+            match self.get_block_id(start_block as u32, block_device) {
+                // a hole
+                0 => dst.fill(0),
+                block_id => get_block_cache(block_id as usize, Arc::clone(block_device))
+                    .lock()
+                    .read_slice(|data_block: &[u8]| {
+                        let src =
+                            &data_block[start % block_size..start % block_size + block_read_size];
+                        dst.copy_from_slice(src);
+                    }),
+            }
             read_size += block_read_size;
             // move to next block
             if end_current_block == end {
@@ -373,25 +612,33 @@ impl DiskInode {
     ) -> usize {
         let mut start = offset;
         let end = (offset + buf.len()).min(self.size as usize);
-        assert!(start <= end);
-        let mut start_block = start / BLOCK_SZ;
+        if start >= end {
+            return 0;
+        }
+        let block_size = block_size();
+        let mut start_block = start / block_size;
         let mut write_size = 0usize;
         loop {
             // calculate end of current block
-            let mut end_current_block = (start / BLOCK_SZ + 1) * BLOCK_SZ;
+            let mut end_current_block = (start / block_size + 1) * block_size;
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            let block_id = self.get_block_id(start_block as u32, block_device);
+            assert!(block_id != 0, "write to a hole");
+            let copy = |data_block: &mut [u8]| {
                 let src = &buf[write_size..write_size + block_write_size];
-                let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
+                let dst =
+                    &mut data_block[start % block_size..start % block_size + block_write_size];
                 dst.copy_from_slice(src);
-            });
+            };
+            let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+            // file data is not journaled, directory entries are
+            if self.is_file() {
+                block_cache.lock().modify_data(copy);
+            } else {
+                block_cache.lock().modify_slice(copy);
+            }
             write_size += block_write_size;
             // move to next block
             if end_current_block == end {
@@ -6,10 +6,11 @@
 //!
 //! They( [`DiskInode`] in disk and [`Inode`] in memory) have different access modes. For the disk, you need to send a request to the disk in a software way to indirectly read and write. so, we also nee to pay special attention to which data structures are store on disk and which are stored in memory.
 //!
-//! easy-fs itself is divided into different levels, forming a hierarchical and modular design architecture. The easy-fs crate can be roughly divided into five different levels from bottom to top:
+//! easy-fs itself is divided into different levels, forming a hierarchical and modular design architecture. The easy-fs crate can be roughly divided into six different levels from bottom to top:
 //!
 //! - Disk block device interface layer
 //! - Block cache layer
+//! - Write-ahead journal layer
 //! - Disk layout & data structure layer
 //! - Disk block manager layer
 //! - index node(inode, namely file control block) layer
@@ -21,14 +22,24 @@ extern crate alloc;
 pub mod bitmap;
 pub mod block_cache;
 pub mod block_dev;
+pub mod clock;
 pub mod efs;
+pub mod journal;
 pub mod layout;
 pub mod vfs;
 
-pub const BLOCK_SZ: usize = 512;
+/// The size of a sector of the block device, the smallest block size
+pub const SECTOR_SZ: usize = 512;
+/// The largest block size
+pub const MAX_BLOCK_SZ: usize = 4096;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+pub use block_cache::{block_cache_drop_all, block_size};
+use block_cache::{
+    block_cache_sync_all, block_cache_sync_step, get_block_cache, set_block_size, set_journal,
+};
 pub use block_dev::BlockDevice;
+pub use clock::set_clock;
 pub use efs::EasyFileSystem;
+use journal::Journal;
 pub use layout::*;
-pub use vfs::Inode;
+pub use vfs::{Inode, InodeRefs, RenameMode};
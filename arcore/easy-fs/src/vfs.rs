@@ -4,15 +4,56 @@
 //!
 //! NOTICE: The difference between [`Inode`] and [`DiskInode`]  can be seen from their names: DiskInode in a relatively fixed location within the disk block, while Inode Is a data structure placed in memory that records file inode information.
 use super::{
-    block_cache_sync_all, get_block_cache, BlockDevice, DirEntry, DiskInode, DiskInodeType,
-    EasyFileSystem, DIRENT_SZ,
+    block_cache_sync_all, block_cache_sync_step, block_size, clock::now, get_block_cache,
+    max_file_size, BlockDevice, DirEntry, DiskInode, DiskInodeType, EasyFileSystem, DIRENT_SZ,
+    NAME_LENGTH_LIMIT, SYMLINK_INLINE_LIMIT, SYMLINK_LENGTH_LIMIT,
 };
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use spin::{Mutex, MutexGuard};
+
+/// How long a read leaves the access time alone, in nanoseconds
+const ATIME_PERIOD: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// How many data blocks a write or a shrink allocates or frees per
+/// transaction, which keeps the metadata they modify within the journal
+const TRANSACTION_BLOCKS: usize = 64;
+
+/// In-memory references to the inodes of a file system
+///
+/// An inode whose last link goes away while it is referenced becomes an
+/// orphan and is freed when its last [`Inode`] is dropped, i.e. on the
+/// final close.
+#[derive(Default)]
+pub struct InodeRefs {
+    /// inode id -> number of live [`Inode`]s
+    counts: BTreeMap<u32, usize>,
+    /// unlinked inodes still referenced
+    orphans: BTreeSet<u32>,
+}
+
+/// How [`Inode::rename`] treats an entry already at the new name
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RenameMode {
+    /// replace it, a directory only with an empty directory
+    Replace,
+    /// fail
+    NoReplace,
+    /// swap the two entries
+    Exchange,
+}
+
+/// The name of the entry keeping an inode reachable during an exchange,
+/// it cannot be looked up as it contains a slash
+const EXCHANGE_SPARE: &str = "/exchange";
+
 /// Inode struct in memory
 pub struct Inode {
+    /// The inode id
+    inode_id: u32,
     /// The block id of the inode
     block_id: usize,
     /// The offset of the inode in the block
@@ -21,6 +62,8 @@ pub struct Inode {
     fs: Arc<Mutex<EasyFileSystem>>,
     /// The block device
     block_device: Arc<dyn BlockDevice>,
+    /// The references of all inodes of the file system
+    refs: Arc<Mutex<InodeRefs>>,
 }
 
 impl Inode {
@@ -28,18 +71,39 @@ impl Inode {
     ///
     /// We should not acquire efs lock here.
     pub fn new(
+        inode_id: u32,
         block_id: u32,
         block_offset: usize,
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
+        refs: Arc<Mutex<InodeRefs>>,
     ) -> Self {
+        *refs.lock().counts.entry(inode_id).or_insert(0) += 1;
         Self {
+            inode_id,
             block_id: block_id as usize,
             block_offset,
             fs,
             block_device,
+            refs,
         }
     }
+    /// Get the inode id
+    pub fn inode_id(&self) -> u32 {
+        self.inode_id
+    }
+    /// Create the in-memory inode of `inode_id`, with the efs lock held
+    fn inode_of(&self, inode_id: u32, fs: &EasyFileSystem) -> Arc<Inode> {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        Arc::new(Self::new(
+            inode_id,
+            block_id,
+            block_offset,
+            self.fs.clone(),
+            self.block_device.clone(),
+            self.refs.clone(),
+        ))
+    }
     /// read the content of the disk inode on disk with 'f' function
     fn read_disk_inode<V>(&self, f: impl FnOnce(&DiskInode) -> V) -> V {
         get_block_cache(self.block_id, Arc::clone(&self.block_device))
@@ -54,8 +118,14 @@ impl Inode {
     }
     /// find the disk inode id according to the file with 'name' by search the directory entries in the disk inode with Directory type
     fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
-        // assert it is a directory
-        assert!(disk_inode.is_dir());
+        self.find_dirent(name, disk_inode)
+            .map(|(_, inode_id)| inode_id)
+    }
+    /// find the index and inode id of the directory entry 'name'
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<(usize, u32)> {
+        if !disk_inode.is_dir() {
+            return None;
+        }
         let file_count = (disk_inode.size as usize) / DIRENT_SZ;
         let mut dirent = DirEntry::empty();
         for i in 0..file_count {
@@ -64,26 +134,117 @@ impl Inode {
                 DIRENT_SZ,
             );
             if dirent.name() == name {
-                return Some(dirent.inode_id() as u32);
+                return Some((i, dirent.inode_id()));
             }
         }
         None
     }
+    /// append the directory entry 'name' for 'inode_id'
+    fn add_dirent(&self, name: &str, inode_id: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        self.modify_disk_inode(|dir_inode| {
+            let file_count = (dir_inode.size as usize) / DIRENT_SZ;
+            self.increase_size(((file_count + 1) * DIRENT_SZ) as u32, dir_inode, fs);
+            let dirent = DirEntry::new(name, inode_id);
+            dir_inode.write_at(
+                file_count * DIRENT_SZ,
+                dirent.as_bytes(),
+                &self.block_device,
+            );
+        });
+    }
+    /// point the directory entry at 'index' to 'inode_id', a single write
+    fn set_dirent(&self, index: usize, inode_id: u32) {
+        self.modify_disk_inode(|dir_inode| {
+            let mut dirent = DirEntry::empty();
+            dir_inode.read_at(index * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+            let dirent = DirEntry::new(dirent.name(), inode_id);
+            dir_inode.write_at(index * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+        });
+    }
+    /// point `..` of this directory to 'parent_id'
+    fn set_parent(&self, parent_id: u32) {
+        if let Some((index, _)) =
+            self.read_disk_inode(|dir_inode| self.find_dirent("..", dir_inode))
+        {
+            self.set_dirent(index, parent_id);
+        }
+    }
+    /// remove the directory entry at 'index', moving the last entry into its
+    /// place and releasing the blocks the directory no longer needs
+    fn remove_dirent(
+        &self,
+        index: usize,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+        let last = file_count - 1;
+        if index != last {
+            let mut dirent = DirEntry::empty();
+            disk_inode.read_at(last * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device);
+            disk_inode.write_at(index * DIRENT_SZ, dirent.as_bytes(), &self.block_device);
+        }
+        let data_blocks_dealloc =
+            disk_inode.decrease_size((last * DIRENT_SZ) as u32, &self.block_device);
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.dealloc_data(data_block);
+        }
+    }
     /// find the disk inode of the file with 'name'
     pub fn find(&self, name: &str) -> Option<Arc<Inode>> {
         let fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            self.find_inode_id(name, disk_inode).map(|inode_id| {
-                let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
-                Arc::new(Self::new(
-                    block_id,
-                    block_offset,
-                    self.fs.clone(),
-                    self.block_device.clone(),
-                ))
-            })
+            self.find_inode_id(name, disk_inode)
+                .map(|inode_id| self.inode_of(inode_id, &fs))
         })
     }
+    /// find the name of the entry for 'inode_id' in this directory, `.` and
+    /// `..` aside
+    pub fn find_name(&self, inode_id: u32) -> Option<String> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let file_count = (disk_inode.size as usize) / DIRENT_SZ;
+            let mut dirent = DirEntry::empty();
+            for i in 0..file_count {
+                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device);
+                if dirent.inode_id() == inode_id && dirent.name() != "." && dirent.name() != ".." {
+                    return Some(String::from(dirent.name()));
+                }
+            }
+            None
+        })
+    }
+    /// find the inode at 'path' relative to this directory, walking one
+    /// component at a time
+    ///
+    /// Empty components and `.` stay in place. `..` follows the `..` entry,
+    /// a directory without one (the root of an old image) is its own parent.
+    /// Symbolic links are not followed, the kernel walks paths itself.
+    pub fn find_path(self: &Arc<Self>, path: &str) -> Option<Arc<Inode>> {
+        let mut inode = Arc::clone(self);
+        for name in path.split('/') {
+            if name.is_empty() || name == "." {
+                if !inode.is_dir() {
+                    return None;
+                }
+                continue;
+            }
+            inode = match inode.find(name) {
+                Some(next) => next,
+                None if name == ".." && inode.is_dir() => inode,
+                None => return None,
+            };
+        }
+        Some(inode)
+    }
+    /// inode is directory?
+    pub fn is_dir(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+    /// inode is symbolic link?
+    pub fn is_symlink(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
+    }
     /// increase the size of file( also known as 'disk inode')
     fn increase_size(
         &self,
@@ -94,66 +255,552 @@ impl Inode {
         if new_size < disk_inode.size {
             return;
         }
-        let blocks_needed = disk_inode.blocks_num_needed(new_size);
+        self.fill_holes(disk_inode.size, new_size, disk_inode, fs);
+        disk_inode.size = new_size;
+    }
+    /// allocate the blocks missing under the bytes 'start..end'
+    fn fill_holes(
+        &self,
+        start: u32,
+        end: u32,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let blocks_needed = disk_inode.blocks_num_needed(start, end, &self.block_device);
+        if blocks_needed == 0 {
+            return;
+        }
         let mut v: Vec<u32> = Vec::new();
         for _ in 0..blocks_needed {
             v.push(fs.alloc_data());
         }
-        disk_inode.increase_size(new_size, v, &self.block_device);
+        disk_inode.fill_holes(start, end, v, &self.block_device);
     }
-    /// create a file with 'name' in the root directory
+    /// create a file with 'name' in this directory, owned by root
     pub fn create(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_as(name, 0o644, (0, 0))
+    }
+    /// create a file with 'name' in this directory, with 'mode' and 'owner'
+    /// (uid, gid) written along with it
+    pub fn create_as(&self, name: &str, mode: u16, owner: (u32, u32)) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::File, &[], mode, owner)
+    }
+    /// create a directory with 'name' in this directory, with `.` and `..`
+    /// entries, owned by root
+    pub fn create_dir(&self, name: &str) -> Option<Arc<Inode>> {
+        self.create_dir_as(name, 0o755, (0, 0))
+    }
+    /// create a directory with 'name' in this directory, with 'mode' and
+    /// 'owner' written along with it
+    pub fn create_dir_as(&self, name: &str, mode: u16, owner: (u32, u32)) -> Option<Arc<Inode>> {
+        self.create_inode(name, DiskInodeType::Directory, &[], mode, owner)
+    }
+    /// create a symbolic link with 'name' in this directory pointing to
+    /// 'target', owned by root
+    pub fn create_symlink(&self, name: &str, target: &str) -> Option<Arc<Inode>> {
+        self.create_symlink_as(name, target, (0, 0))
+    }
+    /// create a symbolic link with 'name' in this directory pointing to
+    /// 'target', with 'owner' written along with it
+    pub fn create_symlink_as(
+        &self,
+        name: &str,
+        target: &str,
+        owner: (u32, u32),
+    ) -> Option<Arc<Inode>> {
+        if target.is_empty() || target.len() > SYMLINK_LENGTH_LIMIT {
+            return None;
+        }
+        self.create_inode(
+            name,
+            DiskInodeType::Symlink,
+            target.as_bytes(),
+            0o777,
+            owner,
+        )
+    }
+    /// create an inode of 'type_' holding 'content' and link it as 'name' in
+    /// this directory, with its mode and owner in the same transaction
+    fn create_inode(
+        &self,
+        name: &str,
+        type_: DiskInodeType,
+        content: &[u8],
+        mode: u16,
+        owner: (u32, u32),
+    ) -> Option<Arc<Inode>> {
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT || name.contains('/') {
+            return None;
+        }
         let mut fs = self.fs.lock();
-        let op = |root_inode: &mut DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
-            // has the file been created?
-            self.find_inode_id(name, root_inode)
+        let op = |dir_inode: &DiskInode| {
+            // has the file been created? also None if this is not a directory,
+            // or a removed one that is only still open
+            dir_inode.is_dir()
+                && dir_inode.nlink > 0
+                && self.find_inode_id(name, dir_inode).is_none()
         };
-        if self.modify_disk_inode(op).is_some() {
+        if !self.read_disk_inode(op) {
             return None;
         }
-        // create a new file
-        // alloc a inode with an indirect block
+        // create a new inode
         let new_inode_id = fs.alloc_inode();
+        let now = now();
+        let is_dir = type_ == DiskInodeType::Directory;
         // initialize inode
         let (new_inode_block_id, new_inode_block_offset) = fs.get_disk_inode_pos(new_inode_id);
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(DiskInodeType::File);
+                new_inode.initialize(type_);
+                // the entry in this directory, and "." of a directory
+                new_inode.nlink = if is_dir { 2 } else { 1 };
+                new_inode.mode = mode & 0o7777;
+                (new_inode.uid, new_inode.gid) = owner;
+                new_inode.set_times(now);
+            });
+        let new_inode = self.inode_of(new_inode_id, &fs);
+        if is_dir {
+            new_inode.modify_disk_inode(|dir_inode| {
+                self.increase_size(2 * DIRENT_SZ as u32, dir_inode, &mut fs);
+                let dot = DirEntry::new(".", new_inode_id);
+                let dotdot = DirEntry::new("..", self.inode_id);
+                dir_inode.write_at(0, dot.as_bytes(), &self.block_device);
+                dir_inode.write_at(DIRENT_SZ, dotdot.as_bytes(), &self.block_device);
+            });
+        }
+        if !content.is_empty() {
+            new_inode.modify_disk_inode(|disk_inode| {
+                if content.len() <= SYMLINK_INLINE_LIMIT {
+                    disk_inode.write_inline(content);
+                } else {
+                    self.increase_size(content.len() as u32, disk_inode, &mut fs);
+                    disk_inode.write_at(0, content, &self.block_device);
+                }
             });
-        self.modify_disk_inode(|root_inode| {
+        }
+        self.modify_disk_inode(|dir_inode| {
             // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
+            let file_count = (dir_inode.size as usize) / DIRENT_SZ;
             let new_size = (file_count + 1) * DIRENT_SZ;
             // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
+            self.increase_size(new_size as u32, dir_inode, &mut fs);
             // write dirent
             let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
+            dir_inode.write_at(
                 file_count * DIRENT_SZ,
                 dirent.as_bytes(),
                 &self.block_device,
             );
+            // ".." of the new directory
+            if is_dir {
+                dir_inode.nlink += 1;
+            }
+            dir_inode.mtime = now;
+            dir_inode.ctime = now;
         });
 
-        let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
         block_cache_sync_all();
         // return inode
-        Some(Arc::new(Self::new(
-            block_id,
-            block_offset,
-            self.fs.clone(),
-            self.block_device.clone(),
-        )))
+        Some(new_inode)
         // release efs lock automatically by compiler
     }
-    /// create a directory with 'name' in the root directory
+    /// Get the size of the inode in bytes
+    pub fn size(&self) -> u32 {
+        self.read_disk_inode(|disk_inode| disk_inode.size)
+    }
+    /// Get the number of blocks the inode occupies, index blocks included
+    pub fn blocks(&self) -> u32 {
+        self.read_disk_inode(|disk_inode| disk_inode.allocated_blocks(&self.block_device))
+    }
+    /// Get the target of a symbolic link, `None` if this is not one
+    pub fn read_link(&self) -> Option<String> {
+        if !self.is_symlink() {
+            return None;
+        }
+        let mut target = vec![0u8; self.size() as usize];
+        let len = self.read_at(0, &mut target);
+        target.truncate(len);
+        String::from_utf8(target).ok()
+    }
+    /// Get the number of links to the inode
+    pub fn nlink(&self) -> u32 {
+        self.read_disk_inode(|disk_inode| disk_inode.nlink as u32)
+    }
+    /// Get the permission bits of the inode
+    pub fn mode(&self) -> u16 {
+        self.read_disk_inode(|disk_inode| disk_inode.mode)
+    }
+    /// Get the user and group ids of the owner
+    pub fn owner(&self) -> (u32, u32) {
+        self.read_disk_inode(|disk_inode| (disk_inode.uid, disk_inode.gid))
+    }
+    /// Get the access, modification and change times, in nanoseconds since the epoch
+    pub fn times(&self) -> (u64, u64, u64) {
+        self.read_disk_inode(|disk_inode| (disk_inode.atime, disk_inode.mtime, disk_inode.ctime))
+    }
+    /// Set the permission bits of the inode
+    pub fn set_mode(&self, mode: u16) {
+        let now = now();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.mode = mode & 0o7777;
+            disk_inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
+    /// Set the owner of the inode
+    pub fn set_owner(&self, uid: u32, gid: u32) {
+        let now = now();
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.uid = uid;
+            disk_inode.gid = gid;
+            disk_inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
+    /// Set the access and modification times, `None` leaves one as it is
+    pub fn set_times(&self, atime: Option<u64>, mtime: Option<u64>) {
+        let now = now();
+        self.modify_disk_inode(|disk_inode| {
+            if let Some(atime) = atime {
+                disk_inode.atime = atime;
+            }
+            if let Some(mtime) = mtime {
+                disk_inode.mtime = mtime;
+            }
+            disk_inode.ctime = now;
+        });
+        block_cache_sync_all();
+    }
+    /// link 'target' as 'name' in this directory, 'target' must not be a directory
     ///
-    /// list the file names in the root directory
+    /// Neither this directory nor 'target' may be removed and only still
+    /// open, their blocks go with their last reference.
+    pub fn link(&self, name: &str, target: &Inode) -> bool {
+        if name.is_empty() || name.len() > NAME_LENGTH_LIMIT || name.contains('/') {
+            return false;
+        }
+        if target.is_dir() {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let op = |dir_inode: &DiskInode| {
+            dir_inode.is_dir()
+                && dir_inode.nlink > 0
+                && self.find_inode_id(name, dir_inode).is_none()
+        };
+        if !self.read_disk_inode(op) || target.nlink() == 0 {
+            return false;
+        }
+        let now = now();
+        self.add_dirent(name, target.inode_id, &mut fs);
+        self.touch(now);
+        target.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.ctime = now;
+        });
+        block_cache_sync_all();
+        true
+    }
+    /// remove the entry 'name' from this directory, a directory must be empty
+    ///
+    /// The inode is freed with its last link, or on the final close if it is
+    /// still open.
+    pub fn unlink(&self, name: &str) -> bool {
+        if name == "." || name == ".." {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let (index, inode_id) =
+            match self.read_disk_inode(|dir_inode| self.find_dirent(name, dir_inode)) {
+                Some(dirent) => dirent,
+                None => return false,
+            };
+        let target = self.inode_of(inode_id, &fs);
+        let is_dir = target.is_dir();
+        if is_dir && target.read_disk_inode(|disk_inode| disk_inode.size as usize > 2 * DIRENT_SZ) {
+            return false;
+        }
+        // a file going with its last name is emptied first, while it is
+        // still reachable, as that may take several transactions
+        let last_ref = self.refs.lock().counts[&inode_id] == 1;
+        if !is_dir && last_ref && target.read_disk_inode(|disk_inode| disk_inode.nlink) == 1 {
+            target.shrink(0, &mut fs);
+        }
+        let now = now();
+        self.modify_disk_inode(|dir_inode| {
+            self.remove_dirent(index, dir_inode, &mut fs);
+            // ".." of the removed directory
+            if is_dir {
+                dir_inode.nlink -= 1;
+            }
+            dir_inode.mtime = now;
+            dir_inode.ctime = now;
+        });
+        let nlink = target.modify_disk_inode(|disk_inode| {
+            // a directory loses its "." along with the entry
+            disk_inode.nlink = match is_dir {
+                true => 0,
+                false => disk_inode.nlink - 1,
+            };
+            disk_inode.ctime = now;
+            disk_inode.nlink
+        });
+        if nlink == 0 {
+            // freed when `target`, the last reference if not open, is dropped
+            self.refs.lock().orphans.insert(inode_id);
+        }
+        drop(fs);
+        drop(target);
+        block_cache_sync_all();
+        true
+    }
+    /// set the modification and change times of this directory to 'now'
+    fn touch(&self, now: u64) {
+        self.modify_disk_inode(|dir_inode| {
+            dir_inode.mtime = now;
+            dir_inode.ctime = now;
+        });
+    }
+    /// add 'delta' to the link count
+    fn add_nlink(&self, delta: i16) {
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink = disk_inode.nlink.wrapping_add_signed(delta);
+        });
+    }
+    /// is this directory the one of 'ancestor_id' or inside it? follows `..`
+    fn within(&self, ancestor_id: u32, fs: &EasyFileSystem) -> bool {
+        let mut inode_id = self.inode_id;
+        loop {
+            if inode_id == ancestor_id {
+                return true;
+            }
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            let parent_id = get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(block_offset, |disk_inode: &DiskInode| {
+                    self.find_inode_id("..", disk_inode)
+                });
+            match parent_id {
+                Some(parent_id) if parent_id != inode_id => inode_id = parent_id,
+                _ => return false,
+            }
+        }
+    }
+    /// is this directory 'dir' or inside it?
+    pub fn is_within(&self, dir: &Inode) -> bool {
+        let fs = self.fs.lock();
+        self.within(dir.inode_id, &fs)
+    }
+    /// move the entry 'old_name' of this directory to 'new_name' in 'new_dir'
+    ///
+    /// An entry at the new name is treated as 'mode' says. A directory can
+    /// not be moved inside itself. Renaming a name to another name of the
+    /// same inode does nothing.
+    ///
+    /// With a journal the rename is one transaction. On an image without
+    /// one, the steps are synced one by one in an order that keeps every
+    /// inode reachable, a crash in between leaves an extra name at worst, and
+    /// link counts no lower than the names on disk.
+    pub fn rename(
+        &self,
+        old_name: &str,
+        new_dir: &Inode,
+        new_name: &str,
+        mode: RenameMode,
+    ) -> bool {
+        if [old_name, new_name]
+            .iter()
+            .any(|name| *name == "." || *name == "..")
+            || new_name.is_empty()
+            || new_name.len() > NAME_LENGTH_LIMIT
+            || new_name.contains('/')
+        {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let old_dirent = self.read_disk_inode(|dir_inode| self.find_dirent(old_name, dir_inode));
+        let (old_index, inode_id) = match old_dirent {
+            // a removed directory that is still open takes no new entries
+            Some(dirent) if new_dir.is_dir() && new_dir.nlink() > 0 => dirent,
+            _ => return false,
+        };
+        let new_dirent =
+            new_dir.read_disk_inode(|dir_inode| new_dir.find_dirent(new_name, dir_inode));
+        let inode = self.inode_of(inode_id, &fs);
+        if inode.is_dir() && new_dir.within(inode_id, &fs) {
+            return false;
+        }
+        let now = now();
+        let replaced = match (mode, new_dirent) {
+            (RenameMode::NoReplace, Some(_)) | (RenameMode::Exchange, None) => return false,
+            (_, None) => {
+                self.move_dirent(old_index, &inode, new_dir, None, new_name, now, &mut fs);
+                None
+            }
+            (_, Some((_, other_id))) if other_id == inode_id => None,
+            (RenameMode::Exchange, Some((new_index, other_id))) => {
+                let other = self.inode_of(other_id, &fs);
+                if other.is_dir() && self.within(other_id, &fs) {
+                    return false;
+                }
+                self.exchange(old_index, &inode, new_dir, new_index, &other, now, &mut fs);
+                None
+            }
+            (_, Some((new_index, other_id))) => {
+                let other = self.inode_of(other_id, &fs);
+                let other_is_dir = other.is_dir();
+                let other_is_empty =
+                    other.read_disk_inode(|disk_inode| disk_inode.size as usize <= 2 * DIRENT_SZ);
+                if inode.is_dir() != other_is_dir || (other_is_dir && !other_is_empty) {
+                    return false;
+                }
+                // as in unlink, a file going with its last name is emptied
+                // first, which may take several transactions
+                let last_ref = self.refs.lock().counts[&other_id] == 1;
+                if !other_is_dir
+                    && last_ref
+                    && other.read_disk_inode(|disk_inode| disk_inode.nlink) == 1
+                {
+                    other.shrink(0, &mut fs);
+                }
+                self.move_dirent(
+                    old_index,
+                    &inode,
+                    new_dir,
+                    Some(new_index),
+                    new_name,
+                    now,
+                    &mut fs,
+                );
+                // the replaced inode loses its name, and a directory its `.` and `..`
+                if other_is_dir {
+                    new_dir.add_nlink(-1);
+                }
+                let nlink = other.modify_disk_inode(|disk_inode| {
+                    disk_inode.nlink = match other_is_dir {
+                        true => 0,
+                        false => disk_inode.nlink - 1,
+                    };
+                    disk_inode.ctime = now;
+                    disk_inode.nlink
+                });
+                if nlink == 0 {
+                    self.refs.lock().orphans.insert(other_id);
+                }
+                Some(other)
+            }
+        };
+        drop(fs);
+        // freed here if it was the last reference
+        drop(replaced);
+        block_cache_sync_all();
+        true
+    }
+    /// move the entry at 'old_index' of this directory, naming 'inode', to
+    /// 'new_dir', as the entry at 'new_index' or a new entry 'new_name'
+    #[allow(clippy::too_many_arguments)]
+    fn move_dirent(
+        &self,
+        old_index: usize,
+        inode: &Inode,
+        new_dir: &Inode,
+        new_index: Option<usize>,
+        new_name: &str,
+        now: u64,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let reparent = inode.is_dir() && self.inode_id != new_dir.inode_id;
+        // count the name before it is written
+        inode.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.ctime = now;
+        });
+        if reparent {
+            new_dir.add_nlink(1);
+        }
+        block_cache_sync_step();
+        // the new name, replacing an entry with one write
+        match new_index {
+            Some(index) => new_dir.set_dirent(index, inode.inode_id),
+            None => new_dir.add_dirent(new_name, inode.inode_id, fs),
+        }
+        new_dir.touch(now);
+        block_cache_sync_step();
+        if reparent {
+            inode.set_parent(new_dir.inode_id);
+            block_cache_sync_step();
+        }
+        // the old name, and its count
+        self.modify_disk_inode(|dir_inode| self.remove_dirent(old_index, dir_inode, fs));
+        if reparent {
+            self.add_nlink(-1);
+        }
+        self.touch(now);
+        inode.add_nlink(-1);
+        block_cache_sync_step();
+    }
+    /// swap 'inode' at 'old_index' of this directory and 'other' at
+    /// 'new_index' of 'new_dir'
+    ///
+    /// A spare entry names 'inode' while its entry is rewritten, so a crash
+    /// without a journal leaves it with a name that `ls` shows but a path
+    /// cannot reach.
+    #[allow(clippy::too_many_arguments)]
+    fn exchange(
+        &self,
+        old_index: usize,
+        inode: &Inode,
+        new_dir: &Inode,
+        new_index: usize,
+        other: &Inode,
+        now: u64,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        inode.add_nlink(1);
+        self.add_dirent(EXCHANGE_SPARE, inode.inode_id, fs);
+        block_cache_sync_step();
+        other.add_nlink(1);
+        self.set_dirent(old_index, other.inode_id);
+        block_cache_sync_step();
+        new_dir.set_dirent(new_index, inode.inode_id);
+        block_cache_sync_step();
+        if self.inode_id != new_dir.inode_id {
+            let (is_dir, other_is_dir) = (inode.is_dir(), other.is_dir());
+            if is_dir {
+                inode.set_parent(new_dir.inode_id);
+            }
+            if other_is_dir {
+                other.set_parent(self.inode_id);
+            }
+            self.add_nlink(other_is_dir as i16 - is_dir as i16);
+            new_dir.add_nlink(is_dir as i16 - other_is_dir as i16);
+            block_cache_sync_step();
+        }
+        // the spare is the last entry
+        self.modify_disk_inode(|dir_inode| {
+            let index = self.find_dirent(EXCHANGE_SPARE, dir_inode).unwrap().0;
+            self.remove_dirent(index, dir_inode, fs);
+        });
+        for changed in [inode, other] {
+            changed.modify_disk_inode(|disk_inode| {
+                disk_inode.nlink -= 1;
+                disk_inode.ctime = now;
+            });
+        }
+        self.touch(now);
+        new_dir.touch(now);
+        block_cache_sync_step();
+    }
+    /// free the data blocks and the disk inode of an orphan
+    fn free(&self, fs: &mut MutexGuard<EasyFileSystem>) {
+        self.shrink(0, fs);
+        self.modify_disk_inode(|disk_inode| disk_inode.initialize(DiskInodeType::File));
+        fs.dealloc_inode(self.inode_id);
+        block_cache_sync_all();
+    }
+    /// list the file names in this directory, without `.` and `..`
     pub fn ls(&self) -> Vec<String> {
-        
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
             let file_count = (disk_inode.size as usize) / DIRENT_SZ;
@@ -164,37 +811,178 @@ impl Inode {
                     disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device,),
                     DIRENT_SZ,
                 );
-                v.push(String::from(dirent.name()));
+                if dirent.name() != "." && dirent.name() != ".." {
+                    v.push(String::from(dirent.name()));
+                }
             }
             v
         })
     }
     /// Read the content in offset position of the file into 'buf'
+    ///
+    /// As with relatime, the access time is only written when it is not
+    /// newer than the last change, or is a day old, and is committed at
+    /// once so that reads never pile up blocks for the next transaction.
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
+        let now = now();
+        let stale = self.read_disk_inode(|disk_inode| {
+            let atime = disk_inode.atime;
+            atime <= disk_inode.mtime
+                || atime <= disk_inode.ctime
+                || now.saturating_sub(atime) >= ATIME_PERIOD
+        });
+        if stale {
+            self.modify_disk_inode(|disk_inode| disk_inode.atime = now);
+            block_cache_sync_all();
+        }
         self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
     }
     /// Write the content in 'buf' into offset position of the file
+    ///
+    /// Blocks are only allocated for the bytes written, writing past the end
+    /// leaves a hole in between.
+    ///
+    /// A large write is committed a few blocks at a time, so a crash may
+    /// leave part of it. The bytes past the max file size are not written.
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let end = offset.saturating_add(buf.len()).min(max_file_size());
+        if offset >= end {
+            return 0;
+        }
+        let mut fs = self.fs.lock();
+        let now = now();
+        let step = TRANSACTION_BLOCKS * block_size();
+        let mut start = offset;
+        let mut size = 0;
+        loop {
+            let step_end = ((start / step + 1) * step).min(end);
+            size += self.modify_disk_inode(|disk_inode| {
+                self.fill_holes(start as u32, step_end as u32, disk_inode, &mut fs);
+                disk_inode.size = disk_inode.size.max(step_end as u32);
+                disk_inode.mtime = now;
+                disk_inode.ctime = now;
+                let src = &buf[start - offset..step_end - offset];
+                disk_inode.write_at(start, src, &self.block_device)
+            });
+            block_cache_sync_all();
+            if step_end == end {
+                break;
+            }
+            start = step_end;
+        }
+        size
+    }
+    /// free the blocks past 'new_size' a few at a time from the end of the
+    /// file, leaving the last ones to the transaction of the caller
+    fn shrink(&self, new_size: u32, fs: &mut MutexGuard<EasyFileSystem>) {
+        let block_size = block_size();
+        let new_blocks = (new_size as usize + block_size - 1) / block_size;
+        loop {
+            let step_size = self.modify_disk_inode(|disk_inode| {
+                let mut inner_id = match disk_inode.is_inline() {
+                    true => 0,
+                    false => disk_inode.data_blocks() as usize,
+                };
+                let mut count = 0;
+                while inner_id > new_blocks && count < TRANSACTION_BLOCKS {
+                    inner_id -= 1;
+                    if disk_inode.get_block_id(inner_id as u32, &self.block_device) != 0 {
+                        count += 1;
+                    }
+                }
+                let step_size = match inner_id > new_blocks {
+                    true => (inner_id * block_size) as u32,
+                    false => new_size,
+                };
+                let data_blocks_dealloc = match step_size {
+                    0 => disk_inode.clear_size(&self.block_device),
+                    _ => disk_inode.decrease_size(step_size, &self.block_device),
+                };
+                for data_block in data_blocks_dealloc.into_iter() {
+                    fs.dealloc_data(data_block);
+                }
+                step_size
+            });
+            if step_size == new_size {
+                break;
+            }
+            block_cache_sync_all();
+        }
+    }
+    /// Set the file length to 'new_size', freeing the blocks past it or
+    /// growing the file by a hole, false if it is too large
+    pub fn truncate(&self, new_size: usize) -> bool {
+        if new_size > max_file_size() {
+            return false;
+        }
         let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs);
-            disk_inode.write_at(offset, buf, &self.block_device)
+        let now = now();
+        if (new_size as u32) < self.read_disk_inode(|disk_inode| disk_inode.size) {
+            self.shrink(new_size as u32, &mut fs);
+        }
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.size = new_size as u32;
+            disk_inode.mtime = now;
+            disk_inode.ctime = now;
         });
         block_cache_sync_all();
-        size
+        true
+    }
+    /// The first offset at or after 'offset' in data, `None` if there is
+    /// none before the end
+    pub fn seek_data(&self, offset: usize) -> Option<usize> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            (offset / block_size()..disk_inode.data_blocks() as usize)
+                .find(|&inner_id| disk_inode.get_block_id(inner_id as u32, &self.block_device) != 0)
+                .map(|inner_id| offset.max(inner_id * block_size()))
+                .filter(|&data| data < disk_inode.size as usize)
+        })
+    }
+    /// The first offset at or after 'offset' in a hole, where the end of the
+    /// file counts as one, `None` past the end
+    pub fn seek_hole(&self, offset: usize) -> Option<usize> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            let size = disk_inode.size as usize;
+            if offset >= size {
+                return None;
+            }
+            let hole = (offset / block_size()..disk_inode.data_blocks() as usize)
+                .find(|&inner_id| disk_inode.get_block_id(inner_id as u32, &self.block_device) == 0)
+                .map_or(size, |inner_id| offset.max(inner_id * block_size()));
+            Some(hole.min(size))
+        })
     }
     /// Set the file(disk inode) length to zero, delloc all data blocks of the file.
     pub fn clear(&self) {
         let mut fs = self.fs.lock();
+        let now = now();
+        self.shrink(0, &mut fs);
         self.modify_disk_inode(|disk_inode| {
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device);
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
-            }
+            disk_inode.mtime = now;
+            disk_inode.ctime = now;
         });
         block_cache_sync_all();
     }
 }
+
+impl Drop for Inode {
+    /// Drop a reference, freeing an orphan with its last one
+    fn drop(&mut self) {
+        let mut refs = self.refs.lock();
+        let count = refs.counts.get_mut(&self.inode_id).unwrap();
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        refs.counts.remove(&self.inode_id);
+        let orphan = refs.orphans.remove(&self.inode_id);
+        drop(refs);
+        if orphan {
+            let mut fs = self.fs.lock();
+            self.free(&mut fs);
+        }
+    }
+}
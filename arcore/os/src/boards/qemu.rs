@@ -6,12 +6,17 @@ pub const CLOCK_FREQ: usize = 12500000;
 
 /// The base address of control registers in VIRT_TEST/RTC/Virtio_Block device
 pub const MMIO: &[(usize, usize)] = &[
-   // (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
+    (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
     (0x1000_1000, 0x00_1000), // Virtio Block in virt machine
 ];
 
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
 
+/// The base address of the goldfish RTC in virt machine
+pub const RTC_BASE: usize = 0x0010_1000;
+
+pub type RtcImpl = crate::drivers::rtc::GoldfishRtc;
+
 //ref:: https://github.com/andre-richter/qemu-exit
 use core::arch::asm;
 
@@ -21,5 +21,13 @@ pub const MAX_SYSCALL_NUM: usize = 500;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 /// the virtual addr of trap context
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
+/// load base of position independent executables
+pub const ELF_DYN_BASE: usize = 0x1000_0000;
+/// number of pages the PIE load base is randomized over
+pub const ELF_DYN_RANDOM_PAGES: usize = 0x400;
+/// load base of the dynamic linker
+pub const DL_INTERP_BASE: usize = 0x20_0000_0000;
+/// end of the user half of the Sv39 address space
+pub const USER_SPACE_END: usize = 1 << 38;
 /// qemu board info
 pub use crate::board::{CLOCK_FREQ, MMIO};
@@ -1,5 +1,7 @@
 //! block device driver
 
 pub mod block;
+pub mod rtc;
 
 pub use block::BLOCK_DEVICE;
+pub use rtc::RTC;
@@ -0,0 +1,42 @@
+use super::Rtc;
+use crate::config::{KERNEL_DIRECT_OFFSET, PAGE_SIZE_BITS};
+use core::ptr::{read_volatile, write_volatile};
+
+/// low 32 bits of time in ns, reading it latches the high half
+const TIME_LOW: usize = 0x00;
+/// high 32 bits of time in ns
+const TIME_HIGH: usize = 0x04;
+
+/// Goldfish RTC driver, counts nanoseconds since the unix epoch
+pub struct GoldfishRtc {
+    /// kernel virtual address of the registers
+    base: usize,
+}
+
+impl GoldfishRtc {
+    /// Create a driver for the RTC at physical address `base`
+    pub fn new(base: usize) -> Self {
+        Self {
+            base: base + (KERNEL_DIRECT_OFFSET << PAGE_SIZE_BITS),
+        }
+    }
+    fn read_reg(&self, reg: usize) -> u32 {
+        unsafe { read_volatile((self.base + reg) as *const u32) }
+    }
+    fn write_reg(&self, reg: usize, value: u32) {
+        unsafe { write_volatile((self.base + reg) as *mut u32, value) }
+    }
+}
+
+impl Rtc for GoldfishRtc {
+    fn read_time_ns(&self) -> u64 {
+        let low = self.read_reg(TIME_LOW) as u64;
+        let high = self.read_reg(TIME_HIGH) as u64;
+        (high << 32) | low
+    }
+    fn set_time_ns(&self, ns: u64) {
+        // the new time takes effect on the write of the low half
+        self.write_reg(TIME_HIGH, (ns >> 32) as u32);
+        self.write_reg(TIME_LOW, ns as u32);
+    }
+}
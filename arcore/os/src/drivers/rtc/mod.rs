@@ -0,0 +1,22 @@
+//! real time clock driver
+
+mod goldfish;
+
+pub use goldfish::GoldfishRtc;
+
+use crate::board::{RtcImpl, RTC_BASE};
+use alloc::sync::Arc;
+use lazy_static::*;
+
+/// A wall clock device
+pub trait Rtc: Send + Sync {
+    /// Read the time in nanoseconds since the unix epoch
+    fn read_time_ns(&self) -> u64;
+    /// Set the time in nanoseconds since the unix epoch
+    fn set_time_ns(&self, ns: u64);
+}
+
+lazy_static! {
+    /// The global rtc driver instance: RTC with Rtc trait
+    pub static ref RTC: Arc<dyn Rtc> = Arc::new(RtcImpl::new(RTC_BASE));
+}
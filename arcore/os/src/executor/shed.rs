@@ -1,13 +1,20 @@
 use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 //use alloc::boxed::Box;
 
 use alloc::sync::Arc;
 use crate::task::taskloop;
+use crate::timer::itimer::charge_cpu_time;
 use crate::{executor, task::TaskControlBlock};
 ///spwan
 pub fn spawn_user_thread(tcb: Arc<TaskControlBlock>) {
     // let future = schedule::OutermostFuture::new(thread.clone(), async {});
-    let (runnable, task) =executor::exu::Executor::spawn( taskloop(tcb));
+    let future = UserTaskFuture {
+        tcb: tcb.clone(),
+        future: taskloop(tcb),
+    };
+    let (runnable, task) =executor::exu::Executor::spawn(future);
     runnable.schedule();
     task.detach();
 }
@@ -18,6 +25,25 @@ pub fn spawn_thread<F: Future<Output = ()> + Send + 'static>(future: F) {
     let (runnable, task) = executor::exu::Executor::spawn(future);
     runnable.schedule();
     task.detach();
-}       
+}
 
+/// The future of a user thread, counts its cpu time only while it is polled
+struct UserTaskFuture<F> {
+    tcb: Arc<TaskControlBlock>,
+    future: F,
+}
 
+impl<F: Future> Future for UserTaskFuture<F> {
+    type Output = F::Output;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of the pinned wrapper
+        let this = unsafe { self.get_unchecked_mut() };
+        this.tcb.inner_exclusive_access().time_info.when_poll_in();
+        let ret = unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx);
+        let stime = this.tcb.inner_exclusive_access().time_info.when_poll_out();
+        if let Some(process) = this.tcb.process.upgrade() {
+            charge_cpu_time(&process, 0, stime);
+        }
+        ret
+    }
+}
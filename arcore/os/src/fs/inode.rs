@@ -1,11 +1,14 @@
-use super::File;
+use super::{File, Stat, StatMode};
 use crate::drivers::BLOCK_DEVICE;
 use crate::mm::UserBuffer;
 use crate::sync::UPSafeCell;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
+use crate::syscall::errno::{EACCES, EINVAL, EISDIR, ELOOP, ENOENT, ENOTDIR, ENXIO};
+use crate::timer::{get_realtime_ns, NSEC_PER_SEC};
+use easy_fs::{block_size, set_clock, EasyFileSystem, Inode, SECTOR_SZ};
 use lazy_static::*;
 
 /// inode in memory
@@ -49,7 +52,9 @@ impl OSInode {
 }
 
 lazy_static! {
+    /// ROOT_INODE: the root directory "/"
     pub static ref ROOT_INODE: Arc<Inode> = {
+        set_clock(|| get_realtime_ns() as u64);
         let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
         Arc::new(EasyFileSystem::root_inode(&efs))
     };
@@ -79,9 +84,22 @@ bitflags! {
         const CREATE = 1 << 9;
         /// truncate file size to 0
         const TRUNC = 1 << 10;
+        /// fail if the path names a symbolic link
+        const NOFOLLOW = 1 << 17;
     }
 }
 
+/// lseek: the offset is absolute
+pub const SEEK_SET: usize = 0;
+/// lseek: the offset is relative to the current one
+pub const SEEK_CUR: usize = 1;
+/// lseek: the offset is relative to the end
+pub const SEEK_END: usize = 2;
+/// lseek: go to the next data at or after the offset
+pub const SEEK_DATA: usize = 3;
+/// lseek: go to the next hole at or after the offset
+pub const SEEK_HOLE: usize = 4;
+
 impl OpenFlags {
     /// Do not check validity for simplicity
     /// Return (readable, writable)
@@ -96,28 +114,227 @@ impl OpenFlags {
     }
 }
 
-/// Open a file
+/// The most symbolic links followed while resolving a path, as on Linux
+pub const MAX_SYMLINKS: usize = 40;
+
+/// Find the inode at `path`, a relative path starts at the directory `dir`
+///
+/// Symbolic links are followed, the one named by the final component only
+/// if `follow` is set or the path ends with a slash. `cred` needs search
+/// permission on every directory crossed.
+pub fn lookup(dir: &Arc<Inode>, path: &str, follow: bool, cred: Cred) -> Result<Arc<Inode>, isize> {
+    walk(dir, path, follow, cred, &mut 0)
+}
+
+/// [`lookup`] counting the symbolic links followed in `links`
+fn walk(
+    dir: &Arc<Inode>,
+    path: &str,
+    follow: bool,
+    cred: Cred,
+    links: &mut usize,
+) -> Result<Arc<Inode>, isize> {
+    let mut inode = match path.starts_with('/') {
+        true => ROOT_INODE.clone(),
+        false => dir.clone(),
+    };
+    let follow = follow || path.ends_with('/');
+    let mut names = path.split('/').filter(|name| !name.is_empty()).peekable();
+    while let Some(name) = names.next() {
+        if !inode.is_dir() {
+            return Err(-ENOTDIR);
+        }
+        if !cred.may(&inode, X_OK) {
+            return Err(-EACCES);
+        }
+        let next = inode.find(name).ok_or(-ENOENT)?;
+        let last = names.peek().is_none();
+        if !next.is_symlink() || (last && !follow) {
+            inode = next;
+            continue;
+        }
+        *links += 1;
+        if *links > MAX_SYMLINKS {
+            return Err(-ELOOP);
+        }
+        // a relative target starts at the directory holding the link
+        let target = next.read_link().ok_or(-ENOENT)?;
+        inode = walk(&inode, &target, true, cred, links)?;
+    }
+    if path.ends_with('/') && !inode.is_dir() {
+        return Err(-ENOTDIR);
+    }
+    Ok(inode)
+}
+
+/// Split `path` into its parent directory and final component, the parent
+/// is empty for a single relative component
+pub fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rsplit_once('/') {
+        Some(("", name)) => ("/", name),
+        Some((parent, name)) => (parent, name),
+        None => ("", path),
+    }
+}
+
+/// The absolute path of the directory `dir`, without symbolic links,
+/// found by climbing `..` and looking up each directory in its parent
+pub fn dir_path(dir: &Arc<Inode>) -> Option<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut inode = dir.clone();
+    loop {
+        let parent = inode.find("..")?;
+        if parent.inode_id() == inode.inode_id() {
+            break;
+        }
+        names.push(parent.find_name(inode.inode_id())?);
+        inode = parent;
+    }
+    let mut path = String::new();
+    for name in names.iter().rev() {
+        path.push('/');
+        path.push_str(name);
+    }
+    if path.is_empty() {
+        path.push('/');
+    }
+    Some(path)
+}
+
+/// Test for read permission
+pub const R_OK: u16 = 4;
+/// Test for write permission
+pub const W_OK: u16 = 2;
+/// Test for execute or search permission
+pub const X_OK: u16 = 1;
+
+/// The credentials files are accessed with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cred {
+    /// user id
+    pub uid: u32,
+    /// group id
+    pub gid: u32,
+}
+
+impl Cred {
+    /// The superuser
+    pub const ROOT: Self = Self { uid: 0, gid: 0 };
+    /// Whether the mode of `inode` grants all of the `access` bits
+    pub fn may(&self, inode: &Inode, access: u16) -> bool {
+        let mode = inode.mode();
+        if self.uid == 0 {
+            // root may execute a file only if anyone may
+            return access & X_OK == 0 || inode.is_dir() || mode & 0o111 != 0;
+        }
+        let (uid, gid) = inode.owner();
+        let bits = match (self.uid == uid, self.gid == gid) {
+            (true, _) => mode >> 6,
+            (false, true) => mode >> 3,
+            (false, false) => mode,
+        };
+        bits & access == access
+    }
+    /// Whether the metadata of `inode` may be changed, by its owner or root
+    pub fn owns(&self, inode: &Inode) -> bool {
+        self.uid == 0 || inode.owner().0 == self.uid
+    }
+}
+
+/// Open a file as root, a relative path starts at the root
 pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
-    trace!("kernel: open_file: name = {}, flags = {:?}", name, flags);
+    open_file_at(&ROOT_INODE, name, flags, 0o644, Cred::ROOT).ok()
+}
+
+/// Open a file with the permissions of `cred`, a relative path starts at the
+/// directory `dir`. A file that is created gets `mode` and `cred` as owner.
+pub fn open_file_at(
+    dir: &Arc<Inode>,
+    path: &str,
+    flags: OpenFlags,
+    mode: u16,
+    cred: Cred,
+) -> Result<Arc<OSInode>, isize> {
+    trace!("kernel: open_file: path = {}, flags = {:?}", path, flags);
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
+    let inode = match lookup(dir, path, !flags.contains(OpenFlags::NOFOLLOW), cred) {
+        Ok(inode) => {
+            if inode.is_symlink() {
+                return Err(-ELOOP);
+            }
+            if inode.is_dir() && writable {
+                return Err(-EISDIR);
+            }
+            let truncate = flags.contains(OpenFlags::CREATE) || flags.contains(OpenFlags::TRUNC);
+            let mut access = 0;
+            if readable {
+                access |= R_OK;
+            }
+            if writable || truncate {
+                access |= W_OK;
+            }
+            if !cred.may(&inode, access) {
+                return Err(-EACCES);
+            }
+            if truncate {
+                // clear size
+                inode.clear();
+            }
+            inode
+        }
+        Err(err) if err == -ENOENT && flags.contains(OpenFlags::CREATE) => {
             // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            let (parent, name) = split_path(path);
+            let parent = lookup(dir, parent, true, cred)?;
+            if !parent.is_dir() {
+                return Err(-ENOTDIR);
+            }
+            if !cred.may(&parent, W_OK | X_OK) {
+                return Err(-EACCES);
+            }
+            parent
+                .create_as(name, mode, (cred.uid, cred.gid))
+                .ok_or(-ENOENT)?
         }
+        Err(err) => return Err(err),
+    };
+    Ok(Arc::new(OSInode::new(readable, writable, inode)))
+}
+
+/// The stat of an easy-fs inode
+pub fn inode_stat(inode: &Inode) -> Stat {
+    let file_type = if inode.is_dir() {
+        StatMode::DIR
+    } else if inode.is_symlink() {
+        StatMode::LINK
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
-            }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+        StatMode::FILE
+    };
+    let (uid, gid) = inode.owner();
+    let (atime, mtime, ctime) = inode.times();
+    let split = |ns: u64| {
+        let ns = ns as usize;
+        ((ns / NSEC_PER_SEC) as i64, (ns % NSEC_PER_SEC) as i64)
+    };
+    let ((atime_sec, atime_nsec), (mtime_sec, mtime_nsec), (ctime_sec, ctime_nsec)) =
+        (split(atime), split(mtime), split(ctime));
+    Stat {
+        ino: inode.inode_id() as u64,
+        mode: file_type | StatMode::from_bits_truncate(inode.mode() as u32),
+        nlink: inode.nlink(),
+        uid,
+        gid,
+        size: inode.size() as u64,
+        blksize: block_size() as u32,
+        blocks: (inode.blocks() as usize * block_size() / SECTOR_SZ) as u64,
+        atime_sec,
+        atime_nsec,
+        mtime_sec,
+        mtime_nsec,
+        ctime_sec,
+        ctime_nsec,
+        ..Default::default()
     }
 }
 
@@ -145,6 +362,32 @@ impl File for OSInode {
         }
         total_read_size
     }
+    /// the inode of the file
+    fn inode(&self) -> Option<Arc<Inode>> {
+        Some(Arc::clone(&self.inner.exclusive_access().inode))
+    }
+    /// the stat of the inode
+    fn stat(&self) -> Stat {
+        inode_stat(&self.inner.exclusive_access().inode)
+    }
+    /// move the offset, which may go past the end
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, isize> {
+        let mut inner = self.inner.exclusive_access();
+        let new_offset = match whence {
+            SEEK_SET => offset,
+            SEEK_CUR => inner.offset as isize + offset,
+            SEEK_END => inner.inode.size() as isize + offset,
+            SEEK_DATA | SEEK_HOLE if offset < 0 => return Err(-ENXIO),
+            SEEK_DATA => inner.inode.seek_data(offset as usize).ok_or(-ENXIO)? as isize,
+            SEEK_HOLE => inner.inode.seek_hole(offset as usize).ok_or(-ENXIO)? as isize,
+            _ => return Err(-EINVAL),
+        };
+        if new_offset < 0 {
+            return Err(-EINVAL);
+        }
+        inner.offset = new_offset as usize;
+        Ok(inner.offset)
+    }
     /// write buffer data into file
     fn write(&self, buf: UserBuffer) -> usize {
         trace!("kernel: OSInode::write");
@@ -152,9 +395,12 @@ impl File for OSInode {
         let mut total_write_size = 0usize;
         for slice in buf.buffers.iter() {
             let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
             inner.offset += write_size;
             total_write_size += write_size;
+            // short at the max file size
+            if write_size < slice.len() {
+                break;
+            }
         }
         total_write_size
     }
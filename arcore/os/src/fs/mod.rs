@@ -5,6 +5,9 @@ mod pipe;
 mod stdio;
 
 use crate::mm::UserBuffer;
+use crate::syscall::errno::ESPIPE;
+use alloc::sync::Arc;
+use easy_fs::Inode;
 
 /// trait File for all file types
 pub trait File: Send + Sync {
@@ -16,11 +19,21 @@ pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> usize;
     /// write to the file from buf, return the number of bytes written
     fn write(&self, buf: UserBuffer) -> usize;
+    /// the easy-fs inode behind the file, if any
+    fn inode(&self) -> Option<Arc<Inode>> {
+        None
+    }
+    /// the metadata of the file
+    fn stat(&self) -> Stat;
+    /// move the offset as lseek does, return the new offset
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, isize> {
+        Err(-ESPIPE)
+    }
 }
 
-/// The stat of a inode
+/// The stat of a inode, laid out as `struct stat` of Linux on riscv64
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct Stat {
     /// ID of device containing file
     pub dev: u64,
@@ -30,23 +43,88 @@ pub struct Stat {
     pub mode: StatMode,
     /// number of hard links
     pub nlink: u32,
+    /// user ID of owner
+    pub uid: u32,
+    /// group ID of owner
+    pub gid: u32,
+    /// device ID of a special file
+    pub rdev: u64,
+    /// unused pad
+    __pad: u64,
+    /// total size in bytes
+    pub size: u64,
+    /// block size for filesystem I/O
+    pub blksize: u32,
     /// unused pad
-    pad: [u64; 7],
+    __pad2: u32,
+    /// number of 512B blocks allocated
+    pub blocks: u64,
+    /// time of last access, seconds
+    pub atime_sec: i64,
+    /// time of last access, nanoseconds
+    pub atime_nsec: i64,
+    /// time of last modification, seconds
+    pub mtime_sec: i64,
+    /// time of last modification, nanoseconds
+    pub mtime_nsec: i64,
+    /// time of last status change, seconds
+    pub ctime_sec: i64,
+    /// time of last status change, nanoseconds
+    pub ctime_nsec: i64,
+    /// unused
+    __unused: [u32; 2],
 }
 
 bitflags! {
     /// The mode of a inode
-    /// whether a directory or a file
+    /// the file type and the permission bits
+    #[derive(Default)]
     pub struct StatMode: u32 {
         /// null
         const NULL  = 0;
+        /// mask of the file type
+        const TYPE_MASK = 0o170000;
+        /// FIFO
+        const FIFO  = 0o010000;
+        /// character device
+        const CHAR  = 0o020000;
         /// directory
         const DIR   = 0o040000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// symbolic link
+        const LINK  = 0o120000;
+        /// set user id on execution
+        const SET_UID = 0o4000;
+        /// set group id on execution
+        const SET_GID = 0o2000;
+        /// sticky bit
+        const STICKY = 0o1000;
+        /// owner may read
+        const OWNER_READ = 0o400;
+        /// owner may write
+        const OWNER_WRITE = 0o200;
+        /// owner may execute or search
+        const OWNER_EXEC = 0o100;
+        /// group may read
+        const GROUP_READ = 0o040;
+        /// group may write
+        const GROUP_WRITE = 0o020;
+        /// group may execute or search
+        const GROUP_EXEC = 0o010;
+        /// others may read
+        const OTHER_READ = 0o004;
+        /// others may write
+        const OTHER_WRITE = 0o002;
+        /// others may execute or search
+        const OTHER_EXEC = 0o001;
     }
 }
 
-pub use inode::{list_apps, open_file, OSInode, OpenFlags};
+pub use inode::{
+    dir_path, inode_stat, list_apps, lookup, open_file, open_file_at, split_path, Cred,
+    OSInode, OpenFlags, ROOT_INODE, R_OK, SEEK_CUR, SEEK_DATA, SEEK_END, SEEK_HOLE, SEEK_SET,
+    W_OK, X_OK,
+};
 pub use pipe::{make_pipe, Pipe};
 pub use stdio::{Stdin, Stdout};
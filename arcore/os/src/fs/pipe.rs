@@ -1,4 +1,4 @@
-use super::File;
+use super::{File, Stat, StatMode};
 use crate::mm::UserBuffer;
 use crate::sync::UPSafeCell;
 use alloc::sync::{Arc, Weak};
@@ -176,4 +176,15 @@ impl File for Pipe {
             }
         }
     }
+    fn stat(&self) -> Stat {
+        Stat {
+            // both ends share the ring buffer
+            ino: Arc::as_ptr(&self.buffer) as u64,
+            mode: StatMode::FIFO | StatMode::from_bits_truncate(0o600),
+            nlink: 1,
+            size: self.buffer.exclusive_access().available_read() as u64,
+            blksize: RING_BUFFER_SIZE as u32,
+            ..Default::default()
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use super::File;
+use super::{File, Stat, StatMode};
 use crate::mm::UserBuffer;
 use crate::sbi::console_getchar;
 
@@ -40,6 +40,9 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn stat(&self) -> Stat {
+        console_stat()
+    }
 }
 
 impl File for Stdout {
@@ -58,4 +61,16 @@ impl File for Stdout {
         }
         user_buf.len()
     }
+    fn stat(&self) -> Stat {
+        console_stat()
+    }
+}
+
+/// The stat of the console, a character device
+fn console_stat() -> Stat {
+    Stat {
+        mode: StatMode::CHAR | StatMode::from_bits_truncate(0o620),
+        nlink: 1,
+        ..Default::default()
+    }
 }
@@ -90,9 +90,12 @@ pub fn rust_main() -> ! {
     mm::heap_test1();
     //mm::heap_test2();
     mm::remap_test();
+    syscall::futex_test();
     trap::init();
+    timer::init_realtime();
     
     fs::list_apps();
+    sync::deadlock_test();
     unsafe { DB=2 };
     debug!("satp:{:x}",satp::read().bits());
     executor::initexecutor();
@@ -106,6 +109,8 @@ pub fn rust_main() -> ! {
     loop{
         
         executor::run_until_idle();
+        // timer interrupts are off, so expire timers while idle
+        timer::check_timer();
     }
     
  
@@ -7,8 +7,13 @@ use super::{StepByOne, VPNRange};
 use crate::config::{KERNEL_DIRECT_OFFSET, MEMORY_END, MMIO, PAGE_SIZE, PAGE_SIZE_BITS, TRAMPOLINE, USER_STACK_SIZE, /* USER_STACK_SIZE */};
 use crate::mm::KernelAddr;
 use crate::sync::UPSafeCell;
+use crate::config::{DL_INTERP_BASE, ELF_DYN_BASE, ELF_DYN_RANDOM_PAGES, USER_SPACE_END};
+use crate::fs::{open_file_at, Cred, File, OpenFlags, ROOT_INODE, X_OK};
+use crate::task::{AuxHeader, AT_BASE, AT_ENTRY, AT_FLAGS, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM};
+use crate::timer::get_time;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
 use lazy_static::*;
@@ -44,6 +49,8 @@ pub struct MemorySet {
     pub page_table: PageTable,
     /// areas
     pub areas: Vec<MapArea>,
+    /// permission of user stacks, executable if the image asks for it
+    pub stack_perm: MapPermission,
 }
 
 impl MemorySet {
@@ -52,6 +59,7 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            stack_perm: MapPermission::R | MapPermission::W | MapPermission::U,
         }
     }
     ///d
@@ -61,7 +69,7 @@ impl MemorySet {
         Self {
             page_table,
             areas: Vec::new(),
-           
+            stack_perm: MapPermission::R | MapPermission::W | MapPermission::U,
         }
     }
     /// Get he page table token
@@ -98,13 +106,18 @@ impl MemorySet {
     /// Add a new MapArea into this MemorySet.
     /// Assuming that there are no conflicts in the virtual address
     /// space.
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
-
-        if let Some(data) = data {
-            map_area.copy_data(&mut self.page_table, data);
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.push_with_offset(map_area, 0, data).unwrap();
+    }
+    /// Same as `push`, but the data starts at `offset` within the first page.
+    /// Returns `None` if the frames run out, the area is kept to free them.
+    fn push_with_offset(&mut self, mut map_area: MapArea, offset: usize, data: Option<&[u8]>) -> Option<()> {
+        let mapped = map_area.try_map(&mut self.page_table);
+        if let (Some(()), Some(data)) = (mapped, data) {
+            map_area.copy_data(&mut self.page_table, offset, data);
         }
         self.areas.push(map_area);
+        mapped
     }
     /// Mention that trampoline is not collected by areas.
     fn _map_trampoline(&mut self) {
@@ -192,25 +205,108 @@ impl MemorySet {
         }
         memory_set
     }
-    /// Include sections in elf and trampoline and T    rapContext and user stack,
-    /// also returns user_sp_base and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    /// Include sections in elf and trampoline and TrapContext and user stack,
+    /// also returns user_sp_base, entry point and the auxiliary vector of the image.
+    ///
+    /// Position independent executables are loaded at a randomized base, and
+    /// if the image asks for an interpreter (`PT_INTERP`) it is mapped as well
+    /// and becomes the entry point, if `cred` may execute it. Returns `None` if
+    /// the image is malformed.
+    pub fn from_elf(elf_data: &[u8], cred: Cred) -> Option<(Self, usize, usize, Vec<AuxHeader>)> {
         let mut memory_set = Self::new_from_kernel();
-        
-        
-        // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf = xmas_elf::ElfFile::new(elf_data).ok()?;
+        let elf_header = elf.header;
+        let base = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::Executable => 0,
+            // randomize the load base of PIE by a few pages
+            xmas_elf::header::Type::SharedObject => {
+                ELF_DYN_BASE + (get_time() % ELF_DYN_RANDOM_PAGES) * PAGE_SIZE
+            }
+            _ => return None,
+        };
+        // the user stacks go above the image, below the interpreter
+        let (max_end_vpn, phdr_va) = memory_set.map_elf(&elf, base, DL_INTERP_BASE)?;
+        let max_end_va: VirtAddr = max_end_vpn.into();
+        let mut user_stack_base: usize = max_end_va.into();
+        user_stack_base += PAGE_SIZE;
+        debug!("ustack_base::{:x}", user_stack_base);
+
+        let program_entry = base.checked_add(elf_header.pt2.entry_point() as usize)?;
+        let mut entry_point = program_entry;
+        let mut interp_base = 0;
+        if let Some(interp_path) = elf_interp(&elf)? {
+            let interp_data = open_interp(interp_path, cred)?;
+            let interp = xmas_elf::ElfFile::new(&interp_data).ok()?;
+            interp_base = DL_INTERP_BASE;
+            memory_set.map_elf(&interp, interp_base, USER_SPACE_END)?;
+            entry_point = interp_base.checked_add(interp.header.pt2.entry_point() as usize)?;
+            debug!("interp {} at {:#x}, entry {:#x}", interp_path, interp_base, entry_point);
+        }
+        let auxv = vec![
+            AuxHeader::new(AT_PHDR, base.checked_add(phdr_va)?),
+            AuxHeader::new(AT_PHENT, elf_header.pt2.ph_entry_size() as usize),
+            AuxHeader::new(AT_PHNUM, elf_header.pt2.ph_count() as usize),
+            AuxHeader::new(AT_PAGESZ, PAGE_SIZE),
+            AuxHeader::new(AT_BASE, interp_base),
+            AuxHeader::new(AT_FLAGS, 0),
+            AuxHeader::new(AT_ENTRY, program_entry),
+        ];
+        Some((memory_set, user_stack_base, entry_point, auxv))
+    }
+    /// Map the `PT_LOAD` segments of an elf image at `base`, with U flag.
+    /// Returns the end of the image and the (unrelocated) address of its program headers.
+    ///
+    /// Fails on segments reaching `limit` or sharing a page with another area,
+    /// and when the frames run out.
+    fn map_elf(&mut self, elf: &xmas_elf::ElfFile, base: usize, limit: usize) -> Option<(VirtPageNum, usize)> {
         let elf_header = elf.header;
-        let magic = elf_header.pt1.magic;
-        assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        if elf_header.pt1.magic != [0x7f, 0x45, 0x4c, 0x46]
+            || elf_header.pt1.class() != xmas_elf::header::Class::SixtyFour
+        {
+            return None;
+        }
         let ph_count = elf_header.pt2.ph_count();
-       
         let mut max_end_vpn = VirtPageNum(0);
+        let mut phdr_va = 0usize;
         for i in 0..ph_count {
-            let ph = elf.program_header(i).unwrap();
-            if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+            let ph = elf.program_header(i).ok()?;
+            let ph_type = ph.get_type().ok()?;
+            match ph_type {
+                xmas_elf::program::Type::Phdr => phdr_va = ph.virtual_addr() as usize,
+                // program headers are usually mapped by the first segment
+                xmas_elf::program::Type::Load if phdr_va == 0 && ph.offset() == 0 => {
+                    phdr_va = (ph.virtual_addr() as usize).checked_add(elf_header.pt2.ph_offset() as usize)?;
+                }
+                // executable stack requested by the image
+                xmas_elf::program::Type::OsSpecific(PT_GNU_STACK) if ph.flags().is_execute() => {
+                    self.stack_perm |= MapPermission::X;
+                }
+                // the TLS template lives inside a PT_LOAD segment, libc finds it
+                // through AT_PHDR and sets up tp for the main thread itself
+                xmas_elf::program::Type::Tls => {
+                    debug!("tls template at {:#x}, memsz {:#x}", base + ph.virtual_addr() as usize, ph.mem_size());
+                }
+                _ => {}
+            }
+            if ph_type == xmas_elf::program::Type::Load {
+                if ph.file_size() > ph.mem_size() {
+                    return None;
+                }
+                let data = elf
+                    .input
+                    .get(ph.offset() as usize..ph.offset().checked_add(ph.file_size())? as usize)?;
+                let start = base.checked_add(ph.virtual_addr() as usize)?;
+                let end = start.checked_add(ph.mem_size() as usize)?;
+                if end > limit {
+                    return None;
+                }
+                let start_va: VirtAddr = start.into();
+                let end_va: VirtAddr = end.into();
+                if self.areas.iter().any(|area| {
+                    start_va.floor() < area.vpn_range.get_end() && area.vpn_range.get_start() < end_va.ceil()
+                }) {
+                    return None;
+                }
                 let mut map_perm = MapPermission::U;
                 let ph_flags = ph.flags();
                 if ph_flags.is_read() {
@@ -224,39 +320,16 @@ impl MemorySet {
                 }
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 debug!("start_va:{:?},end_va:{:?}",start_va,end_va);
-                max_end_vpn = map_area.vpn_range.get_end();
-                debug!("max_end_vpn:{:?}",max_end_vpn);
-               KERNEL_SPACE.exclusive_access().push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                max_end_vpn = max_end_vpn.max(map_area.vpn_range.get_end());
+                self.push_with_offset(map_area, start_va.page_offset(), Some(data))?;
             }
         }
-        debug!("max_end_vpn::{:?}",max_end_vpn);
-        let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE;
-        /* let user_stack_top = user_stack_base + USER_STACK_SIZE;
-        let map_area = MapArea::new(user_stack_base.into(), user_stack_top.into(), MapType::Framed, MapPermission::U | MapPermission::R | MapPermission::W);
-        // map user stack with U flags
-        
-       
-        KERNEL_SPACE.exclusive_access().push(
-            map_area,
-            None,
-        ); */
-        
-        debug!("ustack_base::{:x},ustack_top::{:x}",user_stack_base,user_stack_top);
-       
-        (
-            memory_set,
-            user_stack_base,
-            elf.header.pt2.entry_point() as usize,
-        )
+        Some((max_end_vpn, phdr_va))
     }
     /// Create a new address space by copy code&data from a exited process's address space.
     pub fn from_existed_user(user_space: &Self) -> Self {
         let mut memory_set = Self::new_from_kernel();
+        memory_set.stack_perm = user_space.stack_perm;
         // map trampoline
        
         // copy data sections/trap_context/user_stack
@@ -323,6 +396,36 @@ impl MemorySet {
     }
 }
 
+/// program header type of the stack permission segment
+const PT_GNU_STACK: u32 = 0x6474_e551;
+
+/// The interpreter path named by `PT_INTERP`, `Some(None)` if there is none
+/// and `None` if the segment is malformed
+fn elf_interp<'a>(elf: &xmas_elf::ElfFile<'a>) -> Option<Option<&'a str>> {
+    let ph = match elf
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(xmas_elf::program::Type::Interp))
+    {
+        Some(ph) => ph,
+        None => return Some(None),
+    };
+    let raw = elf
+        .input
+        .get(ph.offset() as usize..ph.offset().checked_add(ph.file_size())? as usize)?;
+    let len = raw.iter().position(|&c| c == 0).unwrap_or(raw.len());
+    core::str::from_utf8(&raw[..len]).ok().map(Some)
+}
+
+/// Read the whole interpreter image, which `cred` must be allowed to execute.
+/// A relative path starts at the root.
+fn open_interp(path: &str, cred: Cred) -> Option<Vec<u8>> {
+    let interp = open_file_at(&ROOT_INODE, path, OpenFlags::RDONLY, 0, cred).ok()?;
+    match interp.inode() {
+        Some(inode) if !inode.is_dir() && cred.may(&inode, X_OK) => Some(interp.read_all()),
+        _ => None,
+    }
+}
+
 pub struct MapArea {
     pub vpn_range: VPNRange,
     pub data_frames: BTreeMap<VirtPageNum, FrameTracker>,
@@ -355,6 +458,10 @@ impl MapArea {
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        self.try_map_one(page_table, vpn).unwrap();
+    }
+    /// Same as `map_one`, `None` if the frames run out
+    pub fn try_map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> Option<()> {
         let ppn: PhysPageNum;
         match self.map_type {
             MapType::Identical => {
@@ -362,7 +469,7 @@ impl MapArea {
                 
             }
             MapType::Framed => {
-                let frame = frame_alloc().unwrap();
+                let frame = frame_alloc()?;
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
@@ -373,6 +480,7 @@ impl MapArea {
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
      /*    if unsafe { DB }==2 {debug!("vpnddd:{:?}",vpn);} */
         page_table.map(vpn, ppn, pte_flags);
+        Some(())
     }
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         if self.map_type == MapType::Framed {
@@ -387,6 +495,13 @@ impl MapArea {
             self.map_one(page_table, vpn);
         }
     }
+    /// Same as `map`, `None` if the frames run out
+    pub fn try_map(&mut self, page_table: &mut PageTable) -> Option<()> {
+        for vpn in self.vpn_range {
+            self.try_map_one(page_table, vpn)?;
+        }
+        Some(())
+    }
     pub fn unmap(&mut self, page_table: &mut PageTable) {
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
@@ -406,10 +521,11 @@ impl MapArea {
         }
         self.vpn_range = VPNRange::new(self.vpn_range.get_start(), new_end);
     }
-    /// data: start-aligned but maybe with shorter length
+    /// data: starts at `offset` of the first page, maybe with shorter length
     /// assume that all frames were cleared before
-    pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
+    pub fn copy_data(&mut self, page_table: &mut PageTable, offset: usize, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
+        let mut page_offset = offset;
         let mut start: usize = 0;
         let mut current_vpn = self.vpn_range.get_start();
         let len = data.len();
@@ -422,14 +538,15 @@ impl MapArea {
             .unwrap()
             .ppn() )).0,len);
         loop {
-            let src = &data[start..len.min(start + PAGE_SIZE)];
+            let src = &data[start..len.min(start + PAGE_SIZE - page_offset)];
             let dst = &mut page_table
                 .translate(current_vpn)
                 .unwrap()
                 .ppn()
-                .get_bytes_array()[..src.len()];
+                .get_bytes_array()[page_offset..page_offset + src.len()];
             dst.copy_from_slice(src);
-            start += PAGE_SIZE;
+            start += PAGE_SIZE - page_offset;
+            page_offset = 0;
             if start >= len {
                 break;
             }
@@ -18,7 +18,7 @@ pub use memory_set::remap_test;
 pub use memory_set::{kernel_token, MapPermission, MemorySet, KERNEL_SPACE};
 use page_table::PTEFlags;
 pub use page_table::{
-    translated_byte_buffer, translated_ref, translated_refmut, translated_str, PageTable,
+    copy_from_user, copy_to_user, translated_byte_buffer, translated_ref, translated_refmut, translated_str, try_copy_from_user, PageTable,
     PageTableEntry, UserBuffer, UserBufferIterator,
 };
 
@@ -1,6 +1,7 @@
 //! Implementation of [`PageTableEntry`] and [`PageTable`].
 use crate::config::KERNEL_DIRECT_OFFSET;
 
+use super::address::VA_WIDTH_SV39;
 use super::{frame_alloc, FrameTracker, PhysAddr, PhysPageNum, StepByOne, VirtAddr, VirtPageNum, KERNEL_SPACE};
 use alloc::string::String;
 use alloc::vec;
@@ -64,6 +65,10 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// The page pointered by page table entry is accessible in user mode?
+    pub fn is_user(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
 }
 
 /// page table structure
@@ -96,7 +101,7 @@ impl PageTable {
 
         // the new pagetable only owns the ownership of its own root ppn
         PageTable {
-            root_ppn: global_root_ppn,
+            root_ppn: frame.ppn,
             frames: vec![frame],
         }
     }
@@ -254,6 +259,50 @@ pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
         .get_mut()
 }
 
+/// Copy `value` to `ptr` in other address space, the destination may cross physical pages
+pub fn copy_to_user<T>(token: usize, ptr: *mut T, value: &T) {
+    let mut src = value as *const T as *const u8;
+    for buffer in translated_byte_buffer(token, ptr as *const u8, core::mem::size_of::<T>()) {
+        unsafe {
+            src.copy_to(buffer.as_mut_ptr(), buffer.len());
+            src = src.add(buffer.len());
+        }
+    }
+}
+
+/// Copy a value from `ptr` in other address space, the source may cross physical pages
+pub fn copy_from_user<T: Copy>(token: usize, ptr: *const T) -> T {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let mut dst = value.as_mut_ptr() as *mut u8;
+    for buffer in translated_byte_buffer(token, ptr as *const u8, core::mem::size_of::<T>()) {
+        unsafe {
+            dst.copy_from(buffer.as_ptr(), buffer.len());
+            dst = dst.add(buffer.len());
+        }
+    }
+    unsafe { value.assume_init() }
+}
+
+/// Same as `copy_from_user`, `None` if any byte at `ptr` is not readable by the user
+pub fn try_copy_from_user<T: Copy>(token: usize, ptr: *const T) -> Option<T> {
+    let page_table = PageTable::from_token(token);
+    let start = ptr as usize;
+    let end = start.checked_add(core::mem::size_of::<T>())?;
+    // user space is the lower half, anything above is no valid user address
+    if end > 1 << (VA_WIDTH_SV39 - 1) {
+        return None;
+    }
+    let mut vpn = VirtAddr::from(start).floor();
+    while VirtAddr::from(vpn).0 < end {
+        let pte = page_table.translate(vpn)?;
+        if !pte.readable() || !pte.is_user() {
+            return None;
+        }
+        vpn.step();
+    }
+    Some(copy_from_user(token, ptr))
+}
+
 /// An abstraction over a buffer passed from user space to kernel space
 pub struct UserBuffer {
     /// A list of buffers
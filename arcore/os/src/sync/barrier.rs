@@ -0,0 +1,92 @@
+//! Barrier
+
+use crate::sync::{UPSafeCell, WaitQueue};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Barrier structure, releases its waiters once `count` of them arrived
+pub struct Barrier {
+    /// barrier inner
+    pub inner: UPSafeCell<BarrierInner>,
+}
+
+pub struct BarrierInner {
+    /// number of tasks to wait for
+    pub count: usize,
+    /// number of tasks waiting in the current round
+    pub arrived: usize,
+    wait_queue: WaitQueue,
+}
+
+impl Barrier {
+    /// Create a new barrier for `count` tasks
+    pub fn new(count: usize) -> Self {
+        trace!("kernel: Barrier::new");
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(BarrierInner {
+                    count,
+                    arrived: 0,
+                    wait_queue: WaitQueue::new(),
+                })
+            },
+        }
+    }
+
+    /// Wait until `count` tasks arrived, resolves to true for the last one
+    pub fn wait(&self) -> BarrierWait<'_> {
+        trace!("kernel: Barrier::wait");
+        BarrierWait {
+            barrier: self,
+            ticket: None,
+        }
+    }
+}
+
+/// Future of [`Barrier::wait`]
+pub struct BarrierWait<'a> {
+    barrier: &'a Barrier,
+    ticket: Option<usize>,
+}
+
+impl Future for BarrierWait<'_> {
+    type Output = bool;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.barrier.inner.exclusive_access();
+        match self.ticket {
+            Some(ticket) => {
+                if inner.wait_queue.update(ticket, cx.waker()) {
+                    return Poll::Pending;
+                }
+                self.ticket = None;
+                Poll::Ready(false)
+            }
+            None => {
+                inner.arrived += 1;
+                if inner.arrived < inner.count {
+                    self.ticket = Some(inner.wait_queue.push(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+                // last arrival releases the round
+                inner.arrived = 0;
+                while let Some(waker) = inner.wait_queue.pop() {
+                    waker.wake();
+                }
+                Poll::Ready(true)
+            }
+        }
+    }
+}
+
+impl Drop for BarrierWait<'_> {
+    /// A waiter dropped before the round completes no longer counts
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            let mut inner = self.barrier.inner.exclusive_access();
+            if inner.wait_queue.remove(ticket) {
+                inner.arrived -= 1;
+            }
+        }
+    }
+}
@@ -1,8 +1,11 @@
-//! Conditian variable
+//! Conditian variable, blocking and async
 
-use crate::sync::{Mutex, UPSafeCell};
+use crate::sync::{AsyncMutexGuard, Mutex, UPSafeCell, WaitQueue};
 use crate::task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock};
 use alloc::{collections::VecDeque, sync::Arc};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
 
 /// Condition variable structure
 pub struct Condvar {
@@ -46,3 +49,94 @@ impl Condvar {
         mutex.lock();
     }
 }
+
+/// Condition variable paired with an [`AsyncMutex`]
+pub struct AsyncCondvar {
+    wait_queue: UPSafeCell<WaitQueue>,
+}
+
+impl AsyncCondvar {
+    /// Create a new condition variable
+    pub const fn new() -> Self {
+        Self {
+            wait_queue: unsafe { UPSafeCell::new(WaitQueue::new()) },
+        }
+    }
+
+    /// Wake the first waiter, returns false if there was none
+    pub fn notify_one(&self) -> bool {
+        let waker = self.wait_queue.exclusive_access().pop();
+        match waker {
+            Some(waker) => {
+                waker.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake all waiters, returns the number woken
+    pub fn notify_all(&self) -> usize {
+        let mut n = 0;
+        while self.notify_one() {
+            n += 1;
+        }
+        n
+    }
+
+    /// Release `guard`, wait for a notification and lock the mutex again
+    pub async fn wait<'a, T: ?Sized>(&self, guard: AsyncMutexGuard<'a, T>) -> AsyncMutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        CondvarWait {
+            condvar: self,
+            guard: Some(guard),
+            ticket: None,
+        }
+        .await;
+        mutex.lock().await
+    }
+}
+
+impl Default for AsyncCondvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Parks until notified, releasing the guard once parked so no notification
+/// is lost in between
+struct CondvarWait<'a, 'b, T: ?Sized> {
+    condvar: &'a AsyncCondvar,
+    guard: Option<AsyncMutexGuard<'b, T>>,
+    ticket: Option<usize>,
+}
+
+impl<T: ?Sized> Future for CondvarWait<'_, '_, T> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut wait_queue = self.condvar.wait_queue.exclusive_access();
+        match self.ticket {
+            None => {
+                let ticket = wait_queue.push(cx.waker().clone());
+                drop(wait_queue);
+                self.ticket = Some(ticket);
+                self.guard = None;
+                Poll::Pending
+            }
+            Some(ticket) if wait_queue.update(ticket, cx.waker()) => Poll::Pending,
+            Some(_) => {
+                drop(wait_queue);
+                self.ticket = None;
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Drop for CondvarWait<'_, '_, T> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            self.condvar.wait_queue.exclusive_access().remove(ticket);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+//! Deadlock detection over the synchronization resources of a process
+//!
+//! Mutexes, semaphores, rwlocks and condvars are put into one Banker's
+//! Algorithm instance. A task that would wait is checked with its request
+//! recorded in its `*_need` fields; if the tasks of the process could then no
+//! longer all finish, the tasks left over and the resources they hold or
+//! wait for are recorded as the deadlock report of the process.
+
+use super::{Mutex, MutexBlocking, RwLock, Semaphore};
+use crate::task::{ProcessControlBlock, TaskControlBlock};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::max;
+
+/// What to do with a request that would deadlock
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DeadlockPolicy {
+    /// fail the request with -0xDEAD
+    Refuse,
+    /// exit a task of the deadlock, releasing what it holds
+    KillVictim,
+    /// record the report and let the request wait
+    LogOnly,
+}
+
+/// Kind of a resource in a deadlock report
+#[derive(Clone, Copy, PartialEq, Debug)]
+#[repr(usize)]
+pub enum ResourceKind {
+    /// entry of `mutex_list`
+    Mutex = 0,
+    /// entry of `semaphore_list`
+    Semaphore = 1,
+    /// entry of `rwlock_list`
+    RwLock = 2,
+    /// entry of `condvar_list`
+    Condvar = 3,
+}
+
+/// A task of the deadlock and a resource it holds or waits for
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct DeadlockEntry {
+    /// tid of the task
+    pub tid: usize,
+    /// a [`ResourceKind`]
+    pub kind: usize,
+    /// id of the resource in its list
+    pub res_id: usize,
+    /// 1 if the task waits for the resource, 0 if it holds it
+    pub waiting: usize,
+}
+
+/// The last deadlock found in a process
+pub struct DeadlockReport {
+    /// tid of the task whose request completed the deadlock
+    pub requester: usize,
+    /// tasks that could not finish, with their resources
+    pub entries: Vec<DeadlockEntry>,
+}
+
+/// Check the request recorded in the `*_need` fields of `task`, returns false
+/// if it must not wait. The caller clears the need on false.
+pub fn check_request(process: &Arc<ProcessControlBlock>, task: &Arc<TaskControlBlock>) -> bool {
+    if !process.inner_exclusive_access().dlcheck_option {
+        return true;
+    }
+    let entries = match detect(process) {
+        Some(entries) => entries,
+        None => return true,
+    };
+    let requester = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    warn!(
+        "kernel: pid[{}] deadlock on request of tid[{}]: {:?}",
+        process.getpid(),
+        requester,
+        entries
+    );
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.deadlock_report = Some(DeadlockReport { requester, entries });
+    let policy = process_inner.dlcheck_policy;
+    drop(process_inner);
+    match policy {
+        DeadlockPolicy::LogOnly => true,
+        DeadlockPolicy::Refuse => false,
+        DeadlockPolicy::KillVictim => {
+            // the victim is the requester: the one task of the report not
+            // parked in a wait queue, so no pending wait has to be cancelled
+            release_all(process, task);
+            task.exit(-0xDEAD);
+            false
+        }
+    }
+}
+
+/// Release every mutex, semaphore and rwlock `task` holds, for a killed victim
+fn release_all(process: &Arc<ProcessControlBlock>, task: &Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    let mutexes = core::mem::take(&mut task_inner.mutex_allocation);
+    let sems = core::mem::take(&mut task_inner.sem_allocation);
+    let rwlocks = core::mem::take(&mut task_inner.rwlock_allocation);
+    drop(task_inner);
+    let process_inner = process.inner_exclusive_access();
+    let mutexes: Vec<_> = mutexes
+        .iter()
+        .filter_map(|&id| process_inner.mutex_list[id].clone())
+        .collect();
+    let sems: Vec<_> = sems
+        .iter()
+        .filter_map(|&(id, count)| Some((process_inner.semaphore_list[id].clone()?, count)))
+        .collect();
+    let rwlocks: Vec<_> = rwlocks
+        .iter()
+        .filter_map(|&(id, _)| process_inner.rwlock_list[id].clone())
+        .collect();
+    drop(process_inner);
+    mutexes.iter().for_each(|mutex| mutex.unlock());
+    for (sem, count) in sems {
+        (0..count).for_each(|_| sem.up());
+    }
+    rwlocks.iter().for_each(|rwlock| {
+        rwlock.unlock();
+    });
+}
+
+/// Run Banker's Algorithm over all resources of `process`, returns the
+/// entries of the tasks that cannot finish
+fn detect(process: &Arc<ProcessControlBlock>) -> Option<Vec<DeadlockEntry>> {
+    let process_inner = process.inner_exclusive_access();
+    let n = process_inner.tasks.len();
+    // columns: mutexes, semaphores, rwlocks, condvars
+    let kinds = [
+        (ResourceKind::Mutex, process_inner.mutex_list.len()),
+        (ResourceKind::Semaphore, process_inner.semaphore_list.len()),
+        (ResourceKind::RwLock, process_inner.rwlock_list.len()),
+        (ResourceKind::Condvar, process_inner.condvar_list.len()),
+    ];
+    let mut columns: Vec<(ResourceKind, usize)> = Vec::new();
+    for (kind, len) in kinds {
+        columns.extend((0..len).map(|id| (kind, id)));
+    }
+    let base = |kind: ResourceKind| -> usize {
+        kinds.iter().take_while(|(k, _)| *k != kind).map(|(_, len)| len).sum()
+    };
+    let (sem_base, rw_base, cv_base) = (
+        base(ResourceKind::Semaphore),
+        base(ResourceKind::RwLock),
+        base(ResourceKind::Condvar),
+    );
+    let m = columns.len();
+    if m == 0 {
+        return None;
+    }
+
+    let mut available: Vec<usize> = vec![0; m];
+    for units in available.iter_mut().take(process_inner.mutex_list.len()) {
+        *units = 1;
+    }
+    for (id, sem) in process_inner.semaphore_list.iter().enumerate() {
+        if let Some(sem) = sem {
+            available[sem_base + id] = max(sem.inner.exclusive_access().count, 0) as usize;
+        }
+    }
+    // a rwlock has n units: a reader holds one, a writer all of them
+    for id in 0..process_inner.rwlock_list.len() {
+        available[rw_base + id] = n;
+    }
+    let mut write_waiting: Vec<bool> = vec![false; process_inner.rwlock_list.len()];
+    for task in process_inner.tasks.iter().flatten() {
+        let task_inner = task.inner_exclusive_access();
+        if task_inner.rwlock_need != usize::MAX && task_inner.rwlock_need_write {
+            write_waiting[task_inner.rwlock_need] = true;
+        }
+    }
+
+    let mut allocation: Vec<Vec<usize>> = vec![vec![0; m]; n];
+    let mut need: Vec<Vec<usize>> = vec![vec![0; m]; n];
+    for (i, task_opt) in process_inner.tasks.iter().enumerate() {
+        let task = match task_opt {
+            Some(task) => task,
+            None => continue,
+        };
+        let task_inner = task.inner_exclusive_access();
+        // an exited task holds nothing and signals no condvar, its row stays
+        // empty and it finishes at once
+        if task_inner.zombie {
+            continue;
+        }
+        for &id in &task_inner.mutex_allocation {
+            allocation[i][id] += 1;
+            available[id] -= 1;
+        }
+        if task_inner.mutex_need != usize::MAX {
+            need[i][task_inner.mutex_need] += 1;
+        }
+        for &(id, count) in &task_inner.sem_allocation {
+            allocation[i][sem_base + id] += count;
+        }
+        if task_inner.sem_need != usize::MAX {
+            need[i][sem_base + task_inner.sem_need] += 1;
+        }
+        // a task taking the same rwlock again still holds one reader's share
+        // (or the writer's), so the units held never exceed the n there are
+        for &(id, write) in &task_inner.rwlock_allocation {
+            let units = if write { n } else { 1 };
+            let held = &mut allocation[i][rw_base + id];
+            let taken = units.saturating_sub(*held);
+            *held += taken;
+            available[rw_base + id] = available[rw_base + id].saturating_sub(taken);
+        }
+        let nid = task_inner.rwlock_need;
+        if nid != usize::MAX {
+            // with writer priority a reader behind a waiting writer gets in
+            // only once the writer is through
+            need[i][rw_base + nid] += if task_inner.rwlock_need_write || write_waiting[nid] {
+                n
+            } else {
+                1
+            };
+        }
+        // a condvar is signalled by any task not waiting on it: each of them
+        // holds one unit, released when it finishes
+        for id in 0..process_inner.condvar_list.len() {
+            if task_inner.condvar_need == id {
+                need[i][cv_base + id] += 1;
+            } else {
+                allocation[i][cv_base + id] += 1;
+            }
+        }
+    }
+
+    let finish = deadlock_check(available, &allocation, &need);
+    let mut entries = Vec::new();
+    for (i, task_opt) in process_inner.tasks.iter().enumerate() {
+        let task = match task_opt {
+            Some(task) if !finish[i] => task,
+            _ => continue,
+        };
+        let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+        for (j, &(kind, res_id)) in columns.iter().enumerate() {
+            // every task "holds" the condvars it does not wait on, not worth reporting
+            let held = allocation[i][j] > 0 && kind != ResourceKind::Condvar;
+            if held || need[i][j] > 0 {
+                entries.push(DeadlockEntry {
+                    tid,
+                    kind: kind as usize,
+                    res_id,
+                    waiting: (need[i][j] > 0) as usize,
+                });
+            }
+        }
+    }
+    match entries.is_empty() {
+        true => None,
+        false => Some(entries),
+    }
+}
+
+/// Banker's Algorithm safety check, returns which tasks can finish
+fn deadlock_check(available: Vec<usize>, allocation: &[Vec<usize>], need: &[Vec<usize>]) -> Vec<bool> {
+    // n: thread count  m: resources count
+    let (n, m) = (allocation.len(), available.len());
+    let mut work = available;
+    let mut finish = vec![false; n];
+    // a slot without a task has no allocation and no need, it finishes at once
+    while let Some(idx) = (0..n).find(|&i| !finish[i] && (0..m).all(|j| need[i][j] <= work[j])) {
+        for (units, allocated) in work.iter_mut().zip(&allocation[idx]) {
+            *units += allocated;
+        }
+        finish[idx] = true;
+    }
+    finish
+}
+
+/// test that a deadlocked pair recovers once the victim is killed
+#[allow(unused)]
+pub fn deadlock_test() {
+    let process = ProcessControlBlock::new_bare(2);
+    let (m0, m1): (Arc<dyn Mutex>, Arc<dyn Mutex>) =
+        (Arc::new(MutexBlocking::new()), Arc::new(MutexBlocking::new()));
+    let sem = Arc::new(Semaphore::new(1));
+    let rwlock = Arc::new(RwLock::new());
+    let mut process_inner = process.inner_exclusive_access();
+    process_inner.mutex_list = vec![Some(m0.clone()), Some(m1.clone())];
+    process_inner.semaphore_list = vec![Some(sem.clone())];
+    process_inner.rwlock_list = vec![Some(rwlock.clone())];
+    process_inner.dlcheck_option = true;
+    process_inner.dlcheck_policy = DeadlockPolicy::KillVictim;
+    let (a, b) = (process_inner.get_task(0), process_inner.get_task(1));
+    drop(process_inner);
+    // a holds m0, a unit of the semaphore and a read of the rwlock, b holds m1
+    m0.lock();
+    m1.lock();
+    sem.inner.exclusive_access().count -= 1;
+    rwlock.inner.exclusive_access().readers += 1;
+    let mut a_inner = a.inner_exclusive_access();
+    a_inner.mutex_allocation.push(0);
+    a_inner.sem_allocation.push((0, 1));
+    a_inner.rwlock_allocation.push((0, false));
+    drop(a_inner);
+    b.inner_exclusive_access().mutex_allocation.push(1);
+    // b waits for m0, which is no deadlock yet
+    b.inner_exclusive_access().mutex_need = 0;
+    assert!(check_request(&process, &b));
+    // a asking for m1 closes the cycle, a is killed and its locks released
+    a.inner_exclusive_access().mutex_need = 1;
+    assert!(!check_request(&process, &a));
+    a.inner_exclusive_access().mutex_need = usize::MAX;
+    assert!(a.is_zombie());
+    assert_eq!(a.inner_exclusive_access().exit_code, Some(-0xDEAD));
+    let report = process.inner_exclusive_access().deadlock_report.take().unwrap();
+    assert_eq!(report.requester, 0);
+    assert!(report.entries.iter().any(|entry| entry.tid == 1));
+    assert_eq!(sem.inner.exclusive_access().count, 1);
+    assert_eq!(rwlock.inner.exclusive_access().readers, 0);
+    // b gets m0, it would block (with no task to block) were m0 still held
+    assert!(detect(&process).is_none());
+    m0.lock();
+    let mut b_inner = b.inner_exclusive_access();
+    b_inner.mutex_need = usize::MAX;
+    b_inner.mutex_allocation.push(0);
+    drop(b_inner);
+    assert!(detect(&process).is_none());
+    // a dropped task frees kernel stack 0, which none of them allocated
+    core::mem::forget(process);
+    println!("deadlock_test passed!");
+}
@@ -0,0 +1,153 @@
+//! Futex wait queues
+//!
+//! Queues are keyed by the physical address of the futex word, so processes
+//! sharing a page share the queue. A waiter parks its executor task on a
+//! [`FutexWait`] future until it is woken.
+
+use crate::sync::UPSafeCell;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use lazy_static::*;
+
+/// bitset matching every waiter
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// A task blocked on a futex
+pub struct FutexWaiter {
+    /// wake only if a wake bitset intersects this one
+    bitset: u32,
+    inner: UPSafeCell<FutexWaiterInner>,
+}
+
+struct FutexWaiterInner {
+    /// key of the queue the waiter sits in, changed by requeue
+    key: usize,
+    woken: bool,
+    waker: Option<Waker>,
+}
+
+impl FutexWaiter {
+    /// Whether the waiter has been woken
+    pub fn is_woken(&self) -> bool {
+        self.inner.exclusive_access().woken
+    }
+    fn wake(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.woken = true;
+        if let Some(waker) = inner.waker.take() {
+            drop(inner);
+            waker.wake();
+        }
+    }
+}
+
+lazy_static! {
+    /// FUTEX_QUEUES: physical address of a futex word -> its waiters
+    static ref FUTEX_QUEUES: UPSafeCell<BTreeMap<usize, VecDeque<Arc<FutexWaiter>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Queue a new waiter on `key`. The caller must have checked the futex word
+/// without yielding, so no wakeup can be lost.
+pub fn futex_enqueue(key: usize, bitset: u32) -> Arc<FutexWaiter> {
+    let waiter = Arc::new(FutexWaiter {
+        bitset,
+        inner: unsafe {
+            UPSafeCell::new(FutexWaiterInner {
+                key,
+                woken: false,
+                waker: None,
+            })
+        },
+    });
+    FUTEX_QUEUES
+        .exclusive_access()
+        .entry(key)
+        .or_default()
+        .push_back(Arc::clone(&waiter));
+    waiter
+}
+
+/// Remove a waiter that gave up, e.g. on timeout
+pub fn futex_dequeue(waiter: &Arc<FutexWaiter>) {
+    let key = waiter.inner.exclusive_access().key;
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    if let Some(queue) = queues.get_mut(&key) {
+        queue.retain(|w| !Arc::ptr_eq(w, waiter));
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+    }
+}
+
+/// Wake at most `count` waiters on `key` whose bitset intersects `bitset`,
+/// returns the number woken
+pub fn futex_wake(key: usize, count: usize, bitset: u32) -> usize {
+    let mut woken = VecDeque::new();
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    if let Some(queue) = queues.get_mut(&key) {
+        let mut i = 0;
+        while i < queue.len() && woken.len() < count {
+            if queue[i].bitset & bitset != 0 {
+                woken.push_back(queue.remove(i).unwrap());
+            } else {
+                i += 1;
+            }
+        }
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+    }
+    drop(queues);
+    // wake after releasing the queues
+    let n = woken.len();
+    woken.iter().for_each(|waiter| waiter.wake());
+    n
+}
+
+/// Wake at most `wake_count` waiters on `key` and move at most `requeue_count`
+/// of the rest to `key2`, returns (woken, requeued)
+pub fn futex_requeue(key: usize, wake_count: usize, key2: usize, requeue_count: usize) -> (usize, usize) {
+    let woken = futex_wake(key, wake_count, FUTEX_BITSET_MATCH_ANY);
+    if key == key2 {
+        return (woken, 0);
+    }
+    let mut queues = FUTEX_QUEUES.exclusive_access();
+    let moved: VecDeque<Arc<FutexWaiter>> = match queues.get_mut(&key) {
+        Some(queue) => {
+            let n = requeue_count.min(queue.len());
+            let moved = queue.drain(..n).collect();
+            if queue.is_empty() {
+                queues.remove(&key);
+            }
+            moved
+        }
+        None => VecDeque::new(),
+    };
+    let requeued = moved.len();
+    for waiter in moved.iter() {
+        waiter.inner.exclusive_access().key = key2;
+    }
+    if requeued > 0 {
+        queues.entry(key2).or_default().extend(moved);
+    }
+    (woken, requeued)
+}
+
+/// Future parking the current task until the waiter is woken
+pub struct FutexWait(pub Arc<FutexWaiter>);
+
+impl Future for FutexWait {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.0.inner.exclusive_access();
+        if inner.woken {
+            return Poll::Ready(());
+        }
+        inner.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
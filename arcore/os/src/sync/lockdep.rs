@@ -0,0 +1,176 @@
+//! Lock dependency validator for [`UPSafeCell`](super::UPSafeCell)
+//!
+//! Enabled with the `lockdep` feature. A lock class is the place a cell is
+//! created, unless its owner names one with
+//! [`UPSafeCell::new_with_class`](super::UPSafeCell::new_with_class), as
+//! process and task inner cells do, so that a cell built by `fork` is of one
+//! class with those built by `create`.
+//! Every borrow records, for each class already held, the order "held before
+//! acquired" together with both call sites. A borrow that closes a cycle in
+//! that order, or borrows a cell that is borrowed already, is reported with
+//! the call sites of both acquisitions.
+//!
+//! The order the kernel keeps is
+//!
+//! - `PID2PCB`, `TASK_MANAGER` and `PROCESSOR` are only held for a lookup and
+//!   are not held while taking any other cell;
+//! - a process inner cell is taken before the inner cells of its tasks, never
+//!   while one of them is held;
+//! - synchronization primitives (semaphores, rwlocks, wait queues) are taken
+//!   after process and task inner cells are dropped, except for reading
+//!   counts in deadlock detection;
+//! - `BLOCK_CACHE_MANAGER` and the block caches are spin locks inside easy-fs,
+//!   taken and dropped within one fs call, so they are always innermost.
+//!
+//! Only [`UPSafeCell`](super::UPSafeCell) is validated. The easy-fs spin locks
+//! (`BLOCK_CACHE_MANAGER`, the block caches and the file system lock) live in
+//! a crate that knows nothing of the kernel and are not checked; the last rule
+//! above holds for them by convention only.
+//!
+//! Cells are borrowed only in kernel code that does not yield, so a single
+//! stack of held locks describes the hart.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt;
+use core::panic::Location;
+use lazy_static::*;
+use spin::Mutex;
+
+type Site = &'static Location<'static>;
+
+/// Lock class of a cell
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockClass {
+    /// the place the cell is created
+    Site(Site),
+    /// a key named by the owner of the cell
+    Named(&'static str),
+}
+
+impl fmt::Display for LockClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Site(site) => write!(f, "{}", site),
+            Self::Named(name) => f.write_str(name),
+        }
+    }
+}
+
+/// A borrow in progress
+struct HeldLock {
+    class: LockClass,
+    instance: usize,
+    site: Site,
+}
+
+/// First observation of "`from` held while `to` acquired"
+#[derive(Clone, Copy)]
+struct Dependency {
+    from_site: Site,
+    to_site: Site,
+}
+
+struct LockDep {
+    held: Vec<HeldLock>,
+    /// class -> classes acquired while holding it
+    graph: BTreeMap<LockClass, BTreeMap<LockClass, Dependency>>,
+    /// class pairs already reported, each inversion is printed once
+    reported: BTreeSet<(LockClass, LockClass)>,
+}
+
+lazy_static! {
+    /// LOCKDEP: global lock dependency state, a spin lock so that it is not
+    /// validated itself
+    static ref LOCKDEP: Mutex<LockDep> = Mutex::new(LockDep {
+        held: Vec::new(),
+        graph: BTreeMap::new(),
+        reported: BTreeSet::new(),
+    });
+}
+
+/// Validate and record the borrow of `instance` of `class` at `site`
+pub fn acquire(class: LockClass, instance: usize, site: Site) {
+    let mut lockdep = LOCKDEP.lock();
+    if let Some(held) = lockdep.held.iter().find(|held| held.instance == instance) {
+        let first = held.site;
+        drop(lockdep);
+        println!("[lockdep] recursive borrow of lock class {}", class);
+        println!("[lockdep]   first acquired at {}", first);
+        println!("[lockdep]   acquired again at {}", site);
+        panic!("lockdep: recursive borrow");
+    }
+    let held: Vec<(LockClass, Site)> = lockdep.held.iter().map(|held| (held.class, held.site)).collect();
+    for (held_class, held_site) in held {
+        if held_class == class {
+            // nesting two cells of one class, e.g. two task inner cells
+            continue;
+        }
+        if let Some(path) = lockdep.path(class, held_class) {
+            if lockdep.reported.insert((held_class, class)) {
+                println!(
+                    "[lockdep] lock order inversion: acquiring {} while holding {}",
+                    class, held_class
+                );
+                println!("[lockdep]   {} acquired at {}", held_class, held_site);
+                println!("[lockdep]   {} acquired at {}", class, site);
+                println!("[lockdep] but the opposite order was seen before:");
+                for (from, to, dep) in path {
+                    println!("[lockdep]   {} held at {}", from, dep.from_site);
+                    println!("[lockdep]   {} then acquired at {}", to, dep.to_site);
+                }
+            }
+            continue;
+        }
+        lockdep
+            .graph
+            .entry(held_class)
+            .or_default()
+            .entry(class)
+            .or_insert(Dependency {
+                from_site: held_site,
+                to_site: site,
+            });
+    }
+    lockdep.held.push(HeldLock {
+        class,
+        instance,
+        site,
+    });
+}
+
+/// Record the end of the borrow of `instance`
+pub fn release(instance: usize) {
+    let mut lockdep = LOCKDEP.lock();
+    // borrows may end out of order
+    if let Some(index) = lockdep.held.iter().rposition(|held| held.instance == instance) {
+        lockdep.held.remove(index);
+    }
+}
+
+impl LockDep {
+    /// A chain of recorded dependencies from `from` to `to`
+    fn path(
+        &self,
+        from: LockClass,
+        to: LockClass,
+    ) -> Option<Vec<(LockClass, LockClass, Dependency)>> {
+        let mut visited = BTreeSet::new();
+        let mut stack: Vec<(LockClass, Vec<(LockClass, LockClass, Dependency)>)> =
+            alloc::vec![(from, Vec::new())];
+        while let Some((class, path)) = stack.pop() {
+            if !visited.insert(class) {
+                continue;
+            }
+            for (&next, &dep) in self.graph.get(&class).into_iter().flatten() {
+                let mut path = path.clone();
+                path.push((class, next, dep));
+                if next == to {
+                    return Some(path);
+                }
+                stack.push((next, path));
+            }
+        }
+        None
+    }
+}
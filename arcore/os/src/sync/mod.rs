@@ -1,10 +1,27 @@
 //! Synchronization and interior mutability primitives
 
+mod barrier;
 mod condvar;
+mod deadlock;
+mod futex;
+#[cfg(feature = "lockdep")]
+pub mod lockdep;
 mod mutex;
+mod rwlock;
 mod semaphore;
 mod up;
-pub use condvar::Condvar;
-pub use mutex::{Mutex, MutexBlocking, MutexSpin};
-pub use semaphore::Semaphore;
-pub use up::UPSafeCell;
+mod wait_queue;
+pub use barrier::{Barrier, BarrierWait};
+pub use condvar::{AsyncCondvar, Condvar};
+pub use deadlock::{
+    check_request, deadlock_test, DeadlockEntry, DeadlockPolicy, DeadlockReport, ResourceKind,
+};
+pub use futex::{
+    futex_dequeue, futex_enqueue, futex_requeue, futex_wake, FutexWait, FutexWaiter,
+    FUTEX_BITSET_MATCH_ANY,
+};
+pub use mutex::{AsyncMutex, AsyncMutexGuard, AsyncMutexLock, Mutex, MutexBlocking, MutexSpin};
+pub use rwlock::{RwLock, RwLockAcquire};
+pub use semaphore::{Semaphore, SemaphoreDown};
+pub use up::{UPRefMut, UPSafeCell};
+pub use wait_queue::WaitQueue;
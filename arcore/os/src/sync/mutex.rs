@@ -1,4 +1,8 @@
-//! Mutex (spin-like and blocking(sleep))
+//! Mutex (spin-like and blocking(sleep), and async for kernel futures)
+
+mod async_mutex;
+
+pub use async_mutex::{AsyncMutex, AsyncMutexGuard, AsyncMutexLock};
 
 use super::UPSafeCell;
 use crate::task::TaskControlBlock;
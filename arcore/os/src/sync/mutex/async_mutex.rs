@@ -0,0 +1,152 @@
+//! Mutex for kernel futures
+//!
+//! Unlike [`super::MutexBlocking`], waiting for an [`AsyncMutex`] parks the
+//! executor task instead of the thread, so the guard may be held across
+//! `.await`.
+
+use crate::sync::{UPSafeCell, WaitQueue};
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// A mutex whose lock operation is a future
+pub struct AsyncMutex<T: ?Sized> {
+    inner: UPSafeCell<AsyncMutexInner>,
+    data: UnsafeCell<T>,
+}
+
+struct AsyncMutexInner {
+    locked: bool,
+    wait_queue: WaitQueue,
+}
+
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    /// Create an unlocked mutex holding `data`
+    pub const fn new(data: T) -> Self {
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(AsyncMutexInner {
+                    locked: false,
+                    wait_queue: WaitQueue::new(),
+                })
+            },
+            data: UnsafeCell::new(data),
+        }
+    }
+    /// Consume the mutex, returns the data
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    /// Acquire the mutex, waiting in FIFO order
+    pub fn lock(&self) -> AsyncMutexLock<'_, T> {
+        AsyncMutexLock {
+            mutex: self,
+            ticket: None,
+            acquired: false,
+        }
+    }
+    /// Acquire the mutex if it is free
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        let mut inner = self.inner.exclusive_access();
+        if inner.locked {
+            return None;
+        }
+        inner.locked = true;
+        Some(AsyncMutexGuard { mutex: self })
+    }
+    /// Release the lock, handing it to the first waiter if any
+    fn unlock(&self) {
+        let mut inner = self.inner.exclusive_access();
+        assert!(inner.locked);
+        // the lock stays held on handoff, the woken waiter owns it
+        match inner.wait_queue.pop() {
+            Some(waker) => {
+                drop(inner);
+                waker.wake();
+            }
+            None => inner.locked = false,
+        }
+    }
+}
+
+/// Future of [`AsyncMutex::lock`]
+pub struct AsyncMutexLock<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+    /// ticket in the wait queue once parked
+    ticket: Option<usize>,
+    acquired: bool,
+}
+
+impl<'a, T: ?Sized> Future for AsyncMutexLock<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mutex = self.mutex;
+        let mut inner = mutex.inner.exclusive_access();
+        match self.ticket {
+            None if !inner.locked => inner.locked = true,
+            None => {
+                self.ticket = Some(inner.wait_queue.push(cx.waker().clone()));
+                return Poll::Pending;
+            }
+            Some(ticket) => {
+                if inner.wait_queue.update(ticket, cx.waker()) {
+                    return Poll::Pending;
+                }
+            }
+        }
+        self.acquired = true;
+        Poll::Ready(AsyncMutexGuard { mutex })
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexLock<'_, T> {
+    /// A waiter dropped while parked leaves the queue, or passes on the lock
+    /// if it was handed over already
+    fn drop(&mut self) {
+        if let (Some(ticket), false) = (self.ticket, self.acquired) {
+            let removed = self.mutex.inner.exclusive_access().wait_queue.remove(ticket);
+            if !removed {
+                self.mutex.unlock();
+            }
+        }
+    }
+}
+
+/// RAII guard of an [`AsyncMutex`], unlocks on drop
+pub struct AsyncMutexGuard<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+impl<'a, T: ?Sized> AsyncMutexGuard<'a, T> {
+    /// The mutex this guard locks
+    pub fn mutex(&self) -> &'a AsyncMutex<T> {
+        self.mutex
+    }
+}
+
+impl<T: ?Sized> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -0,0 +1,170 @@
+//! Reader-writer lock with writer priority
+//!
+//! A reader is admitted only while no writer holds or waits for the lock, so a
+//! stream of readers cannot starve writers. Waiters are handed the lock by the
+//! unlocking task and find it held when polled again.
+
+use crate::sync::{UPSafeCell, WaitQueue};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// Reader-writer lock structure
+pub struct RwLock {
+    /// rwlock inner
+    pub inner: UPSafeCell<RwLockInner>,
+}
+
+pub struct RwLockInner {
+    /// number of readers holding the lock
+    pub readers: usize,
+    /// whether a writer holds the lock
+    pub writer: bool,
+    read_queue: WaitQueue,
+    write_queue: WaitQueue,
+}
+
+impl RwLockInner {
+    /// Hand the lock to waiters if its state allows: one writer first, else
+    /// every queued reader
+    fn dispatch(&mut self) {
+        if self.writer {
+            return;
+        }
+        if !self.write_queue.is_empty() {
+            if self.readers == 0 {
+                self.writer = true;
+                self.write_queue.pop().unwrap().wake();
+            }
+            return;
+        }
+        while let Some(waker) = self.read_queue.pop() {
+            self.readers += 1;
+            waker.wake();
+        }
+    }
+}
+
+impl RwLock {
+    /// Create a new reader-writer lock
+    pub fn new() -> Self {
+        trace!("kernel: RwLock::new");
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(RwLockInner {
+                    readers: 0,
+                    writer: false,
+                    read_queue: WaitQueue::new(),
+                    write_queue: WaitQueue::new(),
+                })
+            },
+        }
+    }
+
+    /// Acquire the lock shared
+    pub fn read(&self) -> RwLockAcquire<'_> {
+        trace!("kernel: RwLock::read");
+        RwLockAcquire {
+            lock: self,
+            write: false,
+            ticket: None,
+            acquired: false,
+        }
+    }
+
+    /// Acquire the lock exclusive
+    pub fn write(&self) -> RwLockAcquire<'_> {
+        trace!("kernel: RwLock::write");
+        RwLockAcquire {
+            lock: self,
+            write: true,
+            ticket: None,
+            acquired: false,
+        }
+    }
+
+    /// Release a hold of the lock, returns false if it was not held
+    pub fn unlock(&self) -> bool {
+        trace!("kernel: RwLock::unlock");
+        let mut inner = self.inner.exclusive_access();
+        if inner.writer {
+            inner.writer = false;
+        } else if inner.readers > 0 {
+            inner.readers -= 1;
+        } else {
+            return false;
+        }
+        inner.dispatch();
+        true
+    }
+}
+
+impl Default for RwLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Future of [`RwLock::read`] and [`RwLock::write`]
+pub struct RwLockAcquire<'a> {
+    lock: &'a RwLock,
+    write: bool,
+    /// ticket in the read or write queue once parked
+    ticket: Option<usize>,
+    acquired: bool,
+}
+
+impl Future for RwLockAcquire<'_> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.lock.inner.exclusive_access();
+        let inner = &mut *inner;
+        let queue = match self.write {
+            true => &mut inner.write_queue,
+            false => &mut inner.read_queue,
+        };
+        match self.ticket {
+            Some(ticket) => {
+                if queue.update(ticket, cx.waker()) {
+                    return Poll::Pending;
+                }
+            }
+            None if self.write => {
+                if inner.writer || inner.readers > 0 || !inner.write_queue.is_empty() {
+                    self.ticket = Some(inner.write_queue.push(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+                inner.writer = true;
+            }
+            None => {
+                if inner.writer || !inner.write_queue.is_empty() {
+                    self.ticket = Some(inner.read_queue.push(cx.waker().clone()));
+                    return Poll::Pending;
+                }
+                inner.readers += 1;
+            }
+        }
+        self.acquired = true;
+        Poll::Ready(())
+    }
+}
+
+impl Drop for RwLockAcquire<'_> {
+    /// A waiter dropped while parked leaves its queue, which may unblock
+    /// readers held back by it, or releases the hold it was handed
+    fn drop(&mut self) {
+        if let (Some(ticket), false) = (self.ticket, self.acquired) {
+            let mut inner = self.lock.inner.exclusive_access();
+            let removed = match self.write {
+                true => inner.write_queue.remove(ticket),
+                false => inner.read_queue.remove(ticket),
+            };
+            if removed {
+                inner.dispatch();
+            } else {
+                drop(inner);
+                self.lock.unlock();
+            }
+        }
+    }
+}
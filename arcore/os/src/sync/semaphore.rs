@@ -1,10 +1,11 @@
 //! Semaphore
 
-use core::panic;
-
 use crate::sync::UPSafeCell;
-use crate::task::/* {block_current_and_run_next, current_task, wakeup_task, */ TaskControlBlock;
+use crate::task::{current_task, TaskControlBlock};
 use alloc::{collections::VecDeque, sync::Arc};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
 
 /// semaphore structure
 pub struct Semaphore {
@@ -14,7 +15,8 @@ pub struct Semaphore {
 
 pub struct SemaphoreInner {
     pub count: isize,
-    pub wait_queue: VecDeque<Arc<TaskControlBlock>>,
+    /// blocked tasks with the waker of their pending `down`
+    pub wait_queue: VecDeque<(Arc<TaskControlBlock>, Waker)>,
 }
 
 impl Semaphore {
@@ -31,48 +33,102 @@ impl Semaphore {
         }
     }
 
-    /// up operation of semaphore
+    /// up operation of semaphore, hands the resource to the first waiter if any
     pub fn up(&self) {
         trace!("kernel: Semaphore::up");
-        panic!("waitdo");
-        /* let mut inner = self.inner.exclusive_access();
+        let mut inner = self.inner.exclusive_access();
         inner.count += 1;
         if inner.count <= 0 {
-            if let Some(task) = inner.wait_queue.pop_front() {
-                let mut task_inner = task.inner_exclusive_access();
-                              let sem_id = task_inner.sem_need;
-                              match task_inner.sem_allocation.iter().position(|&x| x.0 == sem_id) {
-                                  Some(index) => task_inner.sem_allocation[index].1 += 1,
-                                  None => task_inner.sem_allocation.push((sem_id, 1)),
-                              }
-                              task_inner.sem_need = usize::MAX;
-                             drop(task_inner);
-                wakeup_task(task);
+            if let Some((task, waker)) = inner.wait_queue.pop_front() {
+                drop(inner);
+                grant(&task);
+                waker.wake();
             }
-        } */
+        }
     }
 
-    /// down operation of semaphore
-    pub fn down(&self) {
+    /// down operation of semaphore, resolves once the resource is granted
+    ///
+    /// The semaphore id is taken from `sem_need` of the current task, which
+    /// the caller sets before awaiting.
+    pub fn down(&self) -> SemaphoreDown<'_> {
         trace!("kernel: Semaphore::down");
-        panic!("waitdo");
-        /* let mut inner = self.inner.exclusive_access();
-        inner.count -= 1;
-        if inner.count < 0 {
-            inner.wait_queue.push_back(current_task().unwrap());
+        SemaphoreDown {
+            sem: self,
+            task: current_task().unwrap(),
+            queued: false,
+        }
+    }
+}
+
+/// Move the `sem_need` of `task` into its `sem_allocation`
+fn grant(task: &Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    let sem_id = task_inner.sem_need;
+    match task_inner.sem_allocation.iter().position(|&x| x.0 == sem_id) {
+        Some(index) => task_inner.sem_allocation[index].1 += 1,
+        None => task_inner.sem_allocation.push((sem_id, 1)),
+    }
+    task_inner.sem_need = usize::MAX;
+}
+
+/// Future of [`Semaphore::down`]
+pub struct SemaphoreDown<'a> {
+    sem: &'a Semaphore,
+    task: Arc<TaskControlBlock>,
+    /// whether the task is (or was) in the wait queue
+    queued: bool,
+}
+
+impl Future for SemaphoreDown<'_> {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut inner = self.sem.inner.exclusive_access();
+        if !self.queued {
+            inner.count -= 1;
+            if inner.count >= 0 {
+                drop(inner);
+                grant(&self.task);
+                return Poll::Ready(());
+            }
+            inner
+                .wait_queue
+                .push_back((Arc::clone(&self.task), cx.waker().clone()));
+            drop(inner);
+            self.queued = true;
+            return Poll::Pending;
+        }
+        // `up` removes the task from the queue when granting
+        match inner
+            .wait_queue
+            .iter_mut()
+            .find(|(task, _)| Arc::ptr_eq(task, &self.task))
+        {
+            Some((_, waker)) => {
+                waker.clone_from(cx.waker());
+                Poll::Pending
+            }
+            None => Poll::Ready(()),
+        }
+    }
+}
+
+impl Drop for SemaphoreDown<'_> {
+    /// A waiter dropped before being granted gives its place back
+    fn drop(&mut self) {
+        if !self.queued {
+            return;
+        }
+        let mut inner = self.sem.inner.exclusive_access();
+        if let Some(index) = inner
+            .wait_queue
+            .iter()
+            .position(|(task, _)| Arc::ptr_eq(task, &self.task))
+        {
+            inner.wait_queue.remove(index);
+            inner.count += 1;
             drop(inner);
-            block_current_and_run_next();
-        }else {
-                        let task = current_task().unwrap();
-                        let mut task_inner = task.inner_exclusive_access();
-                        let sem_id = task_inner.sem_need;
-                        match task_inner.sem_allocation.iter().position(|&x| x.0 == sem_id) {
-                            Some(index) => task_inner.sem_allocation[index].1 += 1,
-                            None => task_inner.sem_allocation.push((sem_id, 1)),
-                        }
-                        task_inner.sem_need = usize::MAX;
-                       drop(task_inner);
-                        drop(task);
-                    } */
+            self.task.inner_exclusive_access().sem_need = usize::MAX;
+        }
     }
 }
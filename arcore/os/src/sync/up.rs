@@ -3,8 +3,15 @@
 //! UPSafeCell is used to wrap a static data structure which can access safely.
 //!
 //! NOTICE: We should only use it in environment with uniprocessor（single cpu core）, and the kernel can not support task preempting in kernel mode （or trap in kernel mode）.
+//!
+//! With the `lockdep` feature every borrow is checked by [`super::lockdep`].
 
 use core::cell::{RefCell, RefMut};
+use core::ops::{Deref, DerefMut};
+#[cfg(feature = "lockdep")]
+use super::lockdep::LockClass;
+#[cfg(feature = "lockdep")]
+use core::panic::Location;
 
 /// Wrap a static data structure inside it so that we are
 /// able to access it without any `unsafe`.
@@ -16,6 +23,9 @@ use core::cell::{RefCell, RefMut};
 pub struct UPSafeCell<T> {
     /// inner data
     inner: RefCell<T>,
+    /// lock class, the place the cell is created unless named
+    #[cfg(feature = "lockdep")]
+    class: LockClass,
 }
 
 unsafe impl<T> Sync for UPSafeCell<T> {}
@@ -23,13 +33,62 @@ unsafe impl<T> Sync for UPSafeCell<T> {}
 impl<T> UPSafeCell<T> {
     /// User is responsible to guarantee that inner struct is only used in
     /// uniprocessor.
+    #[track_caller]
     pub const unsafe fn new(value: T) -> Self {
         Self {
             inner: RefCell::new(value),
+            #[cfg(feature = "lockdep")]
+            class: LockClass::Site(Location::caller()),
+        }
+    }
+    /// Same as `new`, in lock class `class` wherever the cell is created,
+    /// for cells of one kind built in several places.
+    #[allow(unused_variables)]
+    pub const unsafe fn new_with_class(value: T, class: &'static str) -> Self {
+        Self {
+            inner: RefCell::new(value),
+            #[cfg(feature = "lockdep")]
+            class: LockClass::Named(class),
         }
     }
     /// Panic if the data has been borrowed.
-    pub fn exclusive_access(&self) -> RefMut<'_, T> {
-        self.inner.borrow_mut()
+    #[track_caller]
+    pub fn exclusive_access(&self) -> UPRefMut<'_, T> {
+        #[cfg(feature = "lockdep")]
+        super::lockdep::acquire(self.class, self as *const _ as usize, Location::caller());
+        UPRefMut {
+            inner: self.inner.borrow_mut(),
+            #[cfg(feature = "lockdep")]
+            instance: self as *const _ as usize,
+        }
+    }
+}
+
+/// Borrow of the data in a [`UPSafeCell`]
+pub struct UPRefMut<'a, T> {
+    inner: RefMut<'a, T>,
+    #[cfg(feature = "lockdep")]
+    instance: usize,
+}
+
+impl<T> Deref for UPRefMut<'_, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for UPRefMut<'_, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+#[cfg(feature = "lockdep")]
+impl<T> Drop for UPRefMut<'_, T> {
+    fn drop(&mut self) {
+        super::lockdep::release(self.instance);
     }
 }
@@ -0,0 +1,59 @@
+//! FIFO queue of parked futures
+//!
+//! Each waiter gets a ticket. A waiter whose ticket is no longer in the queue
+//! has been handed what it waited for.
+
+use alloc::collections::VecDeque;
+use core::task::Waker;
+
+/// Wakers of parked futures, in arrival order
+#[derive(Default)]
+pub struct WaitQueue {
+    next_ticket: usize,
+    waiters: VecDeque<(usize, Waker)>,
+}
+
+impl WaitQueue {
+    /// Create an empty wait queue
+    pub const fn new() -> Self {
+        Self {
+            next_ticket: 0,
+            waiters: VecDeque::new(),
+        }
+    }
+    /// Park `waker` at the tail, returns its ticket
+    pub fn push(&mut self, waker: Waker) -> usize {
+        let ticket = self.next_ticket;
+        self.next_ticket = self.next_ticket.wrapping_add(1);
+        self.waiters.push_back((ticket, waker));
+        ticket
+    }
+    /// Remove the head waiter, returns its waker
+    pub fn pop(&mut self) -> Option<Waker> {
+        self.waiters.pop_front().map(|(_, waker)| waker)
+    }
+    /// Replace the waker of `ticket`, returns false if it was popped already
+    pub fn update(&mut self, ticket: usize, waker: &Waker) -> bool {
+        match self.waiters.iter_mut().find(|(t, _)| *t == ticket) {
+            Some((_, old)) => {
+                old.clone_from(waker);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Remove `ticket`, returns false if it was popped already
+    pub fn remove(&mut self, ticket: usize) -> bool {
+        match self.waiters.iter().position(|(t, _)| *t == ticket) {
+            Some(index) => {
+                self.waiters.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Whether no future is parked
+    pub fn is_empty(&self) -> bool {
+        self.waiters.is_empty()
+    }
+}
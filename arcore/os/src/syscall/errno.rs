@@ -0,0 +1,44 @@
+//! Error numbers returned (negated) by syscalls
+
+/// operation not permitted
+pub const EPERM: isize = 1;
+/// no such file or directory
+pub const ENOENT: isize = 2;
+/// no such device or address
+pub const ENXIO: isize = 6;
+/// argument list too long
+pub const E2BIG: isize = 7;
+/// exec format error
+pub const ENOEXEC: isize = 8;
+/// bad file number
+pub const EBADF: isize = 9;
+/// try again
+pub const EAGAIN: isize = 11;
+/// permission denied
+pub const EACCES: isize = 13;
+/// bad address
+pub const EFAULT: isize = 14;
+/// file exists
+pub const EEXIST: isize = 17;
+/// file too large
+pub const EFBIG: isize = 27;
+/// illegal seek
+pub const ESPIPE: isize = 29;
+/// not a directory
+pub const ENOTDIR: isize = 20;
+/// is a directory
+pub const EISDIR: isize = 21;
+/// invalid argument
+pub const EINVAL: isize = 22;
+/// math result not representable
+pub const ERANGE: isize = 34;
+/// file name too long
+pub const ENAMETOOLONG: isize = 36;
+/// function not implemented
+pub const ENOSYS: isize = 38;
+/// directory not empty
+pub const ENOTEMPTY: isize = 39;
+/// too many symbolic links encountered
+pub const ELOOP: isize = 40;
+/// connection timed out
+pub const ETIMEDOUT: isize = 110;
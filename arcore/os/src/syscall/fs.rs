@@ -1,14 +1,61 @@
-use crate::fs::{make_pipe, open_file, OpenFlags, Stat};
-use crate::mm::{kernel_token, translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
+use super::errno::{
+    EACCES, EBADF, EEXIST, EFBIG, EINVAL, EISDIR, ENAMETOOLONG, ENOENT, ENOTDIR, ENOTEMPTY,
+    EPERM, ERANGE,
+};
+use super::time::TimeSpec;
+use crate::fs::{
+    dir_path, inode_stat, lookup, make_pipe, open_file_at, split_path, Cred, File,
+    OpenFlags, Stat, ROOT_INODE, W_OK, X_OK,
+};
+use crate::mm::{
+    copy_from_user, copy_to_user, translated_byte_buffer, translated_refmut, translated_str,
+    UserBuffer,
+};
 use crate::task::{current_process, current_task, current_user_token};
+use crate::timer::get_realtime_ns;
 use alloc::sync::Arc;
+use easy_fs::{Inode, RenameMode, NAME_LENGTH_LIMIT, SYMLINK_LENGTH_LIMIT};
+
+/// special dirfd: paths are relative to the current working directory
+pub const AT_FDCWD: isize = -100;
+
+/// The current working directory of the current process
+pub fn current_cwd() -> Result<Arc<Inode>, isize> {
+    Ok(current_process().inner_exclusive_access().cwd.clone())
+}
+
+/// The credentials of the current process
+pub fn current_cred() -> Cred {
+    current_process().inner_exclusive_access().cred
+}
+
+/// The directory a relative `path` given with `dirfd` starts at
+fn dir_of(dirfd: isize, path: &str) -> Result<Arc<Inode>, isize> {
+    if path.starts_with('/') {
+        return Ok(ROOT_INODE.clone());
+    }
+    if dirfd == AT_FDCWD {
+        return current_cwd();
+    }
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let file = match inner.fd_table.get(dirfd as usize) {
+        Some(Some(file)) => file.clone(),
+        _ => return Err(-EBADF),
+    };
+    drop(inner);
+    match file.inode() {
+        Some(inode) if inode.is_dir() => Ok(inode),
+        _ => Err(-ENOTDIR),
+    }
+}
 /// write syscall
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     trace!(
         "kernel:pid[{}] sys_write",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    let token = kernel_token();
+    let token = current_user_token();
     let process = current_process();
     let inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
@@ -21,7 +68,12 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        let size = file.write(UserBuffer::new(translated_byte_buffer(token, buf, len)));
+        // nothing written to a file at the max file size
+        if size == 0 && len > 0 && file.inode().is_some() {
+            return -EFBIG;
+        }
+        size as isize
     } else {
         -1
     }
@@ -32,7 +84,7 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         "kernel:pid[{}] sys_read",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    let token = kernel_token();
+    let token = current_user_token();
     let process = current_process();
     let inner = process.inner_exclusive_access();
     if fd >= inner.fd_table.len() {
@@ -53,22 +105,31 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         -1
     }
 }
-/// open sys
-pub fn sys_open(path: *const u8, flags: u32) -> isize {
+/// openat syscall, `mode` is the permission bits of a created file
+pub fn sys_openat(dirfd: isize, path: *const u8, flags: u32, mode: u32) -> isize {
     trace!(
-        "kernel:pid[{}] sys_open",
+        "kernel:pid[{}] sys_openat",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
-        let mut inner = process.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
-    } else {
-        -1
+    let dir = match dir_of(dirfd, &path) {
+        Ok(dir) => dir,
+        Err(err) => return err,
+    };
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => return -1,
+    };
+    match open_file_at(&dir, path.as_str(), flags, mode as u16, current_cred()) {
+        Ok(inode) => {
+            let mut inner = process.inner_exclusive_access();
+            let fd = inner.alloc_fd();
+            inner.fd_table[fd] = Some(inode);
+            fd as isize
+        }
+        Err(err) => err,
     }
 }
 /// close syscall
@@ -125,29 +186,708 @@ pub fn sys_dup(fd: usize) -> isize {
     new_fd as isize
 }
 
-/// YOUR JOB: Implement fstat.
-pub fn sys_fstat(_fd: usize, _st: *mut Stat) -> isize {
+/// fstat syscall
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_fstat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let file = match file_of(fd) {
+        Ok(file) => file,
+        Err(err) => return err,
+    };
+    copy_to_user(current_user_token(), st, &file.stat());
+    0
+}
+
+/// lseek syscall
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_lseek",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    match file_of(fd).and_then(|file| file.seek(offset, whence)) {
+        Ok(offset) => offset as isize,
+        Err(err) => err,
+    }
+}
+
+/// Set the length of the file `inode`, write permission is checked by the caller
+fn truncate(inode: &Inode, length: isize) -> isize {
+    if length < 0 {
+        return -EINVAL;
+    }
+    if inode.is_dir() {
+        return -EISDIR;
+    }
+    match inode.truncate(length as usize) {
+        true => 0,
+        false => -EFBIG,
+    }
+}
+
+/// truncate syscall
+pub fn sys_truncate(path: *const u8, length: isize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_truncate",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let path = translated_str(current_user_token(), path);
+    let inode = match inode_at(AT_FDCWD, &path, 0) {
+        Ok(inode) => inode,
+        Err(err) => return err,
+    };
+    if !inode.is_dir() && !current_cred().may(&inode, W_OK) {
+        return -EACCES;
+    }
+    truncate(&inode, length)
+}
+
+/// ftruncate syscall, the file must be open for writing
+pub fn sys_ftruncate(fd: usize, length: isize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_ftruncate",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let file = match file_of(fd) {
+        Ok(file) => file,
+        Err(err) => return err,
+    };
+    match file.inode() {
+        Some(inode) if file.writable() => truncate(&inode, length),
+        _ => -EINVAL,
+    }
+}
+
+/// *at flag: do not follow a symbolic link at the end of the path
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+/// *at flag: an empty path refers to `dirfd` itself
+pub const AT_EMPTY_PATH: u32 = 0x1000;
+
+/// The file of `fd` in the current process
+fn file_of(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    match inner.fd_table.get(fd) {
+        Some(Some(file)) => Ok(file.clone()),
+        _ => Err(-EBADF),
+    }
+}
+
+/// The inode at `path` relative to `dirfd`, or of `dirfd` itself for an
+/// empty path with `AT_EMPTY_PATH`
+fn inode_at(dirfd: isize, path: &str, flags: u32) -> Result<Arc<Inode>, isize> {
+    if flags & !(AT_SYMLINK_NOFOLLOW | AT_EMPTY_PATH) != 0 {
+        return Err(-EINVAL);
+    }
+    if path.is_empty() {
+        if flags & AT_EMPTY_PATH == 0 {
+            return Err(-ENOENT);
+        }
+        if dirfd == AT_FDCWD {
+            return current_cwd();
+        }
+        return file_of(dirfd as usize)?.inode().ok_or(-EINVAL);
+    }
+    let dir = dir_of(dirfd, path)?;
+    lookup(&dir, path, flags & AT_SYMLINK_NOFOLLOW == 0, current_cred())
+}
+
+/// The directory that would hold `path` relative to `dirfd`, and the final
+/// component of `path`
+fn parent_at(dirfd: isize, path: &str) -> Result<(Arc<Inode>, &str), isize> {
+    let dir = dir_of(dirfd, path)?;
+    let (parent, name) = split_path(path);
+    let parent = lookup(&dir, parent, true, current_cred())?;
+    if !parent.is_dir() {
+        return Err(-ENOTDIR);
+    }
+    Ok((parent, name))
+}
+
+/// The stat of `path` relative to `dirfd`
+fn stat_at(dirfd: isize, path: &str, flags: u32) -> Result<Stat, isize> {
+    // a pipe or the console has a stat but no inode
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 && dirfd != AT_FDCWD {
+        return Ok(file_of(dirfd as usize)?.stat());
+    }
+    let inode = inode_at(dirfd, path, flags)?;
+    Ok(inode_stat(&inode))
+}
+
+/// newfstatat syscall
+pub fn sys_newfstatat(dirfd: isize, path: *const u8, st: *mut Stat, flags: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_newfstatat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    match stat_at(dirfd, &path, flags) {
+        Ok(stat) => {
+            copy_to_user(token, st, &stat);
+            0
+        }
+        Err(err) => err,
+    }
+}
+
+/// Timestamp of [`Statx`]
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StatxTimestamp {
+    /// seconds
+    pub sec: i64,
+    /// nanoseconds
+    pub nsec: u32,
+    /// reserved
+    __reserved: i32,
+}
+
+impl StatxTimestamp {
+    fn new(sec: i64, nsec: i64) -> Self {
+        Self {
+            sec,
+            nsec: nsec as u32,
+            __reserved: 0,
+        }
+    }
+}
+
+/// statx mask: all the fields of `struct stat`
+pub const STATX_BASIC_STATS: u32 = 0x7ff;
+
+/// The extended stat of a file, laid out as `struct statx` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Statx {
+    /// which fields are filled in, `STATX_*`
+    pub mask: u32,
+    /// block size for filesystem I/O
+    pub blksize: u32,
+    /// file attributes
+    pub attributes: u64,
+    /// number of hard links
+    pub nlink: u32,
+    /// user ID of owner
+    pub uid: u32,
+    /// group ID of owner
+    pub gid: u32,
+    /// file type and mode
+    pub mode: u16,
+    __spare0: u16,
+    /// inode number
+    pub ino: u64,
+    /// total size in bytes
+    pub size: u64,
+    /// number of 512B blocks allocated
+    pub blocks: u64,
+    /// supported attributes
+    pub attributes_mask: u64,
+    /// time of last access
+    pub atime: StatxTimestamp,
+    /// time of creation, not kept
+    pub btime: StatxTimestamp,
+    /// time of last status change
+    pub ctime: StatxTimestamp,
+    /// time of last modification
+    pub mtime: StatxTimestamp,
+    /// major device ID of a special file
+    pub rdev_major: u32,
+    /// minor device ID of a special file
+    pub rdev_minor: u32,
+    /// major ID of device containing file
+    pub dev_major: u32,
+    /// minor ID of device containing file
+    pub dev_minor: u32,
+    /// mount ID
+    pub mnt_id: u64,
+    __spare2: [u64; 13],
+}
+
+impl From<Stat> for Statx {
+    fn from(stat: Stat) -> Self {
+        Self {
+            mask: STATX_BASIC_STATS,
+            blksize: stat.blksize,
+            nlink: stat.nlink,
+            uid: stat.uid,
+            gid: stat.gid,
+            mode: stat.mode.bits() as u16,
+            ino: stat.ino,
+            size: stat.size,
+            blocks: stat.blocks,
+            atime: StatxTimestamp::new(stat.atime_sec, stat.atime_nsec),
+            ctime: StatxTimestamp::new(stat.ctime_sec, stat.ctime_nsec),
+            mtime: StatxTimestamp::new(stat.mtime_sec, stat.mtime_nsec),
+            rdev_major: (stat.rdev >> 8) as u32,
+            rdev_minor: (stat.rdev & 0xff) as u32,
+            dev_major: (stat.dev >> 8) as u32,
+            dev_minor: (stat.dev & 0xff) as u32,
+            ..Default::default()
+        }
+    }
+}
+
+/// statx syscall, every field but the creation time is always filled in
+pub fn sys_statx(dirfd: isize, path: *const u8, flags: u32, _mask: u32, buf: *mut Statx) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_statx",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    // the AT_STATX_SYNC_* bits are no-ops, easy-fs is local
+    match stat_at(dirfd, &path, flags & !AT_STATX_SYNC_TYPE) {
+        Ok(stat) => {
+            copy_to_user(token, buf, &Statx::from(stat));
+            0
+        }
+        Err(err) => err,
+    }
+}
+
+/// statx flags: how to synchronize with a remote file system
+pub const AT_STATX_SYNC_TYPE: u32 = 0x6000;
+
+/// linkat flag: link the target of a symbolic link instead of the link
+pub const AT_SYMLINK_FOLLOW: u32 = 0x400;
+
+/// linkat syscall
+pub fn sys_linkat(
+    olddirfd: isize,
+    oldpath: *const u8,
+    newdirfd: isize,
+    newpath: *const u8,
+    flags: u32,
+) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_linkat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if flags & !AT_SYMLINK_FOLLOW != 0 {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let oldpath = translated_str(token, oldpath);
+    let newpath = translated_str(token, newpath);
+    let follow = flags & AT_SYMLINK_FOLLOW != 0;
+    let cred = current_cred();
+    let target = dir_of(olddirfd, &oldpath).and_then(|dir| lookup(&dir, &oldpath, follow, cred));
+    let target = match target {
+        Ok(target) => target,
+        Err(err) => return err,
+    };
+    if target.is_dir() {
+        return -EPERM;
+    }
+    let (parent, name) = match parent_at(newdirfd, &newpath) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    if !cred.may(&parent, W_OK | X_OK) {
+        return -EACCES;
+    }
+    if name.len() > NAME_LENGTH_LIMIT {
+        return -ENAMETOOLONG;
+    }
+    if name.is_empty() || name == "." || name == ".." || parent.find(name).is_some() {
+        return -EEXIST;
+    }
+    match parent.link(name, &target) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// unlinkat flag: remove a directory instead of a file
+pub const AT_REMOVEDIR: u32 = 0x200;
+
+/// unlinkat syscall
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_unlinkat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if flags & !AT_REMOVEDIR != 0 {
+        return -EINVAL;
+    }
+    let path = translated_str(current_user_token(), path);
+    let (parent, name) = match parent_at(dirfd, &path) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    let cred = current_cred();
+    if !cred.may(&parent, W_OK | X_OK) {
+        return -EACCES;
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return -EINVAL;
+    }
+    let inode = match parent.find(name) {
+        Some(inode) => inode,
+        None => return -ENOENT,
+    };
+    match (inode.is_dir(), flags & AT_REMOVEDIR != 0) {
+        (true, false) => return -EISDIR,
+        (false, true) => return -ENOTDIR,
+        (true, true) if !inode.ls().is_empty() => return -ENOTEMPTY,
+        _ => {}
+    }
+    drop(inode);
+    match parent.unlink(name) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// renameat2 flag: fail if the new path exists
+pub const RENAME_NOREPLACE: u32 = 1;
+/// renameat2 flag: swap the two paths, which must both exist
+pub const RENAME_EXCHANGE: u32 = 2;
+
+/// renameat2 syscall
+pub fn sys_renameat2(
+    olddirfd: isize,
+    oldpath: *const u8,
+    newdirfd: isize,
+    newpath: *const u8,
+    flags: u32,
+) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_renameat2",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let mode = match flags {
+        0 => RenameMode::Replace,
+        RENAME_NOREPLACE => RenameMode::NoReplace,
+        RENAME_EXCHANGE => RenameMode::Exchange,
+        _ => return -EINVAL,
+    };
+    let token = current_user_token();
+    let oldpath = translated_str(token, oldpath);
+    let newpath = translated_str(token, newpath);
+    let (old_parent, old_name) = match parent_at(olddirfd, &oldpath) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    let (new_parent, new_name) = match parent_at(newdirfd, &newpath) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    let cred = current_cred();
+    if !cred.may(&old_parent, W_OK | X_OK) || !cred.may(&new_parent, W_OK | X_OK) {
+        return -EACCES;
+    }
+    if new_name.len() > NAME_LENGTH_LIMIT {
+        return -ENAMETOOLONG;
+    }
+    let names = [old_name, new_name];
+    if names.iter().any(|name| name.is_empty() || *name == "." || *name == "..") {
+        return -EINVAL;
+    }
+    let inode = match old_parent.find(old_name) {
+        Some(inode) => inode,
+        None => return -ENOENT,
+    };
+    let target = new_parent.find(new_name);
+    match (mode, &target) {
+        (RenameMode::NoReplace, Some(_)) => return -EEXIST,
+        (RenameMode::Exchange, None) => return -ENOENT,
+        (RenameMode::Exchange, Some(target)) => {
+            if target.is_dir() && old_parent.is_within(target) {
+                return -EINVAL;
+            }
+        }
+        (_, Some(target)) if target.inode_id() != inode.inode_id() => {
+            match (inode.is_dir(), target.is_dir()) {
+                (true, false) => return -ENOTDIR,
+                (false, true) => return -EISDIR,
+                (true, true) if !target.ls().is_empty() => return -ENOTEMPTY,
+                _ => {}
+            }
+        }
+        _ => {}
+    }
+    // a directory cannot move inside itself
+    if inode.is_dir() && new_parent.is_within(&inode) {
+        return -EINVAL;
+    }
+    match old_parent.rename(old_name, &new_parent, new_name, mode) {
+        true => 0,
+        false => -1,
+    }
+}
+
+/// symlinkat syscall
+pub fn sys_symlinkat(target: *const u8, newdirfd: isize, linkpath: *const u8) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_symlinkat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let linkpath = translated_str(token, linkpath);
+    if target.is_empty() {
+        return -ENOENT;
+    }
+    if target.len() > SYMLINK_LENGTH_LIMIT {
+        return -ENAMETOOLONG;
+    }
+    let (parent, name) = match parent_at(newdirfd, &linkpath) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    let cred = current_cred();
+    if !cred.may(&parent, W_OK | X_OK) {
+        return -EACCES;
+    }
+    if name.len() > NAME_LENGTH_LIMIT {
+        return -ENAMETOOLONG;
+    }
+    if name.is_empty() || name == "." || name == ".." || parent.find(name).is_some() {
+        return -EEXIST;
+    }
+    match parent.create_symlink_as(name, &target, (cred.uid, cred.gid)) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// readlinkat syscall, returns the length of the target, which is truncated
+/// to `bufsiz` and not nul-terminated
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: *mut u8, bufsiz: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_readlinkat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if bufsiz as isize <= 0 {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let target = match inode_at(dirfd, &path, AT_SYMLINK_NOFOLLOW).map(|inode| inode.read_link()) {
+        Ok(Some(target)) => target,
+        Ok(None) => return -EINVAL,
+        Err(err) => return err,
+    };
+    let len = target.len().min(bufsiz);
+    let mut dst = translated_byte_buffer(token, buf, len).into_iter().flatten();
+    for (byte, dst) in target.bytes().zip(&mut dst) {
+        *dst = byte;
+    }
+    len as isize
+}
+
+/// mkdirat syscall
+pub fn sys_mkdirat(dirfd: isize, path: *const u8, mode: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_mkdirat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let path = translated_str(current_user_token(), path);
+    let (parent, name) = match parent_at(dirfd, &path) {
+        Ok(parent) => parent,
+        Err(err) => return err,
+    };
+    let cred = current_cred();
+    if !cred.may(&parent, W_OK | X_OK) {
+        return -EACCES;
+    }
+    if name.len() > NAME_LENGTH_LIMIT {
+        return -ENAMETOOLONG;
+    }
+    if name.is_empty() || name == "." || name == ".." || parent.find(name).is_some() {
+        return -EEXIST;
+    }
+    match parent.create_dir_as(name, mode as u16, (cred.uid, cred.gid)) {
+        Some(_) => 0,
+        None => -1,
+    }
+}
+
+/// chdir syscall
+pub fn sys_chdir(path: *const u8) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_chdir",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let path = translated_str(current_user_token(), path);
+    let cred = current_cred();
+    let inode = match dir_of(AT_FDCWD, &path).and_then(|dir| lookup(&dir, &path, true, cred)) {
+        Ok(inode) if !inode.is_dir() => return -ENOTDIR,
+        Ok(inode) if !cred.may(&inode, X_OK) => return -EACCES,
+        Ok(inode) => inode,
+        Err(err) => return err,
+    };
+    current_process().inner_exclusive_access().cwd = inode;
+    0
+}
+
+/// getcwd syscall, returns the length of the path including the nul byte
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_getcwd",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let cwd = current_process().inner_exclusive_access().cwd.clone();
+    // the directory may have been removed under us
+    let cwd = match dir_path(&cwd) {
+        Some(cwd) => cwd,
+        None => return -ENOENT,
+    };
+    let len = cwd.len() + 1;
+    if size < len {
+        return -ERANGE;
+    }
+    let bytes = cwd.bytes().chain(core::iter::once(0));
+    let mut dst = translated_byte_buffer(token, buf, len).into_iter().flatten();
+    for (byte, dst) in bytes.zip(&mut dst) {
+        *dst = byte;
+    }
+    len as isize
+}
+
+/// Change the permission bits of `inode`, by its owner or root
+fn chmod(inode: &Inode, mode: u32) -> isize {
+    if !current_cred().owns(inode) {
+        return -EPERM;
+    }
+    inode.set_mode(mode as u16);
+    0
+}
+
+/// fchmodat syscall
+pub fn sys_fchmodat(dirfd: isize, path: *const u8, mode: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_fchmodat",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let path = translated_str(current_user_token(), path);
+    match inode_at(dirfd, &path, 0) {
+        Ok(inode) => chmod(&inode, mode),
+        Err(err) => err,
+    }
+}
+
+/// fchmod syscall
+pub fn sys_fchmod(fd: usize, mode: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_fchmod",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    match file_of(fd).map(|file| file.inode()) {
+        Ok(Some(inode)) => chmod(&inode, mode),
+        Ok(None) => -EINVAL,
+        Err(err) => err,
+    }
+}
+
+/// Change the owner of `inode`, an id of `u32::MAX` is left as it is
+///
+/// Root may give the inode to anyone, its owner may only change the group
+/// to its own.
+fn chown(inode: &Inode, uid: u32, gid: u32) -> isize {
+    let cred = current_cred();
+    let (old_uid, old_gid) = inode.owner();
+    let uid = if uid == u32::MAX { old_uid } else { uid };
+    let gid = if gid == u32::MAX { old_gid } else { gid };
+    let allowed = cred.uid == 0
+        || (cred.uid == old_uid && uid == old_uid && (gid == old_gid || gid == cred.gid));
+    if !allowed {
+        return -EPERM;
+    }
+    inode.set_owner(uid, gid);
+    0
+}
+
+/// fchownat syscall
+pub fn sys_fchownat(dirfd: isize, path: *const u8, uid: u32, gid: u32, flags: u32) -> isize {
     trace!(
-        "kernel:pid[{}] sys_fstat NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_fchownat",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    -1
+    let path = translated_str(current_user_token(), path);
+    match inode_at(dirfd, &path, flags) {
+        Ok(inode) => chown(&inode, uid, gid),
+        Err(err) => err,
+    }
 }
 
-/// YOUR JOB: Implement linkat.
-pub fn sys_linkat(_old_name: *const u8, _new_name: *const u8) -> isize {
+/// fchown syscall
+pub fn sys_fchown(fd: usize, uid: u32, gid: u32) -> isize {
     trace!(
-        "kernel:pid[{}] sys_linkat NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_fchown",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    -1
+    match file_of(fd).map(|file| file.inode()) {
+        Ok(Some(inode)) => chown(&inode, uid, gid),
+        Ok(None) => -EINVAL,
+        Err(err) => err,
+    }
 }
 
-/// YOUR JOB: Implement unlinkat.
-pub fn sys_unlinkat(_name: *const u8) -> isize {
+/// utimensat: set the time to the current time
+pub const UTIME_NOW: usize = (1 << 30) - 1;
+/// utimensat: leave the time as it is
+pub const UTIME_OMIT: usize = (1 << 30) - 2;
+
+/// utimensat syscall, a null `path` means `dirfd` itself as for futimens
+///
+/// Setting both times to now needs write permission, any other time needs
+/// ownership.
+pub fn sys_utimensat(
+    dirfd: isize,
+    path: *const u8,
+    times: *const [TimeSpec; 2],
+    flags: u32,
+) -> isize {
     trace!(
-        "kernel:pid[{}] sys_unlinkat NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_utimensat",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    -1
+    let token = current_user_token();
+    let inode = match path.is_null() {
+        true => file_of(dirfd as usize).and_then(|file| file.inode().ok_or(-EINVAL)),
+        false => inode_at(dirfd, &translated_str(token, path), flags),
+    };
+    let inode = match inode {
+        Ok(inode) => inode,
+        Err(err) => return err,
+    };
+    let now_spec = TimeSpec {
+        sec: 0,
+        nsec: UTIME_NOW,
+    };
+    let times = match times.is_null() {
+        true => [now_spec; 2],
+        false => copy_from_user(token, times),
+    };
+    let now = get_realtime_ns() as u64;
+    let mut new_times = [None; 2];
+    for (new_time, time) in new_times.iter_mut().zip(times.iter()) {
+        *new_time = match time.nsec {
+            UTIME_OMIT => None,
+            UTIME_NOW => Some(now),
+            _ => match time.to_ns() {
+                Some(ns) => Some(ns as u64),
+                None => return -EINVAL,
+            },
+        };
+    }
+    let cred = current_cred();
+    let only_now = times.iter().all(|time| time.nsec == UTIME_NOW);
+    // the owner may set any time, a writer only the current one
+    let allowed = cred.owns(&inode) || (only_now && cred.may(&inode, W_OK));
+    if !allowed {
+        return match only_now {
+            true => -EACCES,
+            false => -EPERM,
+        };
+    }
+    if new_times.iter().any(Option::is_some) {
+        inode.set_times(new_times[0], new_times[1]);
+    }
+    0
 }
@@ -10,22 +10,52 @@
 //! `sys_` then the name of the syscall. You can find functions like this in
 //! submodules, and you should also implement syscalls this way.
 
+/// getcwd syscall
+pub const SYSCALL_GETCWD: usize = 17;
+/// truncate syscall
+pub const SYSCALL_TRUNCATE: usize = 45;
+/// ftruncate syscall
+pub const SYSCALL_FTRUNCATE: usize = 46;
+/// mkdirat syscall
+pub const SYSCALL_MKDIRAT: usize = 34;
+/// chdir syscall
+pub const SYSCALL_CHDIR: usize = 49;
+/// fchmod syscall
+pub const SYSCALL_FCHMOD: usize = 52;
+/// fchmodat syscall
+pub const SYSCALL_FCHMODAT: usize = 53;
+/// fchownat syscall
+pub const SYSCALL_FCHOWNAT: usize = 54;
+/// fchown syscall
+pub const SYSCALL_FCHOWN: usize = 55;
 /// openat syscall
 pub const SYSCALL_OPENAT: usize = 56;
 /// close syscall
 pub const SYSCALL_CLOSE: usize = 57;
+/// lseek syscall
+pub const SYSCALL_LSEEK: usize = 62;
 /// read syscall
 pub const SYSCALL_READ: usize = 63;
 /// write syscall
 pub const SYSCALL_WRITE: usize = 64;
 /// unlinkat syscall
 pub const SYSCALL_UNLINKAT: usize = 35;
+/// symlinkat syscall
+pub const SYSCALL_SYMLINKAT: usize = 36;
 /// linkat syscall
 pub const SYSCALL_LINKAT: usize = 37;
+/// readlinkat syscall
+pub const SYSCALL_READLINKAT: usize = 78;
+/// newfstatat syscall
+pub const SYSCALL_NEWFSTATAT: usize = 79;
 /// fstat syscall
 pub const SYSCALL_FSTAT: usize = 80;
+/// utimensat syscall
+pub const SYSCALL_UTIMENSAT: usize = 88;
 /// exit syscall
 pub const SYSCALL_EXIT: usize = 93;
+/// futex syscall
+pub const SYSCALL_FUTEX: usize = 98;
 /// sleep syscall
 pub const SYSCALL_SLEEP: usize = 101;
 /// yield syscall
@@ -40,8 +70,46 @@ pub const SYSCALL_SIGPROCMASK: usize = 135;
 /// sigreturn syscall
 pub const SYSCALL_SIGRETURN: usize = 139;
 */
+/// getitimer syscall
+pub const SYSCALL_GETITIMER: usize = 102;
+/// setitimer syscall
+pub const SYSCALL_SETITIMER: usize = 103;
+/// timer_create syscall
+pub const SYSCALL_TIMER_CREATE: usize = 107;
+/// timer_gettime syscall
+pub const SYSCALL_TIMER_GETTIME: usize = 108;
+/// timer_getoverrun syscall
+pub const SYSCALL_TIMER_GETOVERRUN: usize = 109;
+/// timer_settime syscall
+pub const SYSCALL_TIMER_SETTIME: usize = 110;
+/// timer_delete syscall
+pub const SYSCALL_TIMER_DELETE: usize = 111;
+/// clock_gettime syscall
+pub const SYSCALL_CLOCK_GETTIME: usize = 113;
+/// clock_getres syscall
+pub const SYSCALL_CLOCK_GETRES: usize = 114;
+/// clock_nanosleep syscall
+pub const SYSCALL_CLOCK_NANOSLEEP: usize = 115;
+/// times syscall
+pub const SYSCALL_TIMES: usize = 153;
+/// getrusage syscall
+pub const SYSCALL_GETRUSAGE: usize = 165;
 /// gettimeofday syscall
 pub const SYSCALL_GETTIMEOFDAY: usize = 169;
+/// settimeofday syscall
+pub const SYSCALL_SETTIMEOFDAY: usize = 170;
+/// setgid syscall
+pub const SYSCALL_SETGID: usize = 144;
+/// setuid syscall
+pub const SYSCALL_SETUID: usize = 146;
+/// getuid syscall
+pub const SYSCALL_GETUID: usize = 174;
+/// geteuid syscall
+pub const SYSCALL_GETEUID: usize = 175;
+/// getgid syscall
+pub const SYSCALL_GETGID: usize = 176;
+/// getegid syscall
+pub const SYSCALL_GETEGID: usize = 177;
 /// getpid syscall
 pub const SYSCALL_GETPID: usize = 172;
 /// gettid syscall
@@ -52,6 +120,10 @@ pub const SYSCALL_FORK: usize = 220;
 pub const SYSCALL_EXEC: usize = 221;
 /// waitpid syscall
 pub const SYSCALL_WAITPID: usize = 260;
+/// renameat2 syscall
+pub const SYSCALL_RENAMEAT2: usize = 276;
+/// statx syscall
+pub const SYSCALL_STATX: usize = 291;
 /// set priority syscall
 pub const SYSCALL_SET_PRIORITY: usize = 140;
 /*
@@ -64,6 +136,10 @@ pub const SYSCALL_MUNMAP: usize = 215;
 pub const SYSCALL_MMAP: usize = 222;
 /// spawn syscall
 pub const SYSCALL_SPAWN: usize = 400;
+/// posix_spawn syscall
+pub const SYSCALL_POSIX_SPAWN: usize = 403;
+/// alarm syscall
+pub const SYSCALL_ALARM: usize = 404;
 /*
 /// mail read syscall
 pub const SYSCALL_MAIL_READ: usize = 401;
@@ -100,46 +176,176 @@ pub const SYSCALL_CONDVAR_CREATE: usize = 471;
 pub const SYSCALL_CONDVAR_SIGNAL: usize = 472;
 /// condvar_wait syscallca
 pub const SYSCALL_CONDVAR_WAIT: usize = 473;
+/// rwlock_create syscall
+pub const SYSCALL_RWLOCK_CREATE: usize = 474;
+/// rwlock_read syscall
+pub const SYSCALL_RWLOCK_READ: usize = 475;
+/// rwlock_write syscall
+pub const SYSCALL_RWLOCK_WRITE: usize = 476;
+/// rwlock_unlock syscall
+pub const SYSCALL_RWLOCK_UNLOCK: usize = 477;
+/// barrier_create syscall
+pub const SYSCALL_BARRIER_CREATE: usize = 478;
+/// barrier_wait syscall
+pub const SYSCALL_BARRIER_WAIT: usize = 479;
+/// deadlock_report syscall
+pub const SYSCALL_DEADLOCK_REPORT: usize = 480;
 
+pub mod errno;
 mod fs;
 mod process;
 mod sync;
 mod thread;
+mod time;
 
 use fs::*;
 use process::*;
 use sync::*;
+pub use sync::futex_test;
 use thread::*;
+use time::*;
 
+use crate::config::MAX_SYSCALL_NUM;
 use crate::fs::Stat;
+use crate::sync::DeadlockEntry;
+use crate::task::current_task;
 
 /// handle syscall exception with `syscall_id` and other arguments
-pub async  fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
+pub async  fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     trace!("kernel: syscall_id = {}",syscall_id);
+    if syscall_id < MAX_SYSCALL_NUM {
+        current_task().unwrap().inner_exclusive_access().syscall_times[syscall_id] += 1;
+    }
     match syscall_id {
         SYSCALL_DUP => sys_dup(args[0]),
-        SYSCALL_LINKAT => sys_linkat(args[1] as *const u8, args[3] as *const u8),
-        SYSCALL_UNLINKAT => sys_unlinkat(args[1] as *const u8),
-        SYSCALL_OPENAT => sys_open(args[1] as *const u8, args[2] as u32),
+        SYSCALL_LINKAT => sys_linkat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as isize,
+            args[3] as *const u8,
+            args[4] as u32,
+        ),
+        SYSCALL_RENAMEAT2 => sys_renameat2(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as isize,
+            args[3] as *const u8,
+            args[4] as u32,
+        ),
+        SYSCALL_SYMLINKAT => {
+            sys_symlinkat(args[0] as *const u8, args[1] as isize, args[2] as *const u8)
+        }
+        SYSCALL_READLINKAT => sys_readlinkat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as *mut u8,
+            args[3],
+        ),
+        SYSCALL_UNLINKAT => {
+            sys_unlinkat(args[0] as isize, args[1] as *const u8, args[2] as u32)
+        }
+        SYSCALL_OPENAT => sys_openat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+        ),
+        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYSCALL_MKDIRAT => sys_mkdirat(args[0] as isize, args[1] as *const u8, args[2] as u32),
+        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+        SYSCALL_FCHMOD => sys_fchmod(args[0], args[1] as u32),
+        SYSCALL_FCHMODAT => sys_fchmodat(args[0] as isize, args[1] as *const u8, args[2] as u32),
+        SYSCALL_FCHOWNAT => sys_fchownat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as u32,
+        ),
+        SYSCALL_FCHOWN => sys_fchown(args[0], args[1] as u32, args[2] as u32),
+        SYSCALL_UTIMENSAT => sys_utimensat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as *const [TimeSpec; 2],
+            args[3] as u32,
+        ),
         SYSCALL_CLOSE => sys_close(args[0]),
+        SYSCALL_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
+        SYSCALL_TRUNCATE => sys_truncate(args[0] as *const u8, args[1] as isize),
+        SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1] as isize),
         SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
         SYSCALL_FSTAT => sys_fstat(args[0], args[1] as *mut Stat),
+        SYSCALL_NEWFSTATAT => sys_newfstatat(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as *mut Stat,
+            args[3] as u32,
+        ),
+        SYSCALL_STATX => sys_statx(
+            args[0] as isize,
+            args[1] as *const u8,
+            args[2] as u32,
+            args[3] as u32,
+            args[4] as *mut Statx,
+        ),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
-        SYSCALL_SLEEP => sys_sleep(args[0]),
+        SYSCALL_FUTEX => sys_futex(args[0], args[1], args[2], args[3], args[4], args[5]).await,
+        SYSCALL_SLEEP => sys_sleep(args[0]).await,
+        SYSCALL_CLOCK_NANOSLEEP => sys_clock_nanosleep(args[0], args[1], args[2], args[3]).await,
         SYSCALL_YIELD => sys_yield(),
         SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_GETUID | SYSCALL_GETEUID => sys_getuid(),
+        SYSCALL_GETGID | SYSCALL_GETEGID => sys_getgid(),
+        SYSCALL_SETUID => sys_setuid(args[0] as u32),
+        SYSCALL_SETGID => sys_setgid(args[0] as u32),
         SYSCALL_GETTID => sys_gettid(),
         SYSCALL_FORK => sys_fork(),
-        SYSCALL_EXEC => sys_exec(args[0] as *const u8, args[1] as *const usize),
+        SYSCALL_EXEC => sys_exec(
+            args[0] as *const u8,
+            args[1] as *const usize,
+            args[2] as *const usize,
+        ),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_GETTIMEOFDAY => sys_get_time(args[0] as *mut TimeVal, args[1]),
+        SYSCALL_SETTIMEOFDAY => sys_settimeofday(args[0] as *const TimeVal, args[1]),
+        SYSCALL_GETITIMER => sys_getitimer(args[0], args[1] as *mut ITimerVal),
+        SYSCALL_SETITIMER => sys_setitimer(
+            args[0],
+            args[1] as *const ITimerVal,
+            args[2] as *mut ITimerVal,
+        ),
+        SYSCALL_ALARM => sys_alarm(args[0]),
+        SYSCALL_TIMER_CREATE => {
+            sys_timer_create(args[0], args[1] as *const SigEvent, args[2] as *mut i32)
+        }
+        SYSCALL_TIMER_SETTIME => sys_timer_settime(
+            args[0],
+            args[1],
+            args[2] as *const ITimerSpec,
+            args[3] as *mut ITimerSpec,
+        ),
+        SYSCALL_TIMER_GETTIME => sys_timer_gettime(args[0], args[1] as *mut ITimerSpec),
+        SYSCALL_TIMER_GETOVERRUN => sys_timer_getoverrun(args[0]),
+        SYSCALL_TIMER_DELETE => sys_timer_delete(args[0]),
+        SYSCALL_CLOCK_GETTIME => sys_clock_gettime(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_CLOCK_GETRES => sys_clock_getres(args[0], args[1] as *mut TimeSpec),
+        SYSCALL_TIMES => sys_times(args[0] as *mut Tms),
+        SYSCALL_GETRUSAGE => sys_getrusage(args[0] as isize, args[1] as *mut Rusage),
         SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
         SYSCALL_SPAWN => sys_spawn(args[0] as *const u8),
+        SYSCALL_POSIX_SPAWN => sys_posix_spawn(
+            args[0] as *const u8,
+            args[1] as *const SpawnFileAction,
+            args[2],
+            args[3] as *const SpawnAttr,
+            args[4] as *const usize,
+            args[5] as *const usize,
+        ),
         SYSCALL_THREAD_CREATE => sys_thread_create(args[0], args[1]),
         SYSCALL_WAITTID => sys_waittid(args[0]) as isize,
         SYSCALL_MUTEX_CREATE => sys_mutex_create(args[0] == 1),
@@ -148,10 +354,17 @@ pub async  fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
         SYSCALL_SEMAPHORE_CREATE => sys_semaphore_create(args[0]),
         SYSCALL_SEMAPHORE_UP => sys_semaphore_up(args[0]),
         SYSCALL_ENABLE_DEADLOCK_DETECT => sys_enable_deadlock_detect(args[0]),
-        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]),
+        SYSCALL_SEMAPHORE_DOWN => sys_semaphore_down(args[0]).await,
         SYSCALL_CONDVAR_CREATE => sys_condvar_create(),
         SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
         SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
+        SYSCALL_RWLOCK_CREATE => sys_rwlock_create(),
+        SYSCALL_RWLOCK_READ => sys_rwlock_read(args[0]).await,
+        SYSCALL_RWLOCK_WRITE => sys_rwlock_write(args[0]).await,
+        SYSCALL_RWLOCK_UNLOCK => sys_rwlock_unlock(args[0]),
+        SYSCALL_BARRIER_CREATE => sys_barrier_create(args[0]),
+        SYSCALL_BARRIER_WAIT => sys_barrier_wait(args[0]).await,
+        SYSCALL_DEADLOCK_REPORT => sys_deadlock_report(args[0] as *mut DeadlockEntry, args[1]),
         SYSCALL_KILL => sys_kill(args[0], args[1] as u32),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
@@ -1,25 +1,22 @@
-use core::mem;
-
 use crate::{
     config::MAX_SYSCALL_NUM,
-    fs::{open_file, OpenFlags},
-    mm::{translated_byte_buffer, translated_ref, translated_refmut, translated_str},
+    fs::{open_file_at, File, OpenFlags, X_OK},
+    mm::{copy_to_user, translated_ref, translated_refmut, translated_str},
     task::{
         current_process, current_task, current_user_token,  pid2process,
         suspend_current_and_run_next, SignalFlags, TaskStatus,
     }, timer::get_time_us,
 };
-use alloc::{string::String, sync::Arc, vec::Vec};
+use super::errno::{EACCES, EBADF, EINVAL, ELOOP, EPERM};
+use super::fs::{current_cred, current_cwd};
+use alloc::{string::String, sync::Arc, vec, vec::Vec};
 
-#[repr(C)]
-#[derive(Debug)]
-pub struct TimeVal {
-    pub sec: usize,
-    pub usec: usize,
-}
+/// nested `#!` interpreters allowed in one exec
+const MAX_SHEBANG_DEPTH: usize = 4;
 
 /// Task information
 #[allow(dead_code)]
+#[repr(C)]
 pub struct TaskInfo {
     /// Task status in it's life cycle
     status: TaskStatus,
@@ -53,6 +50,50 @@ pub fn sys_getpid() -> isize {
     );
     current_task().unwrap().process.upgrade().unwrap().getpid() as isize
 }
+/// getuid syscall
+pub fn sys_getuid() -> isize {
+    trace!(
+        "kernel:pid[{}] sys_getuid",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    current_cred().uid as isize
+}
+/// getgid syscall
+pub fn sys_getgid() -> isize {
+    trace!(
+        "kernel:pid[{}] sys_getgid",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    current_cred().gid as isize
+}
+/// setuid syscall, only root may take another user id
+pub fn sys_setuid(uid: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_setuid",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.cred.uid != 0 && inner.cred.uid != uid {
+        return -EPERM;
+    }
+    inner.cred.uid = uid;
+    0
+}
+/// setgid syscall, only root may take another group id
+pub fn sys_setgid(gid: u32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_setgid",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.cred.uid != 0 && inner.cred.gid != gid {
+        return -EPERM;
+    }
+    inner.cred.gid = gid;
+    0
+}
 /// fork child process syscall
 pub fn sys_fork() -> isize {
     trace!(
@@ -72,34 +113,96 @@ pub fn sys_fork() -> isize {
     new_pid as isize
 }
 /// exec syscall
-pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
+///
+/// `args` and `envp` are NULL-terminated arrays of string pointers, `envp` may be NULL.
+pub fn sys_exec(path: *const u8, args: *const usize, envp: *const usize) -> isize {
     trace!(
         "kernel:pid[{}] sys_exec",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
     let token = current_user_token();
     let path = translated_str(token, path);
-    let mut args_vec: Vec<String> = Vec::new();
+    let args_vec = translated_str_array(token, args);
+    let envs_vec = if envp.is_null() {
+        Vec::new()
+    } else {
+        translated_str_array(token, envp)
+    };
+    let (all_data, args_vec) = match load_program(path, args_vec) {
+        Ok(program) => program,
+        Err(err) => return err,
+    };
+    let process = current_process();
+    let argc = args_vec.len();
+    if let Err(err) = process.exec(all_data.as_slice(), args_vec, envs_vec) {
+        return err;
+    }
+    // return argc because cx.x[10] will be covered with it later
+    argc as isize
+}
+
+/// Read the program at `path`, following `#!` lines to the final elf.
+/// Returns the elf data and the argv rewritten for interpreters.
+fn load_program(mut path: String, mut args_vec: Vec<String>) -> Result<(Vec<u8>, Vec<String>), isize> {
+    for _ in 0..=MAX_SHEBANG_DEPTH {
+        let cred = current_cred();
+        let app_inode = open_file_at(&current_cwd()?, path.as_str(), OpenFlags::RDONLY, 0, cred)?;
+        match app_inode.inode() {
+            Some(inode) if !inode.is_dir() && cred.may(&inode, X_OK) => {}
+            _ => return Err(-EACCES),
+        }
+        let all_data = app_inode.read_all();
+        if let Some((interp, interp_arg)) = parse_shebang(&all_data) {
+            // interp [interp_arg] path argv[1..]
+            let mut new_args = vec![interp.clone()];
+            new_args.extend(interp_arg);
+            new_args.push(path);
+            new_args.extend(args_vec.into_iter().skip(1));
+            path = interp;
+            args_vec = new_args;
+            continue;
+        }
+        return Ok((all_data, args_vec));
+    }
+    // too many levels of interpreters
+    Err(-ELOOP)
+}
+
+/// Parse the `#!interp [arg]` line of a script
+fn parse_shebang(data: &[u8]) -> Option<(String, Option<String>)> {
+    if !data.starts_with(b"#!") {
+        return None;
+    }
+    let line_end = data.iter().position(|&c| c == b'\n').unwrap_or(data.len());
+    let line = core::str::from_utf8(&data[2..line_end]).ok()?.trim();
+    // like Linux, everything after the interpreter is passed as a single argument
+    let (interp, arg) = match line.find(|c: char| c == ' ' || c == '\t') {
+        Some(pos) => (&line[..pos], Some(line[pos..].trim())),
+        None => (line, None),
+    };
+    if interp.is_empty() {
+        return None;
+    }
+    Some((
+        String::from(interp),
+        arg.filter(|arg| !arg.is_empty()).map(String::from),
+    ))
+}
+
+/// Collect a NULL-terminated array of user string pointers
+fn translated_str_array(token: usize, mut ptr: *const usize) -> Vec<String> {
+    let mut v: Vec<String> = Vec::new();
     loop {
-        let arg_str_ptr = *translated_ref(token, args);
-        if arg_str_ptr == 0 {
+        let str_ptr = *translated_ref(token, ptr);
+        if str_ptr == 0 {
             break;
         }
-        args_vec.push(translated_str(token, arg_str_ptr as *const u8));
+        v.push(translated_str(token, str_ptr as *const u8));
         unsafe {
-            args = args.add(1);
+            ptr = ptr.add(1);
         }
     }
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
-        let process = current_process();
-        let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
-        // return argc because cx.x[10] will be covered with it later
-        argc as isize
-    } else {
-        -1
-    }
+    v
 }
 
 /// waitpid syscall
@@ -131,7 +234,12 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child PCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let child_inner = child.inner_exclusive_access();
+        let exit_code = child_inner.exit_code;
+        let (utime, stime) = child_inner.cpu_time();
+        inner.children_utime += utime + child_inner.children_utime;
+        inner.children_stime += stime + child_inner.children_stime;
+        drop(child_inner);
         // ++++ release child PCB
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
@@ -159,44 +267,24 @@ pub fn sys_kill(pid: usize, signal: u32) -> isize {
     }
 }
 
-/// get_time syscall
-///
-/// YOUR JOB: get time with second and microsecond
-/// HINT: You might reimplement it with virtual memory management.
-/// HINT: What if [`TimeVal`] is splitted by two pages ?
-pub fn sys_get_time(_ts: *mut TimeVal, _tz: usize) -> isize {
-    trace!(
-        "kernel:pid[{}] sys_get_time NOT IMPLEMENTED",
-        current_task().unwrap().process.upgrade().unwrap().getpid()
-    );
-    let _us = get_time_us();
-        let time_val = TimeVal {
-            sec: _us / 1_000_000,
-            usec: _us % 1_000_000,
-        };
-        let buffers = translated_byte_buffer(
-            current_user_token(), _ts as *const u8, mem::size_of::<TimeVal>());
-        let mut time_val_ptr = &time_val as *const _ as *const u8;
-        for buffer in buffers {
-            unsafe {
-                time_val_ptr.copy_to(buffer.as_mut_ptr(), buffer.len());
-                time_val_ptr = time_val_ptr.add(buffer.len());
-            }
-        }
-        0
-}
-
 /// task_info syscall
 ///
-/// YOUR JOB: Finish sys_task_info to pass testcases
-/// HINT: You might reimplement it with virtual memory management.
-/// HINT: What if [`TaskInfo`] is splitted by two pages ?
-pub fn sys_task_info(_ti: *mut TaskInfo) -> isize {
+/// Report the status, syscall counts and running time (ms since first scheduled) of the current task.
+pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
     trace!(
-        "kernel:pid[{}] sys_task_info NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_task_info",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    -1
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    let task_info = TaskInfo {
+        status: TaskStatus::Running,
+        syscall_times: inner.syscall_times,
+        time: (get_time_us() - inner.time_info.first_time) / 1000,
+    };
+    drop(inner);
+    copy_to_user(current_user_token(), ti, &task_info);
+    0
 }
 
 /// mmap syscall
@@ -231,14 +319,158 @@ pub fn sys_munmap(_start: usize, _len: usize) -> isize {
 // }
 
 /// spawn syscall
-/// YOUR JOB: Implement spawn.
-/// HINT: fork + exec =/= spawn
-pub fn sys_spawn(_path: *const u8) -> isize {
+///
+/// Create a child running the program at `path` without copying our address space.
+pub fn sys_spawn(path: *const u8) -> isize {
     trace!(
-        "kernel:pid[{}] sys_spawn NOT IMPLEMENTED",
+        "kernel:pid[{}] sys_spawn",
         current_task().unwrap().process.upgrade().unwrap().getpid()
     );
-    -1
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let args_vec = vec![path.clone()];
+    spawn(path, args_vec, Vec::new(), None)
+}
+
+/// posix_spawn file action: close `fd`
+pub const SPAWN_FA_CLOSE: usize = 0;
+/// posix_spawn file action: duplicate `fd` to `newfd`
+pub const SPAWN_FA_DUP2: usize = 1;
+/// posix_spawn file action: open `path` with `flags` at `fd`
+pub const SPAWN_FA_OPEN: usize = 2;
+
+/// A file action applied, in order, to the child's copy of the fd table
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SpawnFileAction {
+    /// one of `SPAWN_FA_*`
+    pub cmd: usize,
+    /// the fd to act on
+    pub fd: usize,
+    /// target fd of `SPAWN_FA_DUP2`
+    pub newfd: usize,
+    /// path of `SPAWN_FA_OPEN`
+    pub path: *const u8,
+    /// open flags of `SPAWN_FA_OPEN`
+    pub flags: u32,
+    /// permission bits of a file created by `SPAWN_FA_OPEN`
+    pub mode: u32,
+}
+
+/// reset effective ids, a no-op since they are always the real ones
+pub const POSIX_SPAWN_RESETIDS: usize = 0x01;
+/// reset signal handlers, a no-op since a new image has none
+pub const POSIX_SPAWN_SETSIGDEF: usize = 0x04;
+/// suspend the parent until exec, a no-op since spawn shares nothing
+pub const POSIX_SPAWN_USEVFORK: usize = 0x40;
+
+/// Attributes of posix_spawn
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct SpawnAttr {
+    /// `POSIX_SPAWN_*` flags
+    pub flags: usize,
+}
+
+/// posix_spawn syscall
+///
+/// Like `sys_spawn`, with argv/envp, `n_actions` file actions and optional attributes.
+pub fn sys_posix_spawn(
+    path: *const u8,
+    file_actions: *const SpawnFileAction,
+    n_actions: usize,
+    attr: *const SpawnAttr,
+    args: *const usize,
+    envp: *const usize,
+) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_posix_spawn",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    if !attr.is_null() {
+        let attr = *translated_ref(token, attr);
+        if attr.flags & !(POSIX_SPAWN_RESETIDS | POSIX_SPAWN_SETSIGDEF | POSIX_SPAWN_USEVFORK) != 0 {
+            return -EINVAL;
+        }
+    }
+    let args_vec = if args.is_null() {
+        vec![path.clone()]
+    } else {
+        translated_str_array(token, args)
+    };
+    let envs_vec = if envp.is_null() {
+        Vec::new()
+    } else {
+        translated_str_array(token, envp)
+    };
+    let actions: Vec<SpawnFileAction> = (0..n_actions)
+        .map(|i| *translated_ref(token, unsafe { file_actions.add(i) }))
+        .collect();
+    spawn(path, args_vec, envs_vec, Some(&actions))
+}
+
+/// Load `path` and start it as a child of the current process.
+/// The child inherits our fd table, modified by `actions`.
+fn spawn(
+    path: String,
+    args_vec: Vec<String>,
+    envs_vec: Vec<String>,
+    actions: Option<&[SpawnFileAction]>,
+) -> isize {
+    let (all_data, args_vec) = match load_program(path, args_vec) {
+        Ok(program) => program,
+        Err(err) => return err,
+    };
+    let process = current_process();
+    let mut fd_table = process.inner_exclusive_access().fd_table.clone();
+    for action in actions.unwrap_or(&[]) {
+        if apply_file_action(&mut fd_table, action).is_none() {
+            return -EBADF;
+        }
+    }
+    match process.spawn(all_data.as_slice(), args_vec, envs_vec, fd_table) {
+        Ok(child) => child.getpid() as isize,
+        Err(err) => err,
+    }
+}
+
+/// Apply one posix_spawn file action to `fd_table`
+fn apply_file_action(
+    fd_table: &mut Vec<Option<Arc<dyn File + Send + Sync>>>,
+    action: &SpawnFileAction,
+) -> Option<()> {
+    let fd = action.fd;
+    match action.cmd {
+        SPAWN_FA_CLOSE => {
+            fd_table.get_mut(fd)?.take()?;
+        }
+        SPAWN_FA_DUP2 => {
+            let file = fd_table.get(fd)?.clone()?;
+            if fd_table.len() <= action.newfd {
+                fd_table.resize(action.newfd + 1, None);
+            }
+            fd_table[action.newfd] = Some(file);
+        }
+        SPAWN_FA_OPEN => {
+            let path = translated_str(current_user_token(), action.path);
+            let inode = open_file_at(
+                &current_cwd().ok()?,
+                path.as_str(),
+                OpenFlags::from_bits(action.flags)?,
+                action.mode as u16,
+                current_cred(),
+            )
+            .ok()?;
+            if fd_table.len() <= fd {
+                fd_table.resize(fd + 1, None);
+            }
+            fd_table[fd] = Some(inode);
+        }
+        _ => return None,
+    }
+    Some(())
 }
 
 /// set priority syscall
@@ -1,15 +1,48 @@
-use crate::sync::{Condvar, Mutex, MutexBlocking, MutexSpin, Semaphore};
-use crate::task::{block_current_and_run_next, current_process, current_task};
-use crate::timer::{add_timer, get_time_ms};
-use alloc::vec;
-use alloc::vec::Vec;
-use core::cmp::max;
+use super::errno::{EAGAIN, EFAULT, EINVAL, ENOSYS, ETIMEDOUT};
+use super::time::TimeSpec;
+use crate::mm::{copy_from_user, copy_to_user, kernel_token, PageTable, PhysAddr, VirtAddr};
+use crate::sync::{
+    check_request, futex_dequeue, futex_enqueue, futex_requeue, futex_wake, Barrier, Condvar,
+    DeadlockEntry, DeadlockPolicy, FutexWait, Mutex, MutexBlocking, MutexSpin, RwLock, Semaphore,
+    FUTEX_BITSET_MATCH_ANY,
+};
+use crate::task::{current_process, current_task, current_user_token};
+use crate::timer::{get_realtime_ns, get_time_ns, timeout as timeout_at, NSEC_PER_SEC};
 
 use alloc::sync::Arc;
-/// sleep syscall
-pub fn sys_sleep(ms: usize) -> isize {
+use core::sync::atomic::{AtomicU32, Ordering};
+
+/// futex op: wait if the word still holds `val`
+pub const FUTEX_WAIT: usize = 0;
+/// futex op: wake `val` waiters
+pub const FUTEX_WAKE: usize = 1;
+/// futex op: wake `val` waiters, move up to `val2` to `uaddr2`
+pub const FUTEX_REQUEUE: usize = 3;
+/// futex op: FUTEX_REQUEUE if the word still holds `val3`
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+/// futex op: FUTEX_WAIT with a bitset and an absolute timeout
+pub const FUTEX_WAIT_BITSET: usize = 9;
+/// futex op: FUTEX_WAKE of waiters matching a bitset
+pub const FUTEX_WAKE_BITSET: usize = 10;
+/// futex flag: the futex is not shared with other processes
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+/// futex flag: absolute timeouts are on CLOCK_REALTIME
+pub const FUTEX_CLOCK_REALTIME: usize = 256;
+
+/// futex syscall
+///
+/// `timeout` is a `*const TimeSpec` for the wait ops and the requeue limit `val2`
+/// for the requeue ops. Pointers are kept as usize since they live across an await.
+pub async fn sys_futex(
+    uaddr: usize,
+    futex_op: usize,
+    val: usize,
+    timeout: usize,
+    uaddr2: usize,
+    val3: usize,
+) -> isize {
     trace!(
-        "kernel:pid[{}] tid[{}] sys_sleep",
+        "kernel:pid[{}] tid[{}] sys_futex",
         current_task().unwrap().process.upgrade().unwrap().getpid(),
         current_task()
             .unwrap()
@@ -19,12 +52,142 @@ pub fn sys_sleep(ms: usize) -> isize {
             .unwrap()
             .tid
     );
-    let expire_ms = get_time_ms() + ms;
-    let task = current_task().unwrap();
-    add_timer(expire_ms, task);
-    block_current_and_run_next();
-    0
+    let token = current_user_token();
+    let key = match futex_key(token, uaddr) {
+        Some(key) => key,
+        None => return -EFAULT,
+    };
+    let op = futex_op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME);
+    match op {
+        FUTEX_WAIT | FUTEX_WAIT_BITSET => {
+            let bitset = match op {
+                FUTEX_WAIT => FUTEX_BITSET_MATCH_ANY,
+                _ => val3 as u32,
+            };
+            if bitset == 0 {
+                return -EINVAL;
+            }
+            // deadline in ms since boot, relative for FUTEX_WAIT
+            let deadline = if timeout == 0 {
+                None
+            } else {
+                let ts = match copy_from_user(token, timeout as *const TimeSpec).to_ns() {
+                    Some(ns) => ns,
+                    None => return -EINVAL,
+                };
+                let now = get_time_ns();
+                let deadline_ns = if op == FUTEX_WAIT {
+                    now.saturating_add(ts)
+                } else if futex_op & FUTEX_CLOCK_REALTIME != 0 {
+                    ts.saturating_sub(get_realtime_ns() - now)
+                } else {
+                    ts
+                };
+                Some(deadline_ns.div_ceil(NSEC_PER_SEC / 1000))
+            };
+            // the check and the enqueue happen without yielding, so no wake is lost
+            if let Err(err) = futex_check(token, uaddr, val as u32) {
+                return err;
+            }
+            let waiter = futex_enqueue(key, bitset);
+            match deadline {
+                None => FutexWait(Arc::clone(&waiter)).await,
+                Some(deadline) => {
+                    if timeout_at(deadline, FutexWait(Arc::clone(&waiter))).await.is_none()
+                        && !waiter.is_woken()
+                    {
+                        futex_dequeue(&waiter);
+                        return -ETIMEDOUT;
+                    }
+                }
+            }
+            0
+        }
+        FUTEX_WAKE => futex_wake(key, val, FUTEX_BITSET_MATCH_ANY) as isize,
+        FUTEX_WAKE_BITSET => {
+            if val3 as u32 == 0 {
+                return -EINVAL;
+            }
+            futex_wake(key, val, val3 as u32) as isize
+        }
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            let key2 = match futex_key(token, uaddr2) {
+                Some(key2) => key2,
+                None => return -EFAULT,
+            };
+            if op == FUTEX_CMP_REQUEUE {
+                if let Err(err) = futex_check(token, uaddr, val3 as u32) {
+                    return err;
+                }
+            }
+            let (woken, requeued) = futex_requeue(key, val, key2, timeout);
+            match op {
+                FUTEX_REQUEUE => woken as isize,
+                _ => (woken + requeued) as isize,
+            }
+        }
+        _ => -ENOSYS,
+    }
+}
+
+/// Physical address of the word at `uaddr`, if its page is readable from user mode
+fn user_word(token: usize, uaddr: usize) -> Option<PhysAddr> {
+    let va = VirtAddr::from(uaddr);
+    let page_table = PageTable::from_token(token);
+    let pte = page_table.translate(va.floor())?;
+    if !pte.readable() || !pte.is_user() {
+        return None;
+    }
+    page_table.translate_va(va)
+}
+
+/// Physical address of the futex word at `uaddr`, which must be 4-byte aligned
+fn futex_key(token: usize, uaddr: usize) -> Option<usize> {
+    if uaddr % core::mem::size_of::<u32>() != 0 {
+        return None;
+    }
+    Some(user_word(token, uaddr)?.into())
+}
+
+/// Check that the futex word at `uaddr` still holds `val`, -EAGAIN if it
+/// does not and -EFAULT if it is not readable by the user
+fn futex_check(token: usize, uaddr: usize, val: u32) -> Result<(), isize> {
+    let pa = user_word(token, uaddr).ok_or(-EFAULT)?;
+    futex_compare(pa.get_ref::<u32>(), val)
 }
+
+/// -EAGAIN unless `word` holds `val`
+fn futex_compare(word: &u32, val: u32) -> Result<(), isize> {
+    match *word == val {
+        true => Ok(()),
+        false => Err(-EAGAIN),
+    }
+}
+
+/// test the futex word check and a wait on a kernel word
+#[allow(unused)]
+pub fn futex_test() {
+    static WORD: AtomicU32 = AtomicU32::new(1);
+    let token = kernel_token();
+    let uaddr = &WORD as *const AtomicU32 as usize;
+    // a kernel page is no futex word of a user
+    assert_eq!(futex_key(token, uaddr), None);
+    assert_eq!(futex_check(token, uaddr, 1), Err(-EFAULT));
+    // waiters only need a unique key, the address of the word serves
+    let key = uaddr;
+    let word = WORD.load(Ordering::Relaxed);
+    // a word that changed is not waited on
+    assert_eq!(futex_compare(&word, 2), Err(-EAGAIN));
+    // a word still holding the value is, until a wake
+    assert_eq!(futex_compare(&word, 1), Ok(()));
+    let waiter = futex_enqueue(key, FUTEX_BITSET_MATCH_ANY);
+    assert!(!waiter.is_woken());
+    assert_eq!(futex_wake(key, 1, FUTEX_BITSET_MATCH_ANY), 1);
+    assert!(waiter.is_woken());
+    assert_eq!(futex_wake(key, 1, FUTEX_BITSET_MATCH_ANY), 0);
+    println!("futex_test passed!");
+}
+
 /// mutex create syscall
 pub fn sys_mutex_create(blocking: bool) -> isize {
     trace!(
@@ -75,50 +238,16 @@ pub fn sys_mutex_lock(mutex_id: usize) -> isize {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
     let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
-    
+    drop(process_inner);
     let task = current_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
-   task_inner.mutex_need = mutex_id;
-    drop(task_inner);
-    drop(task);
-    
-    if process_inner.dlcheck_option {
-        // initialize data structure for Banker's Algorithm:
-        // Avavilable Vector, Allocation Matrix, Need Matrix
-       let n = process_inner.tasks.len();
-        let m = process_inner.mutex_list.len();
-       let mut available: Vec<usize> = vec![1; m];
-       let mut need: Vec<Vec<usize>> = vec![vec![0; m]; n];
-       let mut allocation: Vec<Vec<usize>> = vec![vec![0; m]; n];
-        for (i, task_opt) in process_inner.tasks.iter().enumerate() {
-            match task_opt {
-                Some(task) => {
-                    let task_inner = task.inner_exclusive_access();
-                    for mid in &task_inner.mutex_allocation {
-                        allocation[i][*mid] += 1;
-                        available[*mid] -= 1;
-                    }
-                    let nid = task_inner.mutex_need;
-                    if nid != usize::MAX {
-                        need[i][nid] += 1;
-                    }
-                    drop(task_inner);
-                }
-                None => {}
-            }
-        }
-
-        if !deadlock_check(available, allocation, need) {
-            return -0xDEAD;
-        }
+    task.inner_exclusive_access().mutex_need = mutex_id;
+    if !check_request(&process, &task) {
+        task.inner_exclusive_access().mutex_need = usize::MAX;
+        return -0xDEAD;
     }
-
-    
-    drop(process_inner);
     drop(process);
     mutex.lock();
-        let task = current_task().unwrap();
-   let mut task_inner = task.inner_exclusive_access();
+    let mut task_inner = task.inner_exclusive_access();
     task_inner.mutex_allocation.push(mutex_id);
     task_inner.mutex_need = usize::MAX;
     drop(task_inner);
@@ -217,7 +346,18 @@ pub fn sys_semaphore_up(sem_id: usize) -> isize {
     0
 }
 /// semaphore down syscall
-pub fn sys_semaphore_down(sem_id: usize) -> isize {
+pub async fn sys_semaphore_down(sem_id: usize) -> isize {
+    match semaphore_down_prepare(sem_id) {
+        Ok(sem) => {
+            sem.down().await;
+            0
+        }
+        Err(err) => err,
+    }
+}
+/// Record the semaphore need of the current task and run the deadlock check,
+/// returns the semaphore to wait on
+fn semaphore_down_prepare(sem_id: usize) -> Result<Arc<Semaphore>, isize> {
     trace!(
         "kernel:pid[{}] tid[{}] sys_semaphore_down",
         current_task().unwrap().process.upgrade().unwrap().getpid(),
@@ -232,64 +372,14 @@ pub fn sys_semaphore_down(sem_id: usize) -> isize {
     let process = current_process();
     let process_inner = process.inner_exclusive_access();
     let sem = Arc::clone(process_inner.semaphore_list[sem_id].as_ref().unwrap());
+    drop(process_inner);
     let task = current_task().unwrap();
-    let mut task_inner = task.inner_exclusive_access();
-    task_inner.sem_need = sem_id;
-    drop(task_inner);
-    drop(task);
-
-    if process_inner.dlcheck_option {
-        // initialize data structure for Banker's Algorithm:
-        // Avavilable Vector, Allocation Matrix, Need Matrix
-        let n = process_inner.tasks.len();
-        let m = process_inner.semaphore_list.len();
-        let mut available:Vec<usize> = Vec::new();
-        for sem_opt in &process_inner.semaphore_list {
-            match sem_opt {
-                Some(sem) => {
-                    let sem_inner = sem.inner.exclusive_access();
-                    available.push(max(sem_inner.count, 0) as usize);
-                    drop(sem_inner);
-                }
-                None => available.push(0),
-            }
-        }
-        let mut allocation: Vec<Vec<usize>> = vec![vec![0; m]; n];
-        for (i, task_opt) in process_inner.tasks.iter().enumerate() {
-            match task_opt {
-                Some(task) => {
-                    let task_inner = task.inner_exclusive_access();
-                    for (id, alloc) in &task_inner.sem_allocation {
-                        allocation[i][*id] += *alloc;
-                    }
-                    drop(task_inner);
-                }
-                None => {}
-            }
-        }
-        let mut need: Vec<Vec<usize>> = vec![vec![0; m]; n];
-        for (i, task_opt) in process_inner.tasks.iter().enumerate() {
-            match task_opt {
-                Some(task) => {
-                    let task_inner = task.inner_exclusive_access();
-                    let nid = task_inner.sem_need;
-                    if nid != usize::MAX {
-                        need[i][nid] += 1;
-                    }
-                    drop(task_inner);
-                }
-                None => {}
-            }
-        }
-
-        if !deadlock_check(available, allocation, need) {
-            return -0xDEAD;
-        }
+    task.inner_exclusive_access().sem_need = sem_id;
+    if !check_request(&process, &task) {
+        task.inner_exclusive_access().sem_need = usize::MAX;
+        return Err(-0xDEAD);
     }
-    
-    drop(process_inner);
-    sem.down();
-    0
+    Ok(sem)
 }
 /// condvar create syscall
 pub fn sys_condvar_create() -> isize {
@@ -361,57 +451,254 @@ pub fn sys_condvar_wait(condvar_id: usize, mutex_id: usize) -> isize {
     let condvar = Arc::clone(process_inner.condvar_list[condvar_id].as_ref().unwrap());
     let mutex = Arc::clone(process_inner.mutex_list[mutex_id].as_ref().unwrap());
     drop(process_inner);
+    // the mutex is released while waiting
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    if let Some(index) = task_inner.mutex_allocation.iter().position(|&x| x == mutex_id) {
+        task_inner.mutex_allocation.swap_remove(index);
+    }
+    task_inner.condvar_need = condvar_id;
+    drop(task_inner);
+    if !check_request(&process, &task) {
+        let mut task_inner = task.inner_exclusive_access();
+        task_inner.condvar_need = usize::MAX;
+        if task_inner.zombie {
+            // killed as the victim, the mutex goes with the rest it held
+            drop(task_inner);
+            mutex.unlock();
+        } else {
+            task_inner.mutex_allocation.push(mutex_id);
+        }
+        return -0xDEAD;
+    }
+    drop(process);
     condvar.wait(mutex);
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.condvar_need = usize::MAX;
+    task_inner.mutex_allocation.push(mutex_id);
     0
 }
+/// rwlock create syscall
+pub fn sys_rwlock_create() -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_rwlock_create",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = if let Some(id) = process_inner
+        .rwlock_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.rwlock_list[id] = Some(Arc::new(RwLock::new()));
+        id
+    } else {
+        process_inner.rwlock_list.push(Some(Arc::new(RwLock::new())));
+        process_inner.rwlock_list.len() - 1
+    };
+    id as isize
+}
+/// rwlock read syscall
+pub async fn sys_rwlock_read(rwlock_id: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_rwlock_read",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    rwlock_acquire(rwlock_id, false).await
+}
+/// rwlock write syscall
+pub async fn sys_rwlock_write(rwlock_id: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_rwlock_write",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    rwlock_acquire(rwlock_id, true).await
+}
+async fn rwlock_acquire(rwlock_id: usize, write: bool) -> isize {
+    let rwlock = match rwlock_acquire_prepare(rwlock_id, write) {
+        Ok(rwlock) => rwlock,
+        Err(err) => return err,
+    };
+    match write {
+        true => rwlock.write().await,
+        false => rwlock.read().await,
+    }
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.rwlock_allocation.push((rwlock_id, write));
+    task_inner.rwlock_need = usize::MAX;
+    0
+}
+/// Record the rwlock need of the current task and run the deadlock check,
+/// returns the rwlock to wait on
+fn rwlock_acquire_prepare(rwlock_id: usize, write: bool) -> Result<Arc<RwLock>, isize> {
+    let process = current_process();
+    let rwlock = match process.inner_exclusive_access().rwlock_list.get(rwlock_id) {
+        Some(Some(rwlock)) => Arc::clone(rwlock),
+        _ => return Err(-1),
+    };
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    task_inner.rwlock_need = rwlock_id;
+    task_inner.rwlock_need_write = write;
+    drop(task_inner);
+    if !check_request(&process, &task) {
+        task.inner_exclusive_access().rwlock_need = usize::MAX;
+        return Err(-0xDEAD);
+    }
+    Ok(rwlock)
+}
+/// rwlock unlock syscall
+pub fn sys_rwlock_unlock(rwlock_id: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_rwlock_unlock",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let rwlock = match process_inner.rwlock_list.get(rwlock_id) {
+        Some(Some(rwlock)) => Arc::clone(rwlock),
+        _ => return -1,
+    };
+    drop(process_inner);
+    let task = current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    match task_inner.rwlock_allocation.iter().position(|&x| x.0 == rwlock_id) {
+        Some(index) => task_inner.rwlock_allocation.swap_remove(index),
+        // not held by this task
+        None => return -1,
+    };
+    drop(task_inner);
+    rwlock.unlock();
+    0
+}
+/// barrier create syscall
+pub fn sys_barrier_create(count: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_barrier_create",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    if count == 0 {
+        return -EINVAL;
+    }
+    let process = current_process();
+    let mut process_inner = process.inner_exclusive_access();
+    let id = if let Some(id) = process_inner
+        .barrier_list
+        .iter()
+        .enumerate()
+        .find(|(_, item)| item.is_none())
+        .map(|(id, _)| id)
+    {
+        process_inner.barrier_list[id] = Some(Arc::new(Barrier::new(count)));
+        id
+    } else {
+        process_inner
+            .barrier_list
+            .push(Some(Arc::new(Barrier::new(count))));
+        process_inner.barrier_list.len() - 1
+    };
+    id as isize
+}
+/// barrier wait syscall, returns 1 in the task that completed the round and 0 in the others
+pub async fn sys_barrier_wait(barrier_id: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] tid[{}] sys_barrier_wait",
+        current_task().unwrap().process.upgrade().unwrap().getpid(),
+        current_task()
+            .unwrap()
+            .inner_exclusive_access()
+            .res
+            .as_ref()
+            .unwrap()
+            .tid
+    );
+    let process = current_process();
+    let barrier = match process.inner_exclusive_access().barrier_list.get(barrier_id) {
+        Some(Some(barrier)) => Arc::clone(barrier),
+        _ => return -1,
+    };
+    drop(process);
+    barrier.wait().await as isize
+}
 /// enable deadlock detection syscall
 ///
-/// YOUR JOB: Implement deadlock detection, but might not all in this syscall
-pub fn sys_enable_deadlock_detect(_enabled: usize) -> isize {
+/// 0 disables detection, 1 refuses a request that would deadlock with -0xDEAD,
+/// 2 exits the requesting task with -0xDEAD, releasing its locks, 3 only
+/// records the report
+pub fn sys_enable_deadlock_detect(enabled: usize) -> isize {
     trace!("kernel: sys_enable_deadlock_detect");
-  let process = current_process();
+    let policy = match enabled {
+        0 => None,
+        1 => Some(DeadlockPolicy::Refuse),
+        2 => Some(DeadlockPolicy::KillVictim),
+        3 => Some(DeadlockPolicy::LogOnly),
+        _ => return -1,
+    };
+    let process = current_process();
     let mut process_inner = process.inner_exclusive_access();
-    let mut flag = 0;
-    match _enabled {
-        0 => process_inner.dlcheck_option = false,
-        1 => process_inner.dlcheck_option = true,
-        _ => flag = -1,
+    process_inner.dlcheck_option = policy.is_some();
+    if let Some(policy) = policy {
+        process_inner.dlcheck_policy = policy;
     }
-    drop(process_inner);
-    flag
+    0
 }
-fn deadlock_check(available: Vec<usize>, allocation: Vec<Vec<usize>>, need: Vec<Vec<usize>>) -> bool {
-        // n: thread count  m: resources count
-        let (n, m) = (allocation.len(), allocation[0].len());
-        let mut work = available;
-        let mut finish = vec![false; n];
-        loop {
-            let mut idx = usize::MAX;
-            for i in 0..n {
-                let mut flag = true;
-                if finish[i] {
-                    continue;
-                }
-                for j in 0..m {
-                    if need[i][j] > work[j] {
-                        flag = false;
-                        break;
-                    }
-                }
-                if flag {
-                    idx = i;
-                    break;
-                }
-            }
-            // has found a thread meet the requirement
-            if idx != usize::MAX {
-                for j in 0..m {
-                    work[j] += allocation[idx][j];
-                }
-                finish[idx] = true;
-            } else {
-                break;
-            }
-        }
-        finish.iter().all(|&x| x)
+/// deadlock report syscall
+///
+/// Copies up to `len` entries of the last deadlock found in the process to
+/// `buf`, returns the number of entries of the report, 0 if there is none.
+pub fn sys_deadlock_report(buf: *mut DeadlockEntry, len: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_deadlock_report",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let process = current_process();
+    let process_inner = process.inner_exclusive_access();
+    let report = match process_inner.deadlock_report.as_ref() {
+        Some(report) => report,
+        None => return 0,
+    };
+    for (i, entry) in report.entries.iter().take(len).enumerate() {
+        copy_to_user(token, buf.wrapping_add(i), entry);
     }
+    report.entries.len() as isize
+}
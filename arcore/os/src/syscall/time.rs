@@ -0,0 +1,514 @@
+//! Time related syscalls
+
+use crate::config::CLOCK_FREQ;
+use super::errno::{EFAULT, EINVAL, EPERM};
+use super::fs::current_cred;
+use crate::mm::{copy_from_user, copy_to_user, translated_ref, try_copy_from_user};
+use crate::task::{current_process, current_task, current_user_token, SignalFlags};
+use crate::timer::itimer::{set_itimer, set_posix_timer, ITimerValue, RealTimer, ITIMER_REAL};
+use crate::timer::{
+    get_realtime_ns, get_time, get_time_ns, set_realtime_ns, sleep, sleep_until, NSEC_PER_SEC,
+};
+
+/// wall clock time
+pub const CLOCK_REALTIME: usize = 0;
+/// time since boot
+pub const CLOCK_MONOTONIC: usize = 1;
+/// cpu time of the calling process
+pub const CLOCK_PROCESS_CPUTIME_ID: usize = 2;
+/// cpu time of the calling thread
+pub const CLOCK_THREAD_CPUTIME_ID: usize = 3;
+
+/// `clock_nanosleep` flag: the request is an absolute time
+pub const TIMER_ABSTIME: usize = 1;
+
+/// timer notification: post `sigev_signo` to the process
+pub const SIGEV_SIGNAL: i32 = 0;
+/// timer notification: none
+pub const SIGEV_NONE: i32 = 1;
+/// timer notification: post `sigev_signo` to thread `sigev_notify_thread_id`,
+/// refused as signals are only delivered to processes
+pub const SIGEV_THREAD_ID: i32 = 4;
+
+/// resource usage of the calling process
+pub const RUSAGE_SELF: isize = 0;
+/// resource usage of the waited-for children
+pub const RUSAGE_CHILDREN: isize = -1;
+/// resource usage of the calling thread
+pub const RUSAGE_THREAD: isize = 1;
+
+/// clock ticks per second reported by `times`, matches AT_CLKTCK
+const CLK_TCK: usize = 100;
+
+/// Time in seconds and microseconds
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeVal {
+    /// seconds
+    pub sec: usize,
+    /// microseconds
+    pub usec: usize,
+}
+
+impl TimeVal {
+    /// Split a duration in microseconds
+    pub fn from_us(us: usize) -> Self {
+        Self {
+            sec: us / 1_000_000,
+            usec: us % 1_000_000,
+        }
+    }
+}
+
+/// Time in seconds and nanoseconds
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeSpec {
+    /// seconds
+    pub sec: usize,
+    /// nanoseconds
+    pub nsec: usize,
+}
+
+impl TimeSpec {
+    /// Split a duration in nanoseconds
+    pub fn from_ns(ns: usize) -> Self {
+        Self {
+            sec: ns / NSEC_PER_SEC,
+            nsec: ns % NSEC_PER_SEC,
+        }
+    }
+    /// The duration in nanoseconds, `None` if `nsec` is out of range
+    pub fn to_ns(self) -> Option<usize> {
+        if self.nsec >= NSEC_PER_SEC {
+            return None;
+        }
+        Some(self.sec.saturating_mul(NSEC_PER_SEC).saturating_add(self.nsec))
+    }
+}
+
+/// Interval timer value with microseconds
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ITimerVal {
+    /// reload value
+    pub it_interval: TimeVal,
+    /// time to the next expiration
+    pub it_value: TimeVal,
+}
+
+/// Interval timer value with nanoseconds
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ITimerSpec {
+    /// reload value
+    pub it_interval: TimeSpec,
+    /// time to the next expiration
+    pub it_value: TimeSpec,
+}
+
+/// How a POSIX timer notifies its expiration
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SigEvent {
+    /// data passed along with the notification
+    pub sigev_value: usize,
+    /// signal number
+    pub sigev_signo: i32,
+    /// one of `SIGEV_*`
+    pub sigev_notify: i32,
+    /// target thread of `SIGEV_THREAD_ID`
+    pub sigev_notify_thread_id: i32,
+    /// pad to the size of the Linux structure
+    pub _pad: [i32; 11],
+}
+
+/// Process times in clock ticks
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Tms {
+    /// user time
+    pub tms_utime: usize,
+    /// system time
+    pub tms_stime: usize,
+    /// user time of waited-for children
+    pub tms_cutime: usize,
+    /// system time of waited-for children
+    pub tms_cstime: usize,
+}
+
+/// Resource usage, only the times are tracked
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rusage {
+    /// user time used
+    pub ru_utime: TimeVal,
+    /// system time used
+    pub ru_stime: TimeVal,
+    /// maxrss, ixrss, idrss, isrss, minflt, majflt, nswap, inblock,
+    /// oublock, msgsnd, msgrcv, nsignals, nvcsw, nivcsw
+    pub ru_other: [isize; 14],
+}
+
+/// get_time syscall
+pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_get_time",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    copy_to_user(current_user_token(), ts, &TimeVal::from_us(get_realtime_ns() / 1000));
+    0
+}
+
+/// settimeofday syscall, only root may set the clock
+pub fn sys_settimeofday(tv: *const TimeVal, _tz: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_settimeofday",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if current_cred().uid != 0 {
+        return -EPERM;
+    }
+    if tv.is_null() {
+        return 0;
+    }
+    let tv = *translated_ref(current_user_token(), tv);
+    if tv.usec >= 1_000_000 {
+        return -EINVAL;
+    }
+    match tv
+        .sec
+        .checked_mul(NSEC_PER_SEC)
+        .and_then(|ns| ns.checked_add(tv.usec * 1000))
+    {
+        Some(ns) => set_realtime_ns(ns),
+        None => return -EINVAL,
+    }
+    0
+}
+
+/// clock_gettime syscall
+pub fn sys_clock_gettime(clock_id: usize, tp: *mut TimeSpec) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_clock_gettime",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let ns = match clock_id {
+        CLOCK_REALTIME => get_realtime_ns(),
+        CLOCK_MONOTONIC => get_time_ns(),
+        CLOCK_PROCESS_CPUTIME_ID => {
+            let (utime, stime) = current_process().inner_exclusive_access().cpu_time();
+            (utime + stime) * 1000
+        }
+        CLOCK_THREAD_CPUTIME_ID => {
+            let time_info = current_task().unwrap().inner_exclusive_access().time_info;
+            (time_info.utime + time_info.stime) * 1000
+        }
+        _ => return -1,
+    };
+    copy_to_user(current_user_token(), tp, &TimeSpec::from_ns(ns));
+    0
+}
+
+/// clock_getres syscall
+pub fn sys_clock_getres(clock_id: usize, res: *mut TimeSpec) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_clock_getres",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let ns = match clock_id {
+        // one tick of the time CSR
+        CLOCK_REALTIME | CLOCK_MONOTONIC => (NSEC_PER_SEC / CLOCK_FREQ).max(1),
+        // cpu time is accounted in microseconds
+        CLOCK_PROCESS_CPUTIME_ID | CLOCK_THREAD_CPUTIME_ID => 1000,
+        _ => return -1,
+    };
+    if !res.is_null() {
+        copy_to_user(current_user_token(), res, &TimeSpec::from_ns(ns));
+    }
+    0
+}
+
+/// times syscall
+///
+/// Returns the clock ticks elapsed since boot.
+pub fn sys_times(tms: *mut Tms) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_times",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    let (utime, stime) = inner.cpu_time();
+    let us_to_ticks = |us: usize| us * CLK_TCK / 1_000_000;
+    let times = Tms {
+        tms_utime: us_to_ticks(utime),
+        tms_stime: us_to_ticks(stime),
+        tms_cutime: us_to_ticks(inner.children_utime),
+        tms_cstime: us_to_ticks(inner.children_stime),
+    };
+    drop(inner);
+    if !tms.is_null() {
+        copy_to_user(current_user_token(), tms, &times);
+    }
+    (get_time() / (CLOCK_FREQ / CLK_TCK)) as isize
+}
+
+/// getrusage syscall
+pub fn sys_getrusage(who: isize, usage: *mut Rusage) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_getrusage",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let (utime, stime) = match who {
+        RUSAGE_SELF => current_process().inner_exclusive_access().cpu_time(),
+        RUSAGE_CHILDREN => {
+            let process = current_process();
+            let inner = process.inner_exclusive_access();
+            (inner.children_utime, inner.children_stime)
+        }
+        RUSAGE_THREAD => {
+            let time_info = current_task().unwrap().inner_exclusive_access().time_info;
+            (time_info.utime, time_info.stime)
+        }
+        _ => return -1,
+    };
+    let rusage = Rusage {
+        ru_utime: TimeVal::from_us(utime),
+        ru_stime: TimeVal::from_us(stime),
+        ..Default::default()
+    };
+    copy_to_user(current_user_token(), usage, &rusage);
+    0
+}
+
+/// sleep syscall, for `ms` milliseconds
+pub async fn sys_sleep(ms: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_sleep",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    sleep(ms).await;
+    0
+}
+
+/// clock_nanosleep syscall
+///
+/// Sleeps on the timer wheel, so the wakeup is rounded up to the next millisecond.
+/// `req` and `rem` are `*const TimeSpec` and `*mut TimeSpec`, kept as usize
+/// since raw pointers can't live across an await.
+pub async fn sys_clock_nanosleep(clock_id: usize, flags: usize, req: usize, rem: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_clock_nanosleep",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let req = match try_copy_from_user(current_user_token(), req as *const TimeSpec) {
+        Some(req) => req,
+        None => return -EFAULT,
+    };
+    let req = match req.to_ns() {
+        Some(ns) => ns,
+        None => return -EINVAL,
+    };
+    let now = get_time_ns();
+    // deadline on the monotonic clock
+    let deadline_ns = match (clock_id, flags & TIMER_ABSTIME != 0) {
+        (CLOCK_REALTIME, true) => req.saturating_sub(get_realtime_ns() - now),
+        (CLOCK_MONOTONIC, true) => req,
+        (CLOCK_REALTIME | CLOCK_MONOTONIC, false) => now.saturating_add(req),
+        _ => return -EINVAL,
+    };
+    sleep_until(deadline_ns.div_ceil(NSEC_PER_SEC / 1000)).await;
+    // nothing interrupts a sleep yet, so nothing remains
+    if rem != 0 && flags & TIMER_ABSTIME == 0 {
+        copy_to_user(current_user_token(), rem as *mut TimeSpec, &TimeSpec::default());
+    }
+    0
+}
+
+/// getitimer syscall
+pub fn sys_getitimer(which: usize, curr_value: *mut ITimerVal) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_getitimer",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let value = match current_process().inner_exclusive_access().timers.get_itimer(which) {
+        Some(value) => value,
+        None => return -EINVAL,
+    };
+    copy_to_user(current_user_token(), curr_value, &to_itimerval(value));
+    0
+}
+
+/// setitimer syscall
+pub fn sys_setitimer(which: usize, new_value: *const ITimerVal, old_value: *mut ITimerVal) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_setitimer",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let new = copy_from_user(token, new_value);
+    if new.it_value.usec >= 1_000_000 || new.it_interval.usec >= 1_000_000 {
+        return -EINVAL;
+    }
+    let to_us = |val: TimeVal| val.sec.checked_mul(1_000_000)?.checked_add(val.usec);
+    let new = match (to_us(new.it_value), to_us(new.it_interval)) {
+        (Some(value), Some(interval)) => ITimerValue { value, interval },
+        _ => return -EINVAL,
+    };
+    let old = match set_itimer(&current_process(), which, new) {
+        Some(old) => old,
+        None => return -EINVAL,
+    };
+    if !old_value.is_null() {
+        copy_to_user(token, old_value, &to_itimerval(old));
+    }
+    0
+}
+
+/// alarm syscall
+///
+/// Deliver SIGALRM in `seconds` (cancel if 0) via ITIMER_REAL, returns the seconds left of the old alarm.
+pub fn sys_alarm(seconds: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_alarm",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let new = match seconds.checked_mul(1_000_000) {
+        Some(value) => ITimerValue { value, interval: 0 },
+        None => return -EINVAL,
+    };
+    let old = match set_itimer(&current_process(), ITIMER_REAL, new) {
+        Some(old) => old,
+        None => return -EINVAL,
+    };
+    // round up so a pending alarm never reports 0
+    old.value.div_ceil(1_000_000) as isize
+}
+
+/// timer_create syscall
+///
+/// `sevp` may be NULL, meaning SIGEV_SIGNAL with SIGALRM. SIGEV_THREAD_ID
+/// fails with EINVAL, there is no per-thread delivery.
+pub fn sys_timer_create(clock_id: usize, sevp: *const SigEvent, timerid: *mut i32) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_timer_create",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if clock_id != CLOCK_REALTIME && clock_id != CLOCK_MONOTONIC {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let signal = if sevp.is_null() {
+        Some(SignalFlags::SIGALRM)
+    } else {
+        let event = copy_from_user(token, sevp);
+        let signal = SignalFlags::from_signum(event.sigev_signo as usize);
+        match event.sigev_notify {
+            SIGEV_NONE => None,
+            SIGEV_SIGNAL if signal.is_some() => signal,
+            // signals are only posted to processes, not to one thread
+            SIGEV_THREAD_ID => return -EINVAL,
+            _ => return -EINVAL,
+        }
+    };
+    let id = current_process()
+        .inner_exclusive_access()
+        .timers
+        .create_posix(RealTimer::new(clock_id, signal));
+    copy_to_user(token, timerid, &(id as i32));
+    0
+}
+
+/// timer_settime syscall
+pub fn sys_timer_settime(
+    timerid: usize,
+    flags: usize,
+    new_value: *const ITimerSpec,
+    old_value: *mut ITimerSpec,
+) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_timer_settime",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let token = current_user_token();
+    let process = current_process();
+    let clock_id = match process.inner_exclusive_access().timers.posix_clock(timerid) {
+        Some(clock_id) => clock_id,
+        None => return -EINVAL,
+    };
+    let new = copy_from_user(token, new_value);
+    let (Some(mut value), Some(interval)) = (new.it_value.to_ns(), new.it_interval.to_ns()) else {
+        return -EINVAL;
+    };
+    if flags & TIMER_ABSTIME != 0 && value != 0 {
+        let now = match clock_id {
+            CLOCK_REALTIME => get_realtime_ns(),
+            _ => get_time_ns(),
+        };
+        // an expiration in the past fires at once
+        value = value.saturating_sub(now).max(1);
+    }
+    let new = ITimerValue {
+        value: value.div_ceil(1000),
+        interval: interval.div_ceil(1000),
+    };
+    let old = set_posix_timer(&process, timerid, new).unwrap();
+    if !old_value.is_null() {
+        copy_to_user(token, old_value, &to_itimerspec(old));
+    }
+    0
+}
+
+/// timer_gettime syscall
+pub fn sys_timer_gettime(timerid: usize, curr_value: *mut ITimerSpec) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_timer_gettime",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    let value = match current_process().inner_exclusive_access().timers.get_posix(timerid) {
+        Some(value) => value,
+        None => return -EINVAL,
+    };
+    copy_to_user(current_user_token(), curr_value, &to_itimerspec(value));
+    0
+}
+
+/// timer_getoverrun syscall
+pub fn sys_timer_getoverrun(timerid: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_timer_getoverrun",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    match current_process().inner_exclusive_access().timers.posix_overrun(timerid) {
+        Some(overrun) => overrun.min(i32::MAX as usize) as isize,
+        None => -EINVAL,
+    }
+}
+
+/// timer_delete syscall
+pub fn sys_timer_delete(timerid: usize) -> isize {
+    trace!(
+        "kernel:pid[{}] sys_timer_delete",
+        current_task().unwrap().process.upgrade().unwrap().getpid()
+    );
+    if current_process().inner_exclusive_access().timers.delete_posix(timerid) {
+        0
+    } else {
+        -EINVAL
+    }
+}
+
+fn to_itimerval(value: ITimerValue) -> ITimerVal {
+    ITimerVal {
+        it_interval: TimeVal::from_us(value.interval),
+        it_value: TimeVal::from_us(value.value),
+    }
+}
+
+fn to_itimerspec(value: ITimerValue) -> ITimerSpec {
+    ITimerSpec {
+        it_interval: TimeSpec::from_ns(value.interval * 1000),
+        it_value: TimeSpec::from_ns(value.value * 1000),
+    }
+}
@@ -0,0 +1,61 @@
+//! ELF auxiliary vector entries passed to user programs on the initial stack
+
+/// end of vector
+pub const AT_NULL: usize = 0;
+/// program headers for program
+pub const AT_PHDR: usize = 3;
+/// size of program header entry
+pub const AT_PHENT: usize = 4;
+/// number of program headers
+pub const AT_PHNUM: usize = 5;
+/// system page size
+pub const AT_PAGESZ: usize = 6;
+/// base address of interpreter
+pub const AT_BASE: usize = 7;
+/// flags
+pub const AT_FLAGS: usize = 8;
+/// entry point of program
+pub const AT_ENTRY: usize = 9;
+/// real uid
+pub const AT_UID: usize = 11;
+/// effective uid
+pub const AT_EUID: usize = 12;
+/// real gid
+pub const AT_GID: usize = 13;
+/// effective gid
+pub const AT_EGID: usize = 14;
+/// arch dependent hints at CPU capabilities
+pub const AT_HWCAP: usize = 16;
+/// frequency at which times() increments
+pub const AT_CLKTCK: usize = 17;
+/// secure mode boolean
+pub const AT_SECURE: usize = 23;
+/// address of 16 random bytes
+pub const AT_RANDOM: usize = 25;
+/// filename of program
+pub const AT_EXECFN: usize = 31;
+
+/// RV64IMAFDC, one bit per extension letter
+pub const HWCAP_RV64IMAFDC: usize = (1 << (b'I' - b'A'))
+    | (1 << (b'M' - b'A'))
+    | (1 << 0)
+    | (1 << (b'F' - b'A'))
+    | (1 << (b'D' - b'A'))
+    | (1 << (b'C' - b'A'));
+
+/// An entry of the auxiliary vector
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct AuxHeader {
+    /// type of the entry
+    pub aux_type: usize,
+    /// value of the entry
+    pub value: usize,
+}
+
+impl AuxHeader {
+    /// Create an auxiliary vector entry
+    pub fn new(aux_type: usize, value: usize) -> Self {
+        Self { aux_type, value }
+    }
+}
@@ -9,6 +9,7 @@
 //! Be careful when you see [`__switch`]. Control flow around this function
 //! might not be what you expect.
 
+mod auxv;
 mod context;
 mod id;
 mod manager;
@@ -16,6 +17,7 @@ mod process;
 mod processor;
 mod signal;
 mod switch;
+mod time_info;
 #[allow(clippy::module_inception)]
 mod task;
 use crate::executor:: waker;
@@ -24,14 +26,18 @@ use core::panic;
 
 use crate::fs::{open_file, OpenFlags};
 
-use crate::timer::remove_timer;
 
 use alloc::sync::Arc;
 use lazy_static::*;
-use process::ProcessControlBlock;
+pub use process::ProcessControlBlock;
 /* use riscv::register::satp; */
 use switch::__switch;
 
+pub use auxv::{
+    AuxHeader, AT_BASE, AT_CLKTCK, AT_EGID, AT_ENTRY, AT_EUID, AT_EXECFN, AT_FLAGS, AT_GID,
+    AT_HWCAP, AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM, AT_SECURE, AT_UID,
+    HWCAP_RV64IMAFDC,
+};
 pub use context::TaskContext;
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
 pub use manager::{add_task, pid2process, remove_from_pid2process, remove_task, wakeup_task};
@@ -41,6 +47,7 @@ pub use processor::{
 };
 pub use signal::SignalFlags;
 pub use task::{TaskControlBlock, TaskStatus};
+pub use time_info::ThreadTimeInfo;
 #[allow(unreachable_code)]
 /// Make current task suspended and switch to the next task
 pub fn suspend_current_and_run_next() {
@@ -185,8 +192,6 @@ pub fn current_add_signal(signal: SignalFlags) {
 /// the inactive(blocked) tasks are removed when the PCB is deallocated.(called by exit_current_and_run_next)
 pub fn remove_inactive_task(task: Arc<TaskControlBlock>) {
     remove_task(Arc::clone(&task));
-    trace!("kernel: remove_inactive_task .. remove_timer");
-    remove_timer(Arc::clone(&task));
 }
 #[allow(warnings)]
 ///把两态切换放进async函数中
@@ -220,7 +225,5 @@ pub async fn taskloop(tbc: Arc<TaskControlBlock>) {
       
         }
     }
-    
-    panic!("thread is terminated");
-    
+    trace!("thread is terminated");
 }
\ No newline at end of file
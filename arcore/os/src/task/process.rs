@@ -4,19 +4,33 @@ use super::id::RecycleAllocator;
 use super::manager::insert_into_pid2process;
 use super::TaskControlBlock;
 use super::{add_task, SignalFlags};
+use super::{
+    AuxHeader, AT_CLKTCK, AT_EGID, AT_EUID, AT_EXECFN, AT_GID, AT_HWCAP, AT_NULL, AT_RANDOM,
+    AT_SECURE, AT_UID, HWCAP_RV64IMAFDC,
+};
 use super::{pid_alloc, PidHandle};
+use crate::config::USER_STACK_SIZE;
 use crate::executor::shed;
-use crate::fs::{File, Stdin, Stdout};
+use crate::fs::{Cred, File, Stdin, Stdout, ROOT_INODE};
 use crate::mm::{translated_refmut, MemorySet};
-use crate::sync::{Condvar, Mutex, Semaphore, UPSafeCell};
+use crate::sync::{
+    Barrier, Condvar, DeadlockPolicy, DeadlockReport, Mutex, RwLock, Semaphore, UPRefMut,
+    UPSafeCell,
+};
+use crate::syscall::errno::{E2BIG, ENOEXEC};
 use crate::task::processor::PROCESSOR;
+use crate::timer::get_time;
+use crate::timer::itimer::ProcessTimers;
 use crate::trap::TrapContext;
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
-use core::cell::RefMut;
+use easy_fs::Inode;
 /* use crate::trap::trap_handler; */
+/// lock class of every process inner cell, created by `create` and `fork` alike
+const INNER_CLASS: &str = "ProcessControlBlock::inner";
+
 /// Process Control Block
 pub struct ProcessControlBlock {
     /// immutable
@@ -37,6 +51,10 @@ pub struct ProcessControlBlockInner {
     pub children: Vec<Arc<ProcessControlBlock>>,
     /// exit code
     pub exit_code: i32,
+    /// current working directory
+    pub cwd: Arc<Inode>,
+    /// credentials files are accessed with
+    pub cred: Cred,
     /// file descriptor table
     pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
     /// signal flags
@@ -51,11 +69,58 @@ pub struct ProcessControlBlockInner {
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     /// condvar list
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// rwlock list
+    pub rwlock_list: Vec<Option<Arc<RwLock>>>,
+    /// barrier list
+    pub barrier_list: Vec<Option<Arc<Barrier>>>,
     /// deadlock check option
     pub dlcheck_option: bool,
+    /// what to do with a request that would deadlock
+    pub dlcheck_policy: DeadlockPolicy,
+    /// the last deadlock found
+    pub deadlock_report: Option<DeadlockReport>,
+    /// user time of waited-for children, in us
+    pub children_utime: usize,
+    /// kernel time of waited-for children, in us
+    pub children_stime: usize,
+    /// interval timers and POSIX timers
+    pub timers: ProcessTimers,
 }
 
 impl ProcessControlBlockInner {
+    /// Inner of a process that has no tasks or synchronization resources yet
+    fn new(
+        memory_set: MemorySet,
+        parent: Option<Weak<ProcessControlBlock>>,
+        cwd: Arc<Inode>,
+        cred: Cred,
+        fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    ) -> Self {
+        Self {
+            is_zombie: false,
+            memory_set,
+            parent,
+            children: Vec::new(),
+            exit_code: 0,
+            cwd,
+            cred,
+            fd_table,
+            signals: SignalFlags::empty(),
+            tasks: Vec::new(),
+            task_res_allocator: RecycleAllocator::new(),
+            mutex_list: Vec::new(),
+            semaphore_list: Vec::new(),
+            condvar_list: Vec::new(),
+            rwlock_list: Vec::new(),
+            barrier_list: Vec::new(),
+            dlcheck_option: false,
+            dlcheck_policy: DeadlockPolicy::Refuse,
+            deadlock_report: None,
+            children_utime: 0,
+            children_stime: 0,
+            timers: ProcessTimers::new(),
+        }
+    }
     #[allow(unused)]
     /// get the address of app's page table
     pub fn get_user_token(&self) -> usize {
@@ -70,6 +135,13 @@ impl ProcessControlBlockInner {
             self.fd_table.len() - 1
         }
     }
+    /// user and kernel time of all threads, in us
+    pub fn cpu_time(&self) -> (usize, usize) {
+        self.tasks.iter().flatten().fold((0, 0), |(utime, stime), task| {
+            let time_info = task.inner_exclusive_access().time_info;
+            (utime + time_info.utime, stime + time_info.stime)
+        })
+    }
     /// allocate a new task id
     pub fn alloc_tid(&mut self) -> usize {
         self.task_res_allocator.alloc()
@@ -90,41 +162,111 @@ impl ProcessControlBlockInner {
 
 impl ProcessControlBlock {
     /// inner_exclusive_access
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, ProcessControlBlockInner> {
+    #[track_caller]
+    pub fn inner_exclusive_access(&self) -> UPRefMut<'_, ProcessControlBlockInner> {
         self.inner.exclusive_access()
     }
     /// new process from elf file
     pub fn new(elf_data: &[u8]) -> Arc<Self> {
         trace!("kernel: ProcessControlBlock::new");
+        let fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = vec![
+            // 0 -> stdin
+            Some(Arc::new(Stdin)),
+            // 1 -> stdout
+            Some(Arc::new(Stdout)),
+            // 2 -> stderr
+            Some(Arc::new(Stdout)),
+        ];
+        let process =
+            Self::create(elf_data, Vec::new(), Vec::new(), None, fd_table).expect("invalid elf!");
+        let task = process.inner_exclusive_access().get_task(0);
+        // add main thread to scheduler
+        trace!("shed::spawn_user_thread");
+        PROCESSOR.exclusive_access().current = Some(task.clone());
+        
+        shed::spawn_user_thread(task);
+        process
+    }
+
+    /// A process without a program and with `threads` tasks that are never
+    /// scheduled, for kernel tests. It must be leaked, as dropping its tasks
+    /// frees a kernel stack they never allocated.
+    #[allow(unused)]
+    pub fn new_bare(threads: usize) -> Arc<Self> {
+        let process = Arc::new(Self {
+            pid: pid_alloc(),
+            inner: unsafe {
+                UPSafeCell::new_with_class(
+                    ProcessControlBlockInner::new(
+                        MemorySet::new_bare(),
+                        None,
+                        ROOT_INODE.clone(),
+                        Cred::ROOT,
+                        Vec::new(),
+                    ),
+                    INNER_CLASS,
+                )
+            },
+        });
+        for _ in 0..threads {
+            // any user address does, the address space is empty
+            let task = Arc::new(TaskControlBlock::new(Arc::clone(&process), 0x1000_0000, true));
+            process.inner_exclusive_access().tasks.push(Some(task));
+        }
+        process
+    }
+
+    /// Create a child process straight from an elf file, without copying our `MemorySet`.
+    /// Fails as `create` does.
+    pub fn spawn(
+        self: &Arc<Self>,
+        elf_data: &[u8],
+        args: Vec<String>,
+        envs: Vec<String>,
+        fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    ) -> Result<Arc<Self>, isize> {
+        trace!("kernel: spawn");
+        let child = Self::create(elf_data, args, envs, Some(Arc::downgrade(self)), fd_table)?;
+        // add child
+        self.inner_exclusive_access().children.push(Arc::clone(&child));
+        let task = child.inner_exclusive_access().get_task(0);
+        shed::spawn_user_thread(task);
+        Ok(child)
+    }
+
+    /// Build a process and its main thread from elf file, with arguments on the user stack.
+    /// Fails with -ENOEXEC for a malformed elf and -E2BIG if the arguments do not fit.
+    fn create(
+        elf_data: &[u8],
+        args: Vec<String>,
+        envs: Vec<String>,
+        parent: Option<Weak<Self>>,
+        fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    ) -> Result<Arc<Self>, isize> {
+        // a spawned child starts in the directory of its parent, with its credentials
+        let (cwd, cred) = match parent.as_ref().and_then(Weak::upgrade) {
+            Some(parent) => {
+                let parent_inner = parent.inner_exclusive_access();
+                (parent_inner.cwd.clone(), parent_inner.cred)
+            }
+            None => (ROOT_INODE.clone(), Cred::ROOT),
+        };
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, entry_point, auxv) =
+            MemorySet::from_elf(elf_data, cred).ok_or(-ENOEXEC)?;
+        if user_stack_size(&args, &envs, &auxv) > USER_STACK_SIZE {
+            return Err(-E2BIG);
+        }
+        let token = memory_set.token();
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
             inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: None,
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: vec![
-                        // 0 -> stdin
-                        Some(Arc::new(Stdin)),
-                        // 1 -> stdout
-                        Some(Arc::new(Stdout)),
-                        // 2 -> stderr
-                        Some(Arc::new(Stdout)),
-                    ],
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                    dlcheck_option: false,
-                })
+                UPSafeCell::new_with_class(
+                    ProcessControlBlockInner::new(memory_set, parent, cwd, cred, fd_table),
+                    INNER_CLASS,
+                )
             },
         });
         
@@ -138,40 +280,52 @@ impl ProcessControlBlock {
         let task_inner = task.inner_exclusive_access();
         let trap_cx = task_inner.get_trap_cx();
         let ustack_top = task_inner.res.as_ref().unwrap().ustack_top();
-        //let kstack_top = task.kstack.get_top();
+        drop(task_inner);
+        let (user_sp, argv_base) = init_user_stack(token, ustack_top, &args, &envs, auxv, cred);
 
         debug!("entry_cx::{:?}",entry_point);
-        drop(task_inner);
         *trap_cx = TrapContext::app_init_context(
             entry_point,
-            ustack_top,
+            user_sp,
            
         );
+        trap_cx.x[10] = args.len();
+        trap_cx.x[11] = argv_base;
        
         // add main thread to the process
         let mut process_inner = process.inner_exclusive_access();
         process_inner.tasks.push(Some(Arc::clone(&task)));
         drop(process_inner);
         insert_into_pid2process(process.getpid(), Arc::clone(&process));
-        // add main thread to scheduler
-        trace!("shed::spawn_user_thread");
-        PROCESSOR.exclusive_access().current = Some(task.clone());
-        
-        shed::spawn_user_thread(task);
-        process
+        Ok(process)
     }
 
     /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    /// Leaves the process untouched and fails with -ENOEXEC if the elf is malformed,
+    /// or -E2BIG if the arguments do not fit on the user stack.
+    pub fn exec(
+        self: &Arc<Self>,
+        elf_data: &[u8],
+        args: Vec<String>,
+        envs: Vec<String>,
+    ) -> Result<(), isize> {
         trace!("kernel: exec");
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
         trace!("kernel: exec .. MemorySet::from_elf");
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        // the interpreter is opened with our credentials
+        let cred = self.inner_exclusive_access().cred;
+        let image = MemorySet::from_elf(elf_data, cred);
+        let (memory_set, ustack_base, entry_point, auxv) = image.ok_or(-ENOEXEC)?;
+        if user_stack_size(&args, &envs, &auxv) > USER_STACK_SIZE {
+            return Err(-E2BIG);
+        }
         let new_token = memory_set.token();
         // substitute memory_set
         trace!("kernel: exec .. substitute memory_set");
         self.inner_exclusive_access().memory_set = memory_set;
+        // POSIX timers do not survive exec, interval timers do
+        self.inner_exclusive_access().timers.clear_posix();
         // then we alloc user resource for main thread again
         // since memory_set has been changed
         trace!("kernel: exec .. alloc user resource for main thread again");
@@ -182,40 +336,21 @@ impl ProcessControlBlock {
         task_inner.trap_cx_ppn = task_inner.res.as_mut().unwrap().trap_cx_ppn();
         // push arguments on user stack
         trace!("kernel: exec .. push arguments on user stack");
-        let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
-        user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
-        let argv_base = user_sp;
-        let mut argv: Vec<_> = (0..=args.len())
-            .map(|arg| {
-                translated_refmut(
-                    new_token,
-                    (argv_base + arg * core::mem::size_of::<usize>()) as *mut usize,
-                )
-            })
-            .collect();
-        *argv[args.len()] = 0;
-        for i in 0..args.len() {
-            user_sp -= args[i].len() + 1;
-            *argv[i] = user_sp;
-            let mut p = user_sp;
-            for c in args[i].as_bytes() {
-                *translated_refmut(new_token, p as *mut u8) = *c;
-                p += 1;
-            }
-            *translated_refmut(new_token, p as *mut u8) = 0;
-        }
-        // make the user_sp aligned to 8B for k210 platform
-        user_sp -= user_sp % core::mem::size_of::<usize>();
+        let ustack_top = task_inner.res.as_mut().unwrap().ustack_top();
+        let cred = self.inner_exclusive_access().cred;
+        let (user_sp, argv_base) =
+            init_user_stack(new_token, ustack_top, &args, &envs, auxv, cred);
         // initialize trap_cx
         trace!("kernel: exec .. initialize trap_cx");
-        let  trap_cx = TrapContext::app_init_context(
+        let mut trap_cx = TrapContext::app_init_context(
             entry_point,
             user_sp,
             
         );
-        /* trap_cx.x[10] = args.len();
-        trap_cx.x[11] = argv_base; */
+        trap_cx.x[10] = args.len();
+        trap_cx.x[11] = argv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        Ok(())
     }
 
     /// Only support processes with a single thread.
@@ -240,21 +375,16 @@ impl ProcessControlBlock {
         let child = Arc::new(Self {
             pid,
             inner: unsafe {
-                UPSafeCell::new(ProcessControlBlockInner {
-                    is_zombie: false,
-                    memory_set,
-                    parent: Some(Arc::downgrade(self)),
-                    children: Vec::new(),
-                    exit_code: 0,
-                    fd_table: new_fd_table,
-                    signals: SignalFlags::empty(),
-                    tasks: Vec::new(),
-                    task_res_allocator: RecycleAllocator::new(),
-                    mutex_list: Vec::new(),
-                    semaphore_list: Vec::new(),
-                    condvar_list: Vec::new(),
-                    dlcheck_option: false,
-                })
+                UPSafeCell::new_with_class(
+                    ProcessControlBlockInner::new(
+                        memory_set,
+                        Some(Arc::downgrade(self)),
+                        parent.cwd.clone(),
+                        parent.cred,
+                        new_fd_table,
+                    ),
+                    INNER_CLASS,
+                )
             },
         });
         // add child
@@ -292,3 +422,94 @@ impl ProcessControlBlock {
         self.pid.0
     }
 }
+
+/// auxv entries `init_user_stack` adds to those of the elf, AT_NULL included
+const STACK_AUXV: usize = 10;
+
+/// Upper bound of the bytes `init_user_stack` takes, to be checked before exec
+/// gives up the old image
+fn user_stack_size(args: &[String], envs: &[String], auxv: &[AuxHeader]) -> usize {
+    let strings: usize = args.iter().chain(envs).map(|s| s.len() + 1).sum();
+    let words = 1 + (args.len() + 1) + (envs.len() + 1) + (auxv.len() + STACK_AUXV) * 2;
+    // AT_RANDOM bytes, then up to 15 bytes lost to alignment
+    16 + strings + words * core::mem::size_of::<usize>() + 15
+}
+
+/// Build the SysV initial stack below `ustack_top`:
+/// argc, argv[], NULL, envp[], NULL, auxv[] and the strings they point to.
+/// Returns the new user sp and the address of argv.
+fn init_user_stack(
+    token: usize,
+    ustack_top: usize,
+    args: &[String],
+    envs: &[String],
+    mut auxv: Vec<AuxHeader>,
+    cred: Cred,
+) -> (usize, usize) {
+    debug_assert!(user_stack_size(args, envs, &auxv) <= USER_STACK_SIZE);
+    let elf_auxv = auxv.len();
+    let mut user_sp = ustack_top;
+    // 16 random bytes for AT_RANDOM, used by libc as stack canary seed
+    user_sp -= 16;
+    let random_base = user_sp;
+    let mut seed = get_time() as u64 ^ 0x9e37_79b9_7f4a_7c15;
+    for i in 0..16 {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        *translated_refmut(token, (random_base + i) as *mut u8) = seed as u8;
+    }
+    // strings of envp and argv
+    let envp: Vec<usize> = envs
+        .iter()
+        .map(|env| push_user_str(token, &mut user_sp, env))
+        .collect();
+    let argv: Vec<usize> = args
+        .iter()
+        .map(|arg| push_user_str(token, &mut user_sp, arg))
+        .collect();
+    auxv.push(AuxHeader::new(AT_HWCAP, HWCAP_RV64IMAFDC));
+    auxv.push(AuxHeader::new(AT_CLKTCK, 100));
+    auxv.push(AuxHeader::new(AT_UID, cred.uid as usize));
+    auxv.push(AuxHeader::new(AT_EUID, cred.uid as usize));
+    auxv.push(AuxHeader::new(AT_GID, cred.gid as usize));
+    auxv.push(AuxHeader::new(AT_EGID, cred.gid as usize));
+    auxv.push(AuxHeader::new(AT_SECURE, 0));
+    auxv.push(AuxHeader::new(AT_RANDOM, random_base));
+    if let Some(&execfn) = argv.first() {
+        auxv.push(AuxHeader::new(AT_EXECFN, execfn));
+    }
+    auxv.push(AuxHeader::new(AT_NULL, 0));
+    debug_assert!(auxv.len() <= elf_auxv + STACK_AUXV);
+    // argc, argv[], NULL, envp[], NULL, auxv[], with sp 16B aligned as SysV requires
+    let words = 1 + (argv.len() + 1) + (envp.len() + 1) + auxv.len() * 2;
+    user_sp = (user_sp - words * core::mem::size_of::<usize>()) & !0xf;
+    let argv_base = user_sp + core::mem::size_of::<usize>();
+    let mut p = user_sp;
+    let mut push_word = |value: usize| {
+        *translated_refmut(token, p as *mut usize) = value;
+        p += core::mem::size_of::<usize>();
+    };
+    push_word(argv.len());
+    argv.iter().for_each(|arg| push_word(*arg));
+    push_word(0);
+    envp.iter().for_each(|env| push_word(*env));
+    push_word(0);
+    for aux in auxv.iter() {
+        push_word(aux.aux_type);
+        push_word(aux.value);
+    }
+    (user_sp, argv_base)
+}
+
+/// Copy a string with its terminating 0 below `user_sp` and return its user address
+fn push_user_str(token: usize, user_sp: &mut usize, s: &str) -> usize {
+    *user_sp -= s.len() + 1;
+    let mut p = *user_sp;
+    for c in s.as_bytes() {
+        *translated_refmut(token, p as *mut u8) = *c;
+        p += 1;
+    }
+    *translated_refmut(token, p as *mut u8) = 0;
+    *user_sp
+}
@@ -13,12 +13,31 @@ bitflags! {
         const SIGABRT   = 1 << 6;
         /// Floating point exception
         const SIGFPE    = 1 << 8;
+        /// Kill, cannot be caught
+        const SIGKILL   = 1 << 9;
+        /// User defined signal 1
+        const SIGUSR1   = 1 << 10;
         /// Segmentation fault
         const SIGSEGV   = 1 << 11;
+        /// User defined signal 2
+        const SIGUSR2   = 1 << 12;
+        /// Timer expired, ITIMER_REAL
+        const SIGALRM   = 1 << 14;
+        /// Virtual timer expired, ITIMER_VIRTUAL
+        const SIGVTALRM = 1 << 26;
+        /// Profiling timer expired, ITIMER_PROF
+        const SIGPROF   = 1 << 27;
     }
 }
 
 impl SignalFlags {
+    /// the flag of signal number `signum`
+    pub fn from_signum(signum: usize) -> Option<Self> {
+        if signum == 0 || signum >= 32 {
+            return None;
+        }
+        Self::from_bits(1 << signum)
+    }
     /// convert signal flag to integer & string
     pub fn check_error(&self) -> Option<(i32, &'static str)> {
         if self.contains(Self::SIGINT) {
@@ -29,8 +48,16 @@ impl SignalFlags {
             Some((-6, "Aborted, SIGABRT=6"))
         } else if self.contains(Self::SIGFPE) {
             Some((-8, "Erroneous Arithmetic Operation, SIGFPE=8"))
+        } else if self.contains(Self::SIGKILL) {
+            Some((-9, "Killed, SIGKILL=9"))
         } else if self.contains(Self::SIGSEGV) {
             Some((-11, "Segmentation Fault, SIGSEGV=11"))
+        } else if self.contains(Self::SIGALRM) {
+            Some((-14, "Alarm clock, SIGALRM=14"))
+        } else if self.contains(Self::SIGVTALRM) {
+            Some((-26, "Virtual timer expired, SIGVTALRM=26"))
+        } else if self.contains(Self::SIGPROF) {
+            Some((-27, "Profiling timer expired, SIGPROF=27"))
         } else {
             // warn!("[kernel] signalflags check_error  {:?}", self);
             None
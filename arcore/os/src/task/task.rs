@@ -1,15 +1,21 @@
 //! Types related to task management & Functions for completely changing TCB
 
 use super::id::TaskUserRes;
-use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext};
+use super::{kstack_alloc, KernelStack, ProcessControlBlock, TaskContext, ThreadTimeInfo};
+use crate::config::MAX_SYSCALL_NUM;
 use crate::trap::TrapContext;
-use crate::{mm::PhysPageNum, sync::UPSafeCell};
+use crate::{
+    mm::PhysPageNum,
+    sync::{UPRefMut, UPSafeCell},
+};
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
-use core::cell::RefMut;
 
 use core::task::Waker;
 
+/// lock class of every task inner cell
+const INNER_CLASS: &str = "TaskControlBlock::inner";
+
 /// Task control block structure
 pub struct TaskControlBlock {
     /// immutable
@@ -22,7 +28,8 @@ pub struct TaskControlBlock {
 
 impl TaskControlBlock {
     /// Get the mutable reference of the inner TCB
-    pub fn inner_exclusive_access(&self) -> RefMut<'_, TaskControlBlockInner> {
+    #[track_caller]
+    pub fn inner_exclusive_access(&self) -> UPRefMut<'_, TaskControlBlockInner> {
         self.inner.exclusive_access()
     }
     /// Get the address of app's page table
@@ -38,6 +45,13 @@ impl TaskControlBlock {
             self.inner.exclusive_access().waker = Some(waker);
       
     }
+    /// Mark the task exited with `exit_code`, its task loop ends once the
+    /// current trap is handled
+    pub fn exit(&self, exit_code: i32) {
+        let mut inner = self.inner.exclusive_access();
+        inner.zombie = true;
+        inner.exit_code = Some(exit_code);
+    }
     ///is zombie
     pub fn is_zombie(&self) -> bool {
         let inner = self.inner.exclusive_access();
@@ -62,13 +76,25 @@ pub struct TaskControlBlockInner {
     pub mutex_need: usize, // mutex id need (usize::MAX represents donot need any mutex)
     /// The resources need of semaphore
     pub sem_need: usize,   // semaphore id need (usize::MAX represents donot need any semaphore)
+    /// The resources need of rwlock
+    pub rwlock_need: usize, // rwlock id need (usize::MAX represents donot need any rwlock)
+    /// Whether the rwlock need is for writing
+    pub rwlock_need_write: bool,
+    /// The condvar waited on
+    pub condvar_need: usize, // condvar id waited on (usize::MAX represents not waiting)
     /// The resources allocated of mutex
     pub mutex_allocation: Vec<usize>,         // elem: mutex id allocated
     /// The resources allocated of semaphore
     pub sem_allocation: Vec<(usize, usize)>,  // elem: (sem id allocated, count allocated)
+    /// The resources allocated of rwlock
+    pub rwlock_allocation: Vec<(usize, bool)>, // elem: (rwlock id allocated, held for writing)
     ///waker
     pub waker: Option<Waker>,
     pub zombie:bool,
+    /// The numbers of syscall called by the task, indexed by syscall id
+    pub syscall_times: [u32; MAX_SYSCALL_NUM],
+    /// user and kernel time of the task
+    pub time_info: ThreadTimeInfo,
 }
 
 impl TaskControlBlockInner {
@@ -97,19 +123,28 @@ impl TaskControlBlock {
             process: Arc::downgrade(&process),
             kstack,
             inner: unsafe {
-                UPSafeCell::new(TaskControlBlockInner {
-                    zombie: false,
-                    res: Some(res),
-                    trap_cx_ppn,
-                    task_cx: TaskContext::goto_trap_return(kstack_top),
-                    task_status: TaskStatus::Ready,
-                    exit_code: None,
-                    mutex_need: usize::MAX,
-                    sem_need: usize::MAX,
-                    mutex_allocation: Vec::new(),
-                    sem_allocation: Vec::new(),
-                    waker: None,
-                })
+                UPSafeCell::new_with_class(
+                    TaskControlBlockInner {
+                        zombie: false,
+                        res: Some(res),
+                        trap_cx_ppn,
+                        task_cx: TaskContext::goto_trap_return(kstack_top),
+                        task_status: TaskStatus::Ready,
+                        exit_code: None,
+                        mutex_need: usize::MAX,
+                        sem_need: usize::MAX,
+                        rwlock_need: usize::MAX,
+                        rwlock_need_write: false,
+                        condvar_need: usize::MAX,
+                        mutex_allocation: Vec::new(),
+                        sem_allocation: Vec::new(),
+                        rwlock_allocation: Vec::new(),
+                        waker: None,
+                        syscall_times: [0; MAX_SYSCALL_NUM],
+                        time_info: ThreadTimeInfo::new(),
+                    },
+                    INNER_CLASS,
+                )
             },
         }
     }
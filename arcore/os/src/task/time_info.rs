@@ -0,0 +1,56 @@
+//! Runtime accounting of a thread
+
+use crate::timer::get_time_us;
+
+/// Times of a thread, in microseconds
+#[derive(Clone, Copy, Default)]
+pub struct ThreadTimeInfo {
+    /// when the thread was first scheduled, 0 if it never ran
+    pub first_time: usize,
+    /// total time spent in user mode
+    pub utime: usize,
+    /// total time spent in kernel mode
+    pub stime: usize,
+    /// last time the thread crossed the user/kernel boundary or was polled
+    last_time: usize,
+}
+
+impl ThreadTimeInfo {
+    /// Create an empty record for a thread that has not run yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Start counting kernel time, called when the executor starts polling the thread
+    pub fn when_poll_in(&mut self) {
+        let now = get_time_us();
+        if self.first_time == 0 {
+            // never 0 once scheduled
+            self.first_time = now.max(1);
+        }
+        self.last_time = now;
+    }
+    /// Account kernel time, called when the executor is done polling the thread,
+    /// so the time it is parked or others run is not charged.
+    /// Returns the kernel time just accounted.
+    pub fn when_poll_out(&mut self) -> usize {
+        self.when_trap_ret()
+    }
+    /// Account kernel time, called right before returning to user mode.
+    /// Returns the kernel time just accounted.
+    pub fn when_trap_ret(&mut self) -> usize {
+        let now = get_time_us();
+        let elapsed = now - self.last_time;
+        self.stime += elapsed;
+        self.last_time = now;
+        elapsed
+    }
+    /// Account user time, called on trap entry.
+    /// Returns the user time just accounted.
+    pub fn when_trap_in(&mut self) -> usize {
+        let now = get_time_us();
+        let elapsed = now - self.last_time;
+        self.utime += elapsed;
+        self.last_time = now;
+        elapsed
+    }
+}
@@ -0,0 +1,284 @@
+//! Interval timers and POSIX timers of a process
+//!
+//! Wall clock timers (ITIMER_REAL and `timer_create` timers) sit on the timer
+//! wheel, ITIMER_VIRTUAL and ITIMER_PROF are charged with the cpu time
+//! accounted at trap entry and exit.
+
+use super::{add_timer, get_time_us, TimerHandle};
+use crate::task::{ProcessControlBlock, SignalFlags};
+use alloc::sync::{Arc, Weak};
+use alloc::task::Wake;
+use alloc::vec::Vec;
+use core::task::Waker;
+
+/// decrements in real time, delivers SIGALRM
+pub const ITIMER_REAL: usize = 0;
+/// decrements in user time, delivers SIGVTALRM
+pub const ITIMER_VIRTUAL: usize = 1;
+/// decrements in user and kernel time, delivers SIGPROF
+pub const ITIMER_PROF: usize = 2;
+
+/// Remaining time and reload interval of a timer, in us. A zero value is disarmed.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ITimerValue {
+    /// time until the next expiration
+    pub value: usize,
+    /// reload value after expiration, 0 for one-shot
+    pub interval: usize,
+}
+
+/// Which wall clock timer of a process
+#[derive(Clone, Copy, Debug)]
+enum TimerKey {
+    Real,
+    Posix(usize),
+}
+
+/// A timer counting wall clock time on the timer wheel
+pub struct RealTimer {
+    /// clock the timer was created on, for absolute settings
+    pub clock_id: usize,
+    /// absolute expiration in us since boot, 0 if disarmed
+    deadline: usize,
+    interval: usize,
+    /// signal to post, none for SIGEV_NONE
+    signal: Option<SignalFlags>,
+    /// expirations missed at the last delivery
+    overrun: usize,
+    /// bumped on every set, so a stale wakeup is ignored
+    generation: usize,
+    handle: Option<TimerHandle>,
+}
+
+impl RealTimer {
+    /// Create a disarmed timer
+    pub fn new(clock_id: usize, signal: Option<SignalFlags>) -> Self {
+        Self {
+            clock_id,
+            deadline: 0,
+            interval: 0,
+            signal,
+            overrun: 0,
+            generation: 0,
+            handle: None,
+        }
+    }
+    fn get(&self) -> ITimerValue {
+        ITimerValue {
+            value: match self.deadline {
+                0 => 0,
+                deadline => deadline.saturating_sub(get_time_us()).max(1),
+            },
+            interval: self.interval,
+        }
+    }
+}
+
+/// The timers owned by a process
+pub struct ProcessTimers {
+    real: RealTimer,
+    virt: ITimerValue,
+    prof: ITimerValue,
+    /// timers of `timer_create`, indexed by timer id
+    posix: Vec<Option<RealTimer>>,
+}
+
+impl ProcessTimers {
+    /// A set of disarmed timers
+    pub fn new() -> Self {
+        Self {
+            // clock 0 is CLOCK_REALTIME
+            real: RealTimer::new(0, Some(SignalFlags::SIGALRM)),
+            virt: ITimerValue::default(),
+            prof: ITimerValue::default(),
+            posix: Vec::new(),
+        }
+    }
+    /// Register a POSIX timer, returns its id
+    pub fn create_posix(&mut self, timer: RealTimer) -> usize {
+        if let Some(id) = self.posix.iter().position(|timer| timer.is_none()) {
+            self.posix[id] = Some(timer);
+            id
+        } else {
+            self.posix.push(Some(timer));
+            self.posix.len() - 1
+        }
+    }
+    /// Delete a POSIX timer, disarming it
+    pub fn delete_posix(&mut self, id: usize) -> bool {
+        self.posix.get_mut(id).and_then(|timer| timer.take()).is_some()
+    }
+    /// Delete all POSIX timers, as exec does
+    pub fn clear_posix(&mut self) {
+        self.posix.clear();
+    }
+    /// Overrun count of a POSIX timer
+    pub fn posix_overrun(&self, id: usize) -> Option<usize> {
+        Some(self.posix.get(id)?.as_ref()?.overrun)
+    }
+    /// Current value of a POSIX timer
+    pub fn get_posix(&self, id: usize) -> Option<ITimerValue> {
+        Some(self.posix.get(id)?.as_ref()?.get())
+    }
+    /// Clock of a POSIX timer
+    pub fn posix_clock(&self, id: usize) -> Option<usize> {
+        Some(self.posix.get(id)?.as_ref()?.clock_id)
+    }
+    /// Current value of an interval timer
+    pub fn get_itimer(&self, which: usize) -> Option<ITimerValue> {
+        match which {
+            ITIMER_REAL => Some(self.real.get()),
+            ITIMER_VIRTUAL => Some(self.virt),
+            ITIMER_PROF => Some(self.prof),
+            _ => None,
+        }
+    }
+    fn real_timer(&mut self, key: TimerKey) -> Option<&mut RealTimer> {
+        match key {
+            TimerKey::Real => Some(&mut self.real),
+            TimerKey::Posix(id) => self.posix.get_mut(id)?.as_mut(),
+        }
+    }
+}
+
+impl Default for ProcessTimers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Set an interval timer of `process`, returns the old value, or None if
+/// `which` is unknown or the expiry is out of range
+pub fn set_itimer(
+    process: &Arc<ProcessControlBlock>,
+    which: usize,
+    new: ITimerValue,
+) -> Option<ITimerValue> {
+    match which {
+        ITIMER_REAL => set_real_timer(process, TimerKey::Real, new),
+        ITIMER_VIRTUAL => {
+            let mut inner = process.inner_exclusive_access();
+            Some(core::mem::replace(&mut inner.timers.virt, new))
+        }
+        ITIMER_PROF => {
+            let mut inner = process.inner_exclusive_access();
+            Some(core::mem::replace(&mut inner.timers.prof, new))
+        }
+        _ => None,
+    }
+}
+
+/// Set a POSIX timer of `process`, returns the old value, or None if `id`
+/// is not a timer or the expiry is out of range
+pub fn set_posix_timer(
+    process: &Arc<ProcessControlBlock>,
+    id: usize,
+    new: ITimerValue,
+) -> Option<ITimerValue> {
+    set_real_timer(process, TimerKey::Posix(id), new)
+}
+
+fn set_real_timer(
+    process: &Arc<ProcessControlBlock>,
+    key: TimerKey,
+    new: ITimerValue,
+) -> Option<ITimerValue> {
+    let deadline = match new.value {
+        0 => 0,
+        value => get_time_us().checked_add(value)?,
+    };
+    let mut inner = process.inner_exclusive_access();
+    let timer = inner.timers.real_timer(key)?;
+    let old = timer.get();
+    timer.generation += 1;
+    timer.overrun = 0;
+    timer.interval = new.interval;
+    timer.deadline = deadline;
+    if deadline == 0 {
+        timer.handle = None;
+    } else {
+        timer.handle = Some(arm(process, key, timer));
+    }
+    Some(old)
+}
+
+/// Put `timer` on the timer wheel
+fn arm(process: &Arc<ProcessControlBlock>, key: TimerKey, timer: &RealTimer) -> TimerHandle {
+    let waker = Waker::from(Arc::new(TimerWake {
+        process: Arc::downgrade(process),
+        key,
+        generation: timer.generation,
+    }));
+    add_timer(timer.deadline.div_ceil(1000), waker)
+}
+
+/// Fires a wall clock timer when woken by the timer wheel
+struct TimerWake {
+    process: Weak<ProcessControlBlock>,
+    key: TimerKey,
+    generation: usize,
+}
+
+impl Wake for TimerWake {
+    fn wake(self: Arc<Self>) {
+        let Some(process) = self.process.upgrade() else {
+            return;
+        };
+        let mut inner = process.inner_exclusive_access();
+        let Some(timer) = inner.timers.real_timer(self.key) else {
+            return;
+        };
+        if timer.generation != self.generation || timer.deadline == 0 {
+            return;
+        }
+        let signal = timer.signal;
+        let now = get_time_us();
+        if timer.interval == 0 {
+            timer.deadline = 0;
+            timer.handle = None;
+        } else {
+            // count the periods that passed while the wheel was not checked
+            let missed = now.saturating_sub(timer.deadline) / timer.interval;
+            timer.overrun = missed;
+            let next = (missed + 1).saturating_mul(timer.interval);
+            timer.deadline = timer.deadline.saturating_add(next);
+            timer.handle = Some(arm(&process, self.key, timer));
+        }
+        drop(inner);
+        if let Some(signal) = signal {
+            post_signal(&process, signal);
+        }
+    }
+}
+
+/// Charge cpu time to the ITIMER_VIRTUAL and ITIMER_PROF timers of `process`
+pub fn charge_cpu_time(process: &Arc<ProcessControlBlock>, user_us: usize, kernel_us: usize) {
+    let mut inner = process.inner_exclusive_access();
+    let virt_fired = charge(&mut inner.timers.virt, user_us);
+    let prof_fired = charge(&mut inner.timers.prof, user_us + kernel_us);
+    drop(inner);
+    if virt_fired {
+        post_signal(process, SignalFlags::SIGVTALRM);
+    }
+    if prof_fired {
+        post_signal(process, SignalFlags::SIGPROF);
+    }
+}
+
+/// Count down a cpu time timer, returns true if it expired
+fn charge(timer: &mut ITimerValue, us: usize) -> bool {
+    if timer.value == 0 {
+        return false;
+    }
+    if timer.value > us {
+        timer.value -= us;
+        return false;
+    }
+    timer.value = timer.interval;
+    true
+}
+
+/// Post `signal` to `process`
+fn post_signal(process: &Arc<ProcessControlBlock>, signal: SignalFlags) {
+    process.inner_exclusive_access().signals |= signal;
+}
@@ -0,0 +1,151 @@
+//! RISC-V timer-related functionality
+
+pub mod itimer;
+mod wheel;
+
+use crate::config::CLOCK_FREQ;
+use crate::drivers::RTC;
+use crate::executor::waker::{Select2Futures, SelectOutput};
+use crate::sbi::set_timer;
+use crate::sync::UPSafeCell;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use lazy_static::*;
+use riscv::register::time;
+use wheel::TimerWheel;
+/// The number of ticks per second
+const TICKS_PER_SEC: usize = 100;
+/// The number of milliseconds per second
+const MSEC_PER_SEC: usize = 1000;
+/// The number of microseconds per second
+#[allow(dead_code)]
+const MICRO_PER_SEC: usize = 1_000_000;
+/// The number of nanoseconds per second
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
+
+/// Get the current time in ticks
+pub fn get_time() -> usize {
+    time::read()
+}
+
+/// Get the current time in milliseconds
+pub fn get_time_ms() -> usize {
+    time::read() * MSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// get current time in microseconds
+pub fn get_time_us() -> usize {
+    time::read() * MICRO_PER_SEC / CLOCK_FREQ
+}
+
+/// get current time in nanoseconds
+pub fn get_time_ns() -> usize {
+    let ticks = time::read();
+    ticks / CLOCK_FREQ * NSEC_PER_SEC + ticks % CLOCK_FREQ * NSEC_PER_SEC / CLOCK_FREQ
+}
+
+/// get current wall clock time in nanoseconds since the unix epoch
+pub fn get_realtime_ns() -> usize {
+    get_time_ns() + *REALTIME_OFFSET.exclusive_access()
+}
+
+/// Set the wall clock, also writing it back to the RTC
+pub fn set_realtime_ns(ns: usize) {
+    *REALTIME_OFFSET.exclusive_access() = ns.saturating_sub(get_time_ns());
+    RTC.set_time_ns(ns as u64);
+}
+
+/// Seed the wall clock from the RTC
+pub fn init_realtime() {
+    let ns = RTC.read_time_ns() as usize;
+    *REALTIME_OFFSET.exclusive_access() = ns.saturating_sub(get_time_ns());
+    info!("realtime: {} s since epoch", ns / NSEC_PER_SEC);
+}
+
+/// Set the next timer interrupt
+pub fn set_next_trigger() {
+    set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
+}
+
+lazy_static! {
+    /// TIMERS: global timer wheel, ticking in milliseconds
+    static ref TIMERS: UPSafeCell<TimerWheel> =
+        unsafe { UPSafeCell::new(TimerWheel::new(get_time_ms())) };
+    /// wall clock time at boot in nanoseconds, added to the monotonic time
+    static ref REALTIME_OFFSET: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
+}
+
+/// A pending timer, cancelled on drop
+pub struct TimerHandle(u64);
+
+impl TimerHandle {
+    /// Cancel the timer, returns false if it already fired
+    pub fn cancel(self) -> bool {
+        let id = self.0;
+        core::mem::forget(self);
+        TIMERS.exclusive_access().cancel(id)
+    }
+}
+
+impl Drop for TimerHandle {
+    fn drop(&mut self) {
+        TIMERS.exclusive_access().cancel(self.0);
+    }
+}
+
+/// Wake `waker` once the time reaches `expire_ms`
+pub fn add_timer(expire_ms: usize, waker: Waker) -> TimerHandle {
+    TimerHandle(TIMERS.exclusive_access().add(expire_ms, waker))
+}
+
+/// Wake the tasks whose timers have expired
+pub fn check_timer() {
+    let expired = TIMERS.exclusive_access().advance(get_time_ms());
+    // wake after releasing the wheel, a woken task may add timers
+    for waker in expired {
+        log::trace!("[check_timer] wake a timeout timer at {} ms", get_time_ms());
+        waker.wake();
+    }
+}
+
+/// Future that completes once the time reaches `deadline_ms`
+pub struct Sleep {
+    deadline_ms: usize,
+    timer: Option<TimerHandle>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if get_time_ms() >= self.deadline_ms {
+            self.timer = None;
+            return Poll::Ready(());
+        }
+        // re-arm with the latest waker, the old timer is cancelled on drop
+        self.timer = Some(add_timer(self.deadline_ms, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+/// Sleep until `deadline` in milliseconds since boot
+pub async fn sleep_until(deadline: usize) {
+    Sleep {
+        deadline_ms: deadline,
+        timer: None,
+    }
+    .await
+}
+
+/// Sleep for `ms` milliseconds
+pub async fn sleep(ms: usize) {
+    sleep_until(get_time_ms().saturating_add(ms)).await
+}
+
+/// Run `fut` until `deadline` in milliseconds since boot, `None` if it timed out
+pub async fn timeout<F: Future>(deadline: usize, fut: F) -> Option<F::Output> {
+    match Select2Futures::new(fut, sleep_until(deadline)).await {
+        SelectOutput::Output1(output) => Some(output),
+        SelectOutput::Output2(()) => None,
+    }
+}
@@ -0,0 +1,130 @@
+//! Hierarchical timer wheel
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::task::Waker;
+
+/// bits of slot index of one level
+const WHEEL_BITS: usize = 6;
+/// slots per level
+const WHEEL_SIZE: usize = 1 << WHEEL_BITS;
+/// number of levels, the wheel covers 2^24 ticks ahead
+const WHEEL_LEVELS: usize = 4;
+
+/// where a pending timer lives
+#[derive(Clone, Copy, PartialEq)]
+enum Location {
+    Slot(usize, usize),
+    Overflow,
+}
+
+struct TimerEntry {
+    id: u64,
+    expire: usize,
+    waker: Waker,
+}
+
+/// Timers are kept in `WHEEL_LEVELS` levels of `WHEEL_SIZE` slots, level `l`
+/// slot `s` holds timers expiring in the `s`th block of `WHEEL_SIZE^l` ticks.
+/// Higher levels are cascaded down as time reaches them.
+pub struct TimerWheel {
+    /// all ticks up to `now` have been processed
+    now: usize,
+    slots: Vec<Vec<TimerEntry>>,
+    /// timers too far away for the wheel
+    overflow: Vec<TimerEntry>,
+    /// pending timer id -> location, for cancellation
+    index: BTreeMap<u64, Location>,
+    next_id: u64,
+}
+
+impl TimerWheel {
+    /// Create a wheel starting at tick `now`
+    pub fn new(now: usize) -> Self {
+        let mut slots = Vec::with_capacity(WHEEL_SIZE * WHEEL_LEVELS);
+        slots.resize_with(WHEEL_SIZE * WHEEL_LEVELS, Vec::new);
+        Self {
+            now,
+            slots,
+            overflow: Vec::new(),
+            index: BTreeMap::new(),
+            next_id: 0,
+        }
+    }
+    /// Register `waker` to be woken at tick `expire`, returns the timer id.
+    /// A timer already due fires on the next tick, so wakers never run
+    /// while the wheel is borrowed.
+    pub fn add(&mut self, expire: usize, waker: Waker) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let expire = expire.max(self.now + 1);
+        self.insert(TimerEntry { id, expire, waker });
+        id
+    }
+    /// Remove a pending timer, returns false if it already fired or was cancelled
+    pub fn cancel(&mut self, id: u64) -> bool {
+        match self.index.remove(&id) {
+            Some(Location::Slot(level, slot)) => {
+                self.slots[level * WHEEL_SIZE + slot].retain(|entry| entry.id != id);
+                true
+            }
+            Some(Location::Overflow) => {
+                self.overflow.retain(|entry| entry.id != id);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Move time forward to tick `now`, returning the wakers of expired timers
+    pub fn advance(&mut self, now: usize) -> Vec<Waker> {
+        let mut expired = Vec::new();
+        while self.now < now {
+            if self.index.is_empty() {
+                self.now = now;
+                break;
+            }
+            self.now += 1;
+            let tick = self.now;
+            if tick % (1 << (WHEEL_BITS * WHEEL_LEVELS)) == 0 {
+                for entry in core::mem::take(&mut self.overflow) {
+                    self.insert(entry);
+                }
+            }
+            // cascade from the top so entries can fall through several levels
+            for level in (1..WHEEL_LEVELS).rev() {
+                if tick % (1 << (WHEEL_BITS * level)) == 0 {
+                    let slot = (tick >> (WHEEL_BITS * level)) % WHEEL_SIZE;
+                    for entry in core::mem::take(&mut self.slots[level * WHEEL_SIZE + slot]) {
+                        self.insert(entry);
+                    }
+                }
+            }
+            for entry in core::mem::take(&mut self.slots[tick % WHEEL_SIZE]) {
+                self.index.remove(&entry.id);
+                expired.push(entry.waker);
+            }
+        }
+        expired
+    }
+    fn insert(&mut self, entry: TimerEntry) {
+        if entry.expire <= self.now {
+            // due while cascading, fires on the current tick
+            let location = Location::Slot(0, self.now % WHEEL_SIZE);
+            self.index.insert(entry.id, location);
+            self.slots[self.now % WHEEL_SIZE].push(entry);
+            return;
+        }
+        // the lowest level whose enclosing block also holds `now`
+        let location = (0..WHEEL_LEVELS)
+            .find(|&level| {
+                entry.expire >> (WHEEL_BITS * (level + 1)) == self.now >> (WHEEL_BITS * (level + 1))
+            })
+            .map(|level| Location::Slot(level, (entry.expire >> (WHEEL_BITS * level)) % WHEEL_SIZE))
+            .unwrap_or(Location::Overflow);
+        self.index.insert(entry.id, location);
+        match location {
+            Location::Slot(level, slot) => self.slots[level * WHEEL_SIZE + slot].push(entry),
+            Location::Overflow => self.overflow.push(entry),
+        }
+    }
+}
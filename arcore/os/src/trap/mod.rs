@@ -16,9 +16,10 @@ mod context;
 
 /* use crate::syscall::syscall; */
 use crate::task::{
-    check_signals_of_current, current_add_signal, current_trap_cx, /* current_trap_cx_user_va, */
+    check_signals_of_current, current_add_signal, current_process, current_task, current_trap_cx, /* current_trap_cx_user_va, */
     /* current_user_token, */suspend_current_and_run_next, SignalFlags,
 };
+use crate::timer::itimer::charge_cpu_time;
 use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{/* asm, */ global_asm};
 use core::panic;
@@ -80,6 +81,8 @@ pub async fn trap_handler() {
                     cx.x[11],
                     cx.x[12],
                     cx.x[13],
+                    cx.x[14],
+                    cx.x[15],
                    
                 ],
             ).await
@@ -148,9 +151,12 @@ pub  fn trap_return()  {
      // }
      //check_signal_for_current_task();
      
+     // switch to the address space of the process about to run
+     current_process().inner_exclusive_access().memory_set.activate();
      debug!("satp:{:x}",satp::read().bits());
      unsafe {
-        // (current_task().inner.get()).time_info.when_trap_ret();
+         let stime = current_task().unwrap().inner_exclusive_access().time_info.when_trap_ret();
+         charge_cpu_time(&current_process(), 0, stime);
  
          // Restore the float regs if needed.
          // Two cases that may need to restore regs:
@@ -167,7 +173,8 @@ pub  fn trap_return()  {
          // Next trap will arrive here
          // current_trap_cx().user_fx.save();
  
-         //(*current_task().inner.get()).time_info.when_trap_in();
+         let utime = current_task().unwrap().inner_exclusive_access().time_info.when_trap_in();
+         charge_cpu_time(&current_process(), utime, 0);
      }
 }
 